@@ -30,36 +30,77 @@ const uint64 = (property: string = 'uint64'): Object => {
   return BufferLayout.blob(8, property)
 }
 
+/**
+ * Layout for a billing cycle length, a unit tag followed by a 64bit count
+ */
+const cycleInterval = (property: string = 'cycleInterval'): Object => {
+  return BufferLayout.struct([BufferLayout.u8('unit'), uint64('count')], property)
+}
+
+// Units of `CycleInterval`, in the order the program packs them
+const CycleUnit = {
+  Seconds: 0,
+  Days: 1,
+  Weeks: 2,
+  CalendarMonths: 3,
+  Years: 4
+}
+
+// Instruction tags, the declaration order of `RecurringPaymentsInstruction` in instruction.rs
+const CREATE_SUBSCRIPTION_PLAN = 0
+const CREATE_SUBSCRIPTION = 1
+
+// Must match `CUSTOMER_REFERENCE_LEN` in constants.rs
+const CUSTOMER_REFERENCE_LEN = 32
+
 function createSubscriptionPlanInstruction(
-  subscriptionAccount: PublicKey,
-  tokenAddress: PublicKey,
+  subscriptionPlanAccount: PublicKey,
   owner: PublicKey,
+  authority: PublicKey,
+  nonce: number,
+  mint: PublicKey,
+  payoutAccount: PublicKey,
+  cycleUnit: number,
+  cycleCount: number | Numberu64,
   maxAmount: number | Numberu64,
-  subscriptionTimeframe: number | Numberu64,
   recurringPaymentsProgramId: PublicKey
 ): TransactionInstruction {
   const dataLayout = BufferLayout.struct([
     BufferLayout.u8('instruction'),
-    uint64('subscription_timeframe'),
-    uint64('max_amount')
+    BufferLayout.u8('nonce'),
+    cycleInterval('subscription_timeframe'),
+    uint64('max_amount'),
+    uint64('setup_fee'),
+    uint64('max_cycles'),
+    uint64('end_timestamp'),
+    BufferLayout.u8('charge_upfront'),
+    BufferLayout.u8('billing_anchor_day')
   ])
 
   const data = Buffer.alloc(dataLayout.span)
   dataLayout.encode(
     {
-      instruction: 1, // CreateSubscriptionPlan instruction
+      instruction: CREATE_SUBSCRIPTION_PLAN,
+      nonce,
       // @ts-ignore
-      subscription_timeframe: new Numberu64(subscriptionTimeframe).toBuffer(),
+      subscription_timeframe: { unit: cycleUnit, count: new Numberu64(cycleCount).toBuffer() },
       // @ts-ignore
-      max_amount: new Numberu64(maxAmount).toBuffer()
+      max_amount: new Numberu64(maxAmount).toBuffer(),
+      setup_fee: new Numberu64(0).toBuffer(),
+      max_cycles: new Numberu64(0).toBuffer(),
+      end_timestamp: new Numberu64(0).toBuffer(),
+      charge_upfront: 0,
+      billing_anchor_day: 0
     },
     data
   )
 
   const keys = [
-    { pubkey: subscriptionAccount, isSigner: false, isWritable: true },
-    { pubkey: tokenAddress, isSigner: false, isWritable: false },
-    { pubkey: owner, isSigner: false, isWritable: false }
+    { pubkey: subscriptionPlanAccount, isSigner: false, isWritable: true },
+    { pubkey: owner, isSigner: true, isWritable: false },
+    { pubkey: authority, isSigner: false, isWritable: false },
+    { pubkey: mint, isSigner: false, isWritable: false },
+    { pubkey: payoutAccount, isSigner: false, isWritable: false }
   ]
 
   return new TransactionInstruction({
@@ -71,36 +112,47 @@ function createSubscriptionPlanInstruction(
 
 function createSubscriptionInstruction(
   subscriptionAccount: PublicKey,
-  tokenAddress: PublicKey,
-  customer: PublicKey,
-  payoutAddress: PublicKey,
+  subscriptionPlanAccount: PublicKey,
+  subscriber: PublicKey,
+  tokenAccount: PublicKey,
+  mint: PublicKey,
+  cycleUnit: number,
+  cycleCount: number | Numberu64,
   maxAmount: number | Numberu64,
-  subscriptionTimeframe: number | Numberu64,
-  recurringPaymentsProgramId: PublicKey
+  recurringPaymentsProgramId: PublicKey,
+  // Merchant's reference to the customer, logged by every claim. All zeros for none
+  customerReference: Buffer = Buffer.alloc(CUSTOMER_REFERENCE_LEN)
 ): TransactionInstruction {
   const dataLayout = BufferLayout.struct([
     BufferLayout.u8('instruction'),
+    cycleInterval('subscription_timeframe'),
     uint64('max_amount'),
-    uint64('subscription_timeframe')
+    uint64('prepaid_cycles'),
+    BufferLayout.blob(CUSTOMER_REFERENCE_LEN, 'customer_reference')
   ])
 
   const data = Buffer.alloc(dataLayout.span)
   dataLayout.encode(
     {
-      instruction: 0, // CreateSubscription instruction
+      instruction: CREATE_SUBSCRIPTION,
       // @ts-ignore
-      subscription_timeframe: new Numberu64(subscriptionTimeframe).toBuffer(),
+      subscription_timeframe: { unit: cycleUnit, count: new Numberu64(cycleCount).toBuffer() },
       // @ts-ignore
-      max_amount: new Numberu64(maxAmount).toBuffer()
+      max_amount: new Numberu64(maxAmount).toBuffer(),
+      // Delegate mode, claims pull from the subscriber's token account
+      prepaid_cycles: new Numberu64(0).toBuffer(),
+      customer_reference: customerReference
     },
     data
   )
 
   const keys = [
     { pubkey: subscriptionAccount, isSigner: false, isWritable: true },
-    { pubkey: tokenAddress, isSigner: false, isWritable: false },
-    { pubkey: customer, isSigner: false, isWritable: false },
-    { pubkey: payoutAddress, isSigner: false, isWritable: false },
+    { pubkey: subscriptionPlanAccount, isSigner: false, isWritable: false },
+    { pubkey: subscriber, isSigner: true, isWritable: false },
+    { pubkey: tokenAccount, isSigner: false, isWritable: true },
+    { pubkey: mint, isSigner: false, isWritable: false },
+    { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false }
   ]
 
@@ -111,8 +163,30 @@ function createSubscriptionInstruction(
   })
 }
 
-const SUBSCRIPTION_PLAN_SIZE = 81
-const SUBSCRIPTION_SIZE = 162
+/**
+ * Finds the plan authority, the program address derived from the plan account and a nonce
+ */
+async function findAuthority(
+  subscriptionPlanAccount: PublicKey,
+  recurringPaymentsProgramId: PublicKey
+): Promise<[PublicKey, number]> {
+  for (let nonce = 255; nonce >= 0; nonce--) {
+    try {
+      const authority = await PublicKey.createProgramAddress(
+        [subscriptionPlanAccount.toBuffer(), Buffer.from([nonce])],
+        recurringPaymentsProgramId
+      )
+      return [authority, nonce]
+    } catch (err) {
+      // On the curve, try the next nonce
+    }
+  }
+  throw new Error('Unable to find the plan authority')
+}
+
+// Account sizes, these must match `SUBSCRIPTION_PLAN_SIZE` and `SUBSCRIPTION_SIZE` in constants.rs
+const SUBSCRIPTION_PLAN_SIZE = 1419
+const SUBSCRIPTION_SIZE = 327
 
 const main = async () => {
   const _ourAccount = await getOurAccount()
@@ -135,10 +209,9 @@ const main = async () => {
   // Mint token account for test
   await token.mintTo(tokenAddress, _ourAccount, [], 1000 * 10 ** 9)
 
-  const customer = new Account()
-  const payoutAddress = new Account()
+  const [authority, nonce] = await findAuthority(subscriptionPlanAccount.publicKey, s.programId)
   const maxAmount = 10
-  const subscriptionTimeframe = 10
+  const cycleCount = 1
 
   const transaction = new Transaction()
   // Subscription account
@@ -163,7 +236,8 @@ const main = async () => {
     })
   )
 
-  await token.approve(tokenAddress, s.programId, _ourAccount, [], 500)
+  // Claims are made by the plan authority as delegate of the subscriber's token account
+  await token.approve(tokenAddress, authority, _ourAccount, [], 500)
 
   // Approve
   // transaction.add(
@@ -180,9 +254,13 @@ const main = async () => {
   transaction.add(
     createSubscriptionPlanInstruction(
       subscriptionPlanAccount.publicKey,
-      tokenAddress,
       _ourAccount.publicKey,
-      subscriptionTimeframe,
+      authority,
+      nonce,
+      _tokenAddress,
+      tokenAddress,
+      CycleUnit.CalendarMonths,
+      cycleCount,
       maxAmount,
       s.programId
     )
@@ -191,11 +269,13 @@ const main = async () => {
   transaction.add(
     createSubscriptionInstruction(
       subscriptionAccount.publicKey,
+      subscriptionPlanAccount.publicKey,
+      _ourAccount.publicKey,
       tokenAddress,
-      customer.publicKey,
-      payoutAddress.publicKey,
+      _tokenAddress,
+      CycleUnit.CalendarMonths,
+      cycleCount,
       maxAmount,
-      subscriptionTimeframe,
       s.programId
     )
   )
@@ -0,0 +1,158 @@
+//! Builds ClaimBatch transactions and sends them with retries
+
+use crate::scanner::{ClaimGroup, DueClaim};
+use recurring_payments_service::{instruction, state::PricingMode};
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    sysvar,
+    transaction::Transaction,
+};
+use std::{cmp, thread, time::Duration};
+
+/// Compute budget requested for a batch, each claim can make a token transfer CPI
+const CLAIM_BATCH_COMPUTE_UNITS: u32 = 1_400_000;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Builds the ClaimBatch instruction for some of a group's due claims, `None` if the plan doesn't take its mint
+pub fn claim_batch_instruction(
+    program_id: &Pubkey,
+    keeper: &Pubkey,
+    group: &ClaimGroup,
+    claims: &[DueClaim],
+) -> Option<Instruction> {
+    let subscription_plan = &group.subscription_plan;
+    let accepted_mint = subscription_plan.accepted_mint(&group.mint).ok()?;
+
+    let claim_vault = if accepted_mint.is_native() {
+        None
+    } else if subscription_plan.dispute_window > 0 {
+        Some(&accepted_mint.escrow_account)
+    } else if subscription_plan.settlement_interval > 0 {
+        Some(&accepted_mint.settlement_vault)
+    } else {
+        None
+    };
+    let rent = sysvar::rent::id();
+    let rent_or_price_feed = if accepted_mint.is_native() {
+        Some(&rent)
+    } else if accepted_mint.pricing_mode == PricingMode::UsdCents {
+        Some(&accepted_mint.price_feed)
+    } else {
+        None
+    };
+    let claims: Vec<_> = claims
+        .iter()
+        .map(|claim| (claim.subscription, claim.source, claim.subscriber_profile))
+        .collect();
+
+    Some(instruction::claim_batch(
+        program_id,
+        keeper,
+        &group.subscription_plan_key,
+        &subscription_plan.authority,
+        &accepted_mint.payout_account,
+        &accepted_mint.mint,
+        &accepted_mint.token_program,
+        claim_vault,
+        rent_or_price_feed,
+        &claims,
+    ))
+}
+
+/// Instructions of a batch transaction, the ClaimBatch after its compute budget
+fn batch_instructions(instruction: Instruction) -> [Instruction; 2] {
+    [
+        ComputeBudgetInstruction::set_compute_unit_limit(CLAIM_BATCH_COMPUTE_UNITS),
+        instruction,
+    ]
+}
+
+/// Sends a batch, resending it with a fresh blockhash and exponential backoff until it lands or retries run out
+pub fn send_with_retry(
+    rpc_client: &RpcClient,
+    keeper: &Keypair,
+    instruction: Instruction,
+    max_retries: u32,
+) -> Result<Signature, ClientError> {
+    let instructions = batch_instructions(instruction);
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result = rpc_client.get_latest_blockhash().and_then(|blockhash| {
+            let transaction =
+                Transaction::new_signed_with_payer(&instructions, Some(&keeper.pubkey()), &[keeper], blockhash);
+            rpc_client.send_and_confirm_transaction(&transaction)
+        });
+        match result {
+            Err(error) if attempt < max_retries => {
+                attempt += 1;
+                eprintln!(
+                    "Claim batch failed, retry {} of {} in {:?}: {}",
+                    attempt, max_retries, backoff, error
+                );
+                thread::sleep(backoff);
+                backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MAX_BATCH_SIZE;
+    use recurring_payments_service::{
+        constants::SUBSCRIPTION_PLAN_SIZE,
+        state::{AcceptedMint, SubscriptionPlan},
+    };
+    use solana_sdk::{packet::PACKET_DATA_SIZE, program_pack::Pack};
+
+    #[test]
+    fn largest_batch_fits_in_a_packet() {
+        // Every account distinct and both optional accounts passed, a token claim into a vault priced in USD
+        let accepted_mint = AcceptedMint {
+            mint: Pubkey::new_unique(),
+            token_program: Pubkey::new_unique(),
+            payout_account: Pubkey::new_unique(),
+            pricing_mode: PricingMode::UsdCents,
+            price_feed: Pubkey::new_unique(),
+            settlement_vault: Pubkey::new_unique(),
+            ..AcceptedMint::default()
+        };
+        let mut subscription_plan = SubscriptionPlan::unpack_unchecked(&[0; SUBSCRIPTION_PLAN_SIZE]).unwrap();
+        subscription_plan.authority = Pubkey::new_unique();
+        subscription_plan.settlement_interval = 1;
+        subscription_plan.accepted_mints.push(accepted_mint.clone());
+        let group = ClaimGroup {
+            subscription_plan_key: Pubkey::new_unique(),
+            subscription_plan,
+            mint: accepted_mint.mint,
+            claims: Vec::new(),
+        };
+        let claims: Vec<_> = (0..MAX_BATCH_SIZE)
+            .map(|_| DueClaim {
+                subscription: Pubkey::new_unique(),
+                source: Pubkey::new_unique(),
+                subscriber_profile: Pubkey::new_unique(),
+            })
+            .collect();
+        let keeper = Keypair::new();
+        let instruction = claim_batch_instruction(&Pubkey::new_unique(), &keeper.pubkey(), &group, &claims).unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &batch_instructions(instruction),
+            Some(&keeper.pubkey()),
+            &[&keeper],
+            Default::default(),
+        );
+
+        // A legacy transaction is its signatures, behind a one byte count, followed by the message
+        let size = 1 + transaction.signatures.len() * 64 + transaction.message.serialize().len();
+        assert!(size <= PACKET_DATA_SIZE, "{} byte transaction", size);
+    }
+}
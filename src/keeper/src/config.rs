@@ -0,0 +1,86 @@
+//! Command line options of the keeper
+
+use solana_sdk::pubkey::Pubkey;
+use std::{str::FromStr, time::Duration};
+
+pub const USAGE: &str = "\
+Usage: recurring-payments-keeper --program-id <PUBKEY> [options]
+
+Options:
+    --url <URL>             RPC endpoint, defaults to a local test validator at http://localhost:8899
+    --keypair <PATH>        keeper keypair paying fees and earning tips, defaults to ~/.config/solana/id.json
+    --program-id <PUBKEY>   recurring payments program to crank
+    --batch-size <N>        subscriptions per ClaimBatch transaction, defaults to 7
+    --interval <SECONDS>    time between scans, defaults to 60
+    --max-retries <N>       times a failed transaction is resent with backoff, defaults to 5
+    --once                  scan and claim once, then exit
+";
+
+/// Most subscriptions that fit in one legacy transaction next to the plan accounts, the claim vault and price feed
+pub const MAX_BATCH_SIZE: usize = 7;
+
+#[derive(Debug)]
+pub struct Config {
+    pub rpc_url: String,
+    pub keypair_path: String,
+    pub program_id: Pubkey,
+    pub batch_size: usize,
+    pub interval: Duration,
+    pub max_retries: u32,
+    pub once: bool,
+}
+
+impl Config {
+    /// Parses the options following the binary name
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut rpc_url = "http://localhost:8899".to_string();
+        let mut keypair_path = None;
+        let mut program_id = None;
+        let mut batch_size = MAX_BATCH_SIZE;
+        let mut interval = 60;
+        let mut max_retries = 5;
+        let mut once = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--url" => rpc_url = value()?,
+                "--keypair" => keypair_path = Some(value()?),
+                "--program-id" => program_id = Some(parse(&arg, &value()?)?),
+                "--batch-size" => batch_size = parse(&arg, &value()?)?,
+                "--interval" => interval = parse(&arg, &value()?)?,
+                "--max-retries" => max_retries = parse(&arg, &value()?)?,
+                "--once" => once = true,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+
+        if batch_size == 0 || batch_size > MAX_BATCH_SIZE {
+            return Err(format!("--batch-size must be between 1 and {}", MAX_BATCH_SIZE));
+        }
+        let keypair_path = match keypair_path {
+            Some(keypair_path) => keypair_path,
+            None => {
+                let home =
+                    std::env::var("HOME").map_err(|_| "--keypair is required when HOME is not set".to_string())?;
+                format!("{}/.config/solana/id.json", home)
+            },
+        };
+
+        Ok(Config {
+            rpc_url,
+            keypair_path,
+            program_id: program_id.ok_or("--program-id is required")?,
+            batch_size,
+            interval: Duration::from_secs(interval),
+            max_retries,
+            once,
+        })
+    }
+}
+
+fn parse<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, option))
+}
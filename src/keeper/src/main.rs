@@ -0,0 +1,75 @@
+//! Keeper that claims due subscriptions of the recurring payments program on a schedule
+
+// `ClientError` is what every `RpcClient` call returns, boxing it at each call site would gain nothing
+#![allow(clippy::result_large_err)]
+
+mod claimer;
+mod config;
+mod scanner;
+
+use config::{Config, USAGE};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    signature::{read_keypair_file, Keypair, Signer},
+};
+use std::{process, thread};
+
+fn main() {
+    let config = Config::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}\n\n{}", error, USAGE);
+        process::exit(2);
+    });
+    let keeper = read_keypair_file(&config.keypair_path).unwrap_or_else(|error| {
+        eprintln!("Could not read keypair {}: {}", config.keypair_path, error);
+        process::exit(1);
+    });
+    let rpc_client = RpcClient::new_with_commitment(config.rpc_url.clone(), CommitmentConfig::confirmed());
+    println!(
+        "Keeper {} cranking {} at {}",
+        keeper.pubkey(),
+        config.program_id,
+        config.rpc_url
+    );
+
+    loop {
+        crank(&rpc_client, &keeper, &config);
+        if config.once {
+            break;
+        }
+        thread::sleep(config.interval);
+    }
+}
+
+/// Scans once and submits a ClaimBatch for every batch of due subscriptions, a failed batch doesn't stop the rest
+fn crank(rpc_client: &RpcClient, keeper: &Keypair, config: &Config) {
+    let groups = match scanner::scan(rpc_client, &config.program_id, &keeper.pubkey()) {
+        Ok(groups) => groups,
+        Err(error) => {
+            eprintln!("Scan failed: {}", error);
+            return;
+        },
+    };
+
+    for group in &groups {
+        for claims in group.claims.chunks(config.batch_size) {
+            let instruction =
+                match claimer::claim_batch_instruction(&config.program_id, &keeper.pubkey(), group, claims) {
+                    Some(instruction) => instruction,
+                    None => continue,
+                };
+            match claimer::send_with_retry(rpc_client, keeper, instruction, config.max_retries) {
+                Ok(signature) => println!(
+                    "Claimed {} subscriptions of plan {}: {}",
+                    claims.len(),
+                    group.subscription_plan_key,
+                    signature
+                ),
+                Err(error) => eprintln!(
+                    "Giving up on a batch of plan {}: {}",
+                    group.subscription_plan_key, error
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Finds the subscriptions the keeper can claim
+
+use recurring_payments_service::{
+    constants::{SUBSCRIBER_PROFILE_SEED, SUBSCRIPTION_PLAN_SIZE, SUBSCRIPTION_SIZE},
+    state::{AccountType, PaymentMode, Subscription, SubscriptionPlan},
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::ClientError,
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    account::{from_account, Account},
+    clock::{Clock, UnixTimestamp},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    sysvar,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// A subscription with something left to claim this cycle, and the accounts its claim needs
+#[derive(Debug)]
+pub struct DueClaim {
+    pub subscription: Pubkey,
+    pub source: Pubkey,
+    pub subscriber_profile: Pubkey,
+}
+
+/// Due subscriptions of one plan paying with the same mint, which can share a ClaimBatch
+#[derive(Debug)]
+pub struct ClaimGroup {
+    pub subscription_plan_key: Pubkey,
+    pub subscription_plan: SubscriptionPlan,
+    pub mint: Pubkey,
+    pub claims: Vec<DueClaim>,
+}
+
+/// Scans the program's accounts for subscriptions the keeper is allowed to claim and that are due
+pub fn scan(rpc_client: &RpcClient, program_id: &Pubkey, keeper: &Pubkey) -> Result<Vec<ClaimGroup>, ClientError> {
+    let clock_account = rpc_client.get_account(&sysvar::clock::id())?;
+    let now = from_account::<Clock, _>(&clock_account)
+        .map(|clock| clock.unix_timestamp)
+        .ok_or_else(|| ClientError::from(std::io::Error::other("clock sysvar could not be decoded")))?;
+
+    // Only the owner can claim plans that don't tip keepers
+    let subscription_plans: HashMap<Pubkey, SubscriptionPlan> = program_accounts(
+        rpc_client,
+        program_id,
+        AccountType::SubscriptionPlan,
+        SUBSCRIPTION_PLAN_SIZE,
+    )?
+    .into_iter()
+    .filter_map(|(key, account)| Some((key, SubscriptionPlan::unpack(&account.data).ok()?)))
+    .filter(|(_, subscription_plan)| subscription_plan.owner == *keeper || subscription_plan.keeper_tip > 0)
+    .collect();
+
+    let mut due_claims: BTreeMap<(Pubkey, Pubkey), Vec<DueClaim>> = BTreeMap::new();
+    for (key, account) in program_accounts(rpc_client, program_id, AccountType::Subscription, SUBSCRIPTION_SIZE)? {
+        let subscription = match Subscription::unpack(&account.data) {
+            Ok(subscription) => subscription,
+            Err(_) => continue,
+        };
+        let subscription_plan = match subscription_plans.get(&subscription.subscription_plan_account) {
+            Some(subscription_plan) => subscription_plan,
+            None => continue,
+        };
+        if !is_due(subscription_plan, &subscription, now) {
+            continue;
+        }
+
+        let (subscriber_profile, _) =
+            Pubkey::find_program_address(&[SUBSCRIBER_PROFILE_SEED, subscription.owner.as_ref()], program_id);
+        let source = match subscription.payment_mode {
+            PaymentMode::Delegate => subscription.token_account,
+            PaymentMode::Escrow | PaymentMode::NativeEscrow => subscription.vault_account,
+        };
+        due_claims
+            .entry((subscription.subscription_plan_account, subscription.mint))
+            .or_default()
+            .push(DueClaim {
+                subscription: key,
+                source,
+                subscriber_profile,
+            });
+    }
+
+    Ok(due_claims
+        .into_iter()
+        .map(|((subscription_plan_key, mint), claims)| ClaimGroup {
+            subscription_plan_key,
+            subscription_plan: subscription_plans[&subscription_plan_key].clone(),
+            mint,
+            claims,
+        })
+        .collect())
+}
+
+/// Whether a claim would transfer anything right now, mirroring the checks the program makes
+fn is_due(subscription_plan: &SubscriptionPlan, subscription: &Subscription, now: UnixTimestamp) -> bool {
+    if !subscription.is_approved || (subscription_plan.dispute_window > 0 && subscription.is_disputed) {
+        return false;
+    }
+    if subscription.is_term_complete(now).unwrap_or(true) {
+        return false;
+    }
+    let mut subscription = subscription.clone();
+    subscription.roll_cycle(now).is_ok() && subscription.claimable_amount() > 0
+}
+
+/// Fetches every account of the program with the given type and data size
+fn program_accounts(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    account_type: AccountType,
+    data_size: usize,
+) -> Result<Vec<(Pubkey, Account)>, ClientError> {
+    rpc_client.get_program_accounts_with_config(
+        program_id,
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(data_size as u64),
+                RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, vec![account_type as u8])),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        },
+    )
+}
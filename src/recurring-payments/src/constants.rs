@@ -1,8 +1,57 @@
+use crate::state::{
+  LegacySubscription, LegacySubscriptionPlan, MaxEncodedSize, PriceFeed, SubscriberProfile, Subscription,
+  SubscriptionPlan, SubscriptionPlanV1, SubscriptionPlanV2, SubscriptionV1,
+};
 #[cfg(feature = "production")]
 use std::env;
 
-pub const SUBSCRIPTION_PLAN_SIZE: usize = 114;
-pub const SUBSCRIPTION_SIZE: usize = 130;
+pub const MAX_ACCEPTED_MINTS: usize = 4;
+/// Bytes kept free at the end of the fixed fields for new fields, see `state::layout`
+pub const SUBSCRIPTION_PLAN_RESERVED_SIZE: usize = 64;
+pub const SUBSCRIPTION_RESERVED_SIZE: usize = 32;
+/// Size of the merchant's reference to their customer a subscription can carry
+pub const CUSTOMER_REFERENCE_LEN: usize = 32;
+/// Sizes of the plan metadata fields in bytes of UTF-8
+pub const MAX_PLAN_NAME_LEN: usize = 32;
+pub const MAX_PLAN_URI_LEN: usize = 200;
+pub const MAX_PLAN_EXTERNAL_ID_LEN: usize = 64;
+// Account sizes follow from the field types of each account, see `state::layout`
+pub const SUBSCRIPTION_PLAN_SIZE: usize = SubscriptionPlan::MAX_ENCODED_SIZE;
+pub const SUBSCRIPTION_SIZE: usize = Subscription::MAX_ENCODED_SIZE;
+pub const PRICE_FEED_SIZE: usize = PriceFeed::MAX_ENCODED_SIZE;
+pub const SUBSCRIBER_PROFILE_SIZE: usize = SubscriberProfile::MAX_ENCODED_SIZE;
+pub const LEGACY_SUBSCRIPTION_PLAN_SIZE: usize = LegacySubscriptionPlan::MAX_ENCODED_SIZE;
+pub const LEGACY_SUBSCRIPTION_SIZE: usize = LegacySubscription::MAX_ENCODED_SIZE;
+pub const SUBSCRIPTION_PLAN_V1_SIZE: usize = SubscriptionPlanV1::MAX_ENCODED_SIZE;
+pub const SUBSCRIPTION_PLAN_V2_SIZE: usize = SubscriptionPlanV2::MAX_ENCODED_SIZE;
+pub const SUBSCRIPTION_V1_SIZE: usize = SubscriptionV1::MAX_ENCODED_SIZE;
+
+/// Layout versions accounts are written in, bumped whenever MigrateAccount has to rewrite older accounts. Version 0
+/// is the legacy layout from before accounts carried a version, every older layout is kept in `state::legacy`.
+pub const SUBSCRIPTION_PLAN_VERSION: u8 = 3;
+pub const SUBSCRIPTION_VERSION: u8 = 2;
+
+/// Seed of the per-wallet `SubscriberProfile` PDA, followed by the wallet address
+pub const SUBSCRIBER_PROFILE_SEED: &[u8] = b"subscriber_profile";
+
+/// Seed of the vault PDA a token escrow subscription holds its deposit in, followed by the plan and subscription
+pub const SUBSCRIPTION_VAULT_SEED: &[u8] = b"subscription_vault";
+/// Seeds of the per-mint escrow account and settlement vault PDAs of a plan, followed by the plan and the mint
+pub const PLAN_ESCROW_SEED: &[u8] = b"plan_escrow";
+pub const SETTLEMENT_VAULT_SEED: &[u8] = b"settlement_vault";
+
+pub const SECONDS_PER_DAY: i64 = 86_400;
+/// Shortest billing cycle a plan can be created with, in seconds
+pub const MIN_CYCLE_DURATION: i64 = 60 * 60;
+/// Longest billing cycle a plan can be created with, in seconds. Ten years of 366 days, so `Years(10)` fits.
+pub const MAX_CYCLE_DURATION: i64 = 10 * 366 * SECONDS_PER_DAY;
+/// Latest day of the month billing can be anchored to, so every month has it
+pub const MAX_BILLING_ANCHOR_DAY: u8 = 28;
+
+/// Oldest price feed update a claim will convert USD cents with
+pub const MAX_PRICE_FEED_AGE: i64 = 60;
+/// Widest confidence interval a claim accepts, in basis points of the price
+pub const MAX_PRICE_CONFIDENCE_BPS: u64 = 200;
 
 #[cfg(feature = "production")]
 const PROGRAM_OWNER_FEE_ADDRESS: &'static str = env!("PROGRAM_OWNER_FEE_ADDRESS");
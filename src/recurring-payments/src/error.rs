@@ -20,6 +20,110 @@ pub enum RecurringPaymentsError {
   IncorrectTokenProgramId,
   #[error("Deserialized account is not an SPL Token account")]
   ExpectedAccount,
+  #[error("Token account mint does not match the subscription plan token")]
+  InvalidMint,
+  #[error("Payout account does not match the subscription plan")]
+  InvalidPayoutAccount,
+  #[error("Subscription does not belong to the provided subscription plan")]
+  InvalidSubscriptionPlan,
+  #[error("Source account does not match the subscription")]
+  InvalidSourceAccount,
+  #[error("Vault account is not the vault PDA of the subscription")]
+  InvalidVaultAccount,
+  #[error("Instruction is not supported by the subscription payment mode")]
+  InvalidPaymentMode,
+  #[error("Subscription is not approved")]
+  SubscriptionNotApproved,
+  #[error("Nothing to claim in the current cycle")]
+  NothingToClaim,
+  #[error("Vault does not hold enough unreserved funds")]
+  InsufficientVaultFunds,
+  #[error("Calculation overflow")]
+  Overflow,
+  #[error("Account is not the subscriber")]
+  InvalidSubscriber,
+  #[error("Native SOL plans only support native escrow subscriptions")]
+  NativePlanRequiresEscrow,
+  #[error("Deserialized account is not an SPL Token mint")]
+  ExpectedMint,
+  #[error("Mint uses a Token-2022 extension that subscriptions do not support")]
+  UnsupportedMintExtension,
+  #[error("Vault received less than the deposit after the Token-2022 transfer fee")]
+  DepositShortAfterTransferFee,
+  #[error("Mint decimals do not match the subscription plan")]
+  InvalidMintDecimals,
+  #[error("Mint is already accepted by the plan")]
+  MintAlreadyAccepted,
+  #[error("Subscription plan accepts the maximum number of mints")]
+  TooManyAcceptedMints,
+  #[error("Price feed does not match the plan or holds no usable price")]
+  InvalidPriceFeed,
+  #[error("Price feed has not been updated recently enough")]
+  StalePriceFeed,
+  #[error("Price feed confidence interval is too wide")]
+  PriceConfidenceTooWide,
+  #[error("USD priced mints only support delegate subscriptions")]
+  OraclePricingRequiresDelegate,
+  #[error("Price feed was published after the current time")]
+  PriceFeedFromFuture,
+  #[error("Billing cycle is shorter than the minimum duration")]
+  CycleTooShort,
+  #[error("Billing cycle is longer than the maximum duration")]
+  CycleTooLong,
+  #[error("Subscription term is complete")]
+  TermCompleted,
+  #[error("Subscription term is not complete yet")]
+  TermNotComplete,
+  #[error("Vault still holds funds")]
+  VaultNotEmpty,
+  #[error("Billing anchor day must be within the month and needs a calendar month cycle")]
+  InvalidBillingAnchor,
+  #[error("Subscriber profile is not the PDA of the subscriber")]
+  InvalidSubscriberProfile,
+  #[error("Claim would exceed the subscriber's monthly spending cap")]
+  SpendingCapExceeded,
+  #[error("Subscription is not paid in the mint of the subscriber's spending cap")]
+  SpendingCapMintMismatch,
+  #[error("Destination account does not match the subscription")]
+  InvalidDestinationAccount,
+  #[error("Only fixed price subscriptions can have their allowance restored by a refund")]
+  CannotRestoreUsdAllowance,
+  #[error("Dispute window cannot be negative")]
+  InvalidDisputeWindow,
+  #[error("Escrow account does not match the subscription plan")]
+  InvalidEscrowAccount,
+  #[error("Subscription has an open dispute")]
+  SubscriptionDisputed,
+  #[error("Nothing is held in escrow that can still be disputed")]
+  DisputeWindowClosed,
+  #[error("Dispute window has not passed yet")]
+  DisputeWindowOpen,
+  #[error("Subscription has no open dispute")]
+  NotDisputed,
+  #[error("Signer is not the plan arbiter")]
+  InvalidArbiter,
+  #[error("Refund is larger than the escrowed amount")]
+  RefundExceedsEscrow,
+  #[error("Subscription still has funds held in escrow")]
+  EscrowNotReleased,
+  #[error("Settlement interval cannot be negative")]
+  InvalidSettlementInterval,
+  #[error("Settlement vault does not match the subscription plan")]
+  InvalidSettlementVault,
+  #[error("Settlement interval has not passed yet")]
+  SettlementNotDue,
+  #[error("Subscriber's token account cannot cover the claim")]
+  InsufficientSourceFunds,
+  #[error("Subscription plan account cannot fund the keeper tip")]
+  InsufficientKeeperTipFunds,
+  #[error("Account is of the wrong type")]
+  WrongAccountType,
+  #[error("Account layout version is not supported, it needs to be migrated")]
+  UnsupportedAccountVersion,
+  #[error("Account is not in a layout that can be migrated")]
+  NothingToMigrate,
+  #[error("Plan metadata is too long or not valid text")]
+  InvalidPlanMetadata,
 }
 
 impl From<RecurringPaymentsError> for ProgramError {
@@ -1,90 +1,1086 @@
-use solana_program::program_error::ProgramError;
-use std::convert::TryInto;
+use solana_program::{
+    clock::UnixTimestamp,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
 
-use crate::error::RecurringPaymentsError;
+use crate::{constants::CUSTOMER_REFERENCE_LEN, error::RecurringPaymentsError, state::CycleInterval};
+use borsh::{BorshDeserialize, BorshSerialize};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum RecurringPaymentsInstruction {
-    /// Starts the trade by creating and populating an RecurringPayments account and transferring ownership of the given temp token account to the PDA
+    /// Creates a subscription plan that subscribers can sign up for, paid in a single mint until more are added
     ///
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person initializing the RecurringPayments
-    /// 1. `[writable]` The subscription account, it will hold all necessary info about the subscription.
-    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The RecurringPayments account, it will hold all necessary info about the trade.
-    /// 4. `[]` The clock sysvar
+    /// 0. `[writable]` The subscription plan account, it will hold all necessary info about the plan.
+    /// 1. `[signer]` The plan owner
+    /// 2. `[]` The plan authority, derived from the plan account and nonce
+    /// 3. `[]` The spl-token or Token-2022 mint the plan is paid in, the native mint for SOL plans
+    /// 4. `[]` The owner's token account that receives claimed funds, or a wallet for SOL plans
+    /// 5. `[]` USD pricing only: the price feed that converts `max_amount` from USD cents at claim time
     CreateSubscriptionPlan {
         /// nonce used to create valid program address
         nonce: u8,
-        /// Length of the subscription (1 Month ususally) in days
-        subscription_timeframe: u64,
-        /// max amount that can be withdrawn in one timeframe
+        /// Length of a billing cycle, packed as a unit tag followed by a u64 count. Must lie between
+        /// `MIN_CYCLE_DURATION` and `MAX_CYCLE_DURATION`
+        subscription_timeframe: CycleInterval,
+        /// max amount of the mint that can be withdrawn in one timeframe, in USD cents when a price feed is passed
         max_amount: u64,
+        /// one-time fee charged to the payout account when subscribing, in the same unit as `max_amount`
+        setup_fee: u64,
+        /// number of cycles each subscription lasts, 0 for no limit
+        max_cycles: u64,
+        /// time all subscriptions to the plan end, 0 for no end date
+        end_timestamp: UnixTimestamp,
+        /// charge the first period inside CreateSubscription instead of waiting for a claim
+        charge_upfront: bool,
+        /// day of the month (1-28) cycles start on, 0 to start them on signup. Calendar month cycles only. The first
+        /// period up to the anchor is prorated against the plan's whole cycle, all n months of `CalendarMonths(n)`
+        billing_anchor_day: u8,
     },
-    /// Starts the trade by creating and populating an RecurringPayments account and transferring ownership of the given temp token account to the PDA
+    /// Subscribes to a plan. With `prepaid_cycles` set to 0 the subscriber must approve the plan authority as
+    /// delegate of their token account, otherwise `prepaid_cycles * max_amount` is deposited into the vault and
+    /// claims draw from there. Native SOL plans keep the prepaid lamports in the subscription account itself. The
+    /// subscriber pays any Token-2022 transfer fee on top of the deposit, so the vault receives it in full.
+    ///
+    /// Plans with a billing anchor day start with a prorated period up to the next anchor day. Plans that charge
+    /// upfront transfer that first period from the subscriber to the payout account in the same instruction,
+    /// together with the setup fee of the chosen mint.
     ///
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` The account of the person initializing the RecurringPayments
-    /// 1. `[writable]` The subscription account, it will hold all necessary info about the subscription.
-    /// 2. `[]` The initializer's token account for the token they will receive should the trade go through
-    /// 3. `[writable]` The RecurringPayments account, it will hold all necessary info about the trade.
-    /// 4. `[]` The clock sysvar
+    /// 0. `[writable]` The subscription account, it will hold all necessary info about the subscription.
+    /// 1. `[]` The subscription plan account
+    /// 2. `[writable, signer]` The subscriber, pays for the vault in token escrow mode
+    /// 3. `[writable]` The subscriber's token account, or for SOL plans the subscriber's own wallet
+    /// 4. `[]` The accepted plan mint the subscriber pays in
+    /// 5. `[]` The mint's token program, or the system program for SOL
+    /// 6. `[]` The clock sysvar
+    /// 7. `[writable]` Token escrow mode only: the subscription vault, the PDA of `SUBSCRIPTION_VAULT_SEED`, the plan
+    ///    and the subscription. It is created here as a token account owned by the plan authority
+    /// 8. `[]` Token escrow mode only: the system program
+    /// 9. `[writable]` Upfront or setup fee plans only: the plan payout account for the mint, passed as 7 without a
+    ///    vault
+    /// 10. `[]` Upfront or setup fee USD priced plans only: the plan's price feed, right after the payout account
     CreateSubscription {
-        /// Length of the subscription (1 Month ususally) in days
-        subscription_timeframe: u64,
-        /// max amount that can be withdrawn in one timeframe
+        /// Length of a billing cycle, must match the plan
+        subscription_timeframe: CycleInterval,
+        /// max amount that can be withdrawn in one timeframe, the plan price for the chosen mint
         max_amount: u64,
+        /// number of cycles to deposit into the vault, 0 for delegate mode
+        prepaid_cycles: u64,
+        /// merchant's reference to the customer, such as a hash of their ID or a zero padded short string, logged by
+        /// every claim. All zeros for none
+        customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
     },
-
+    /// Transfers what is left to claim in the current cycle to the plan payout account. Plans that pay keeper tips
+    /// can be claimed by anyone, the keeper receives the tip from the plan account's lamports.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The plan owner, or any keeper when the plan pays keeper tips
+    /// 1. `[writable]` The subscription account
+    /// 2. `[writable]` The subscription plan account
+    /// 3. `[]` The plan authority
+    /// 4. `[writable]` The subscriber's token account, or the vault in escrow mode
+    /// 5. `[writable]` The plan payout account for the subscription mint
+    /// 6. `[]` The subscription mint, its decimals must match the ones recorded on the plan
+    /// 7. `[]` The mint's token program
+    /// 8. `[]` The clock sysvar
+    /// 9. `[writable]` The subscriber's profile PDA, the claim counts towards its cap once the subscriber created it
+    /// 10. `[writable]` Token plans with a dispute window only: the mint's escrow account, which receives the claim.
+    ///     Token plans with a settlement interval and no dispute window only: the mint's settlement vault
+    /// 11. `[]` Native escrow mode only: the rent sysvar. USD priced mints only: the plan's price feed
     Claim {},
+    /// Deposits more funds into the vault of an escrow subscription
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The owner of the source token account
+    /// 1. `[]` The subscription account
+    /// 2. `[writable]` The source token account, or the funding wallet for SOL plans
+    /// 3. `[writable]` The subscription vault PDA, or the subscription account for SOL plans
+    /// 4. `[]` The subscription mint
+    /// 5. `[]` The mint's token program, or the system program for SOL
+    TopUp {
+        /// amount the vault receives, any Token-2022 transfer fee is charged to the source account on top
+        amount: u64,
+    },
+    /// Returns vault funds that are not reserved for the current cycle to the subscriber
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The subscriber
+    /// 1. `[writable]` The subscription account
+    /// 2. `[]` The subscription plan account
+    /// 3. `[]` The plan authority
+    /// 4. `[writable]` The subscription vault PDA, or the subscription account for SOL plans
+    /// 5. `[writable]` The destination token account, or wallet for SOL plans
+    /// 6. `[]` The subscription mint
+    /// 7. `[]` The mint's token program
+    /// 8. `[]` The clock sysvar
+    /// 9. `[]` Native escrow mode only: the rent sysvar
+    WithdrawUnused {
+        /// amount to withdraw
+        amount: u64,
+    },
+    /// Lets subscribers pay the plan in another mint, at its own price and to its own payout account
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[signer]` The plan owner
+    /// 2. `[]` The spl-token or Token-2022 mint to accept, the native mint for SOL
+    /// 3. `[]` The owner's token account that receives claimed funds, or a wallet for SOL
+    /// 4. `[]` USD pricing only: the price feed that converts `max_amount` from USD cents at claim time, owned by the
+    ///    same program as the plan's other feeds
+    AddAcceptedMint {
+        /// max amount of the mint that can be withdrawn in one timeframe, in USD cents when a price feed is passed
+        max_amount: u64,
+        /// one-time fee charged to the payout account when subscribing, in the same unit as `max_amount`
+        setup_fee: u64,
+    },
+    /// Closes a subscription whose fixed term is complete, returning its rent and any prepaid lamports to the
+    /// subscriber. Anyone can close it, token escrow subscriptions must have their vault emptied first.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription account
+    /// 1. `[writable]` The subscriber's wallet, receives the lamports
+    /// 2. `[]` The clock sysvar
+    /// 3. `[]` Token escrow mode only: the vault token account
+    CloseSubscription {},
+    /// Sets the monthly cap on what all of a wallet's subscriptions can claim together, creating the wallet's
+    /// subscriber profile on first use. The cap is counted in one mint and claims in other mints fail while the
+    /// profile exists. Changing the mint restarts the count for the current period.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscriber profile PDA, derived from `SUBSCRIBER_PROFILE_SEED` and the subscriber
+    /// 1. `[writable, signer]` The subscriber, pays for the profile account
+    /// 2. `[]` The mint the cap is counted in
+    /// 3. `[]` The system program
+    /// 4. `[]` The clock sysvar
+    /// 5. `[]` The rent sysvar
+    SetSpendingCap {
+        /// max amount of the mint all subscriptions together can claim per calendar month
+        monthly_cap: u64,
+    },
+    /// Sends funds from the plan payout account back to the subscriber and records them on the subscription
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The payout account owner or its delegate, the payout wallet itself for SOL plans
+    /// 1. `[writable]` The subscription account
+    /// 2. `[]` The subscription plan account
+    /// 3. `[writable]` The plan payout account for the subscription mint
+    /// 4. `[writable]` The subscriber's token account, or their wallet for SOL plans
+    /// 5. `[]` The subscription mint
+    /// 6. `[]` The mint's token program, or the system program for SOL
+    /// 7. `[]` The clock sysvar
+    Refund {
+        /// amount to refund, in token units
+        amount: u64,
+        /// take the refund off the amount withdrawn this cycle so it can be claimed again, fixed price mints only
+        restore_allowance: bool,
+    },
+    /// Holds claims in escrow for a dispute window before they can be released to the merchant. Token claims go to
+    /// an escrow account per mint, SOL claims stay in the subscription account. Mints added later need this
+    /// instruction to run again before they can be claimed.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[signer, writable]` The plan owner, pays for escrow accounts created here
+    /// 2. `[]` The arbiter, resolves disputes subscribers open
+    /// 3. `[]` With a dispute window only: the system program
+    /// 4. ..4+3N With a dispute window only: for each accepted mint other than SOL, in the order the plan lists them
+    ///    `[writable]` The mint's escrow account, the PDA of `PLAN_ESCROW_SEED`, the plan and the mint. It is
+    ///    created as a token account owned by the plan authority unless the plan already has it
+    ///    `[]` The mint
+    ///    `[]` The mint's token program
+    SetDisputeSettings {
+        /// seconds claims are held before they can be released, 0 to pay out directly
+        dispute_window: i64,
+    },
+    /// Freezes what the subscriber's claims hold in escrow until the arbiter resolves the dispute
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The subscriber
+    /// 1. `[writable]` The subscription account
+    /// 2. `[]` The subscription plan account
+    /// 3. `[]` The clock sysvar
+    OpenDispute {},
+    /// Splits a disputed escrow between a refund to the subscriber and a release to the merchant
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` The plan arbiter
+    /// 1. `[writable]` The subscription account
+    /// 2. `[]` The subscription plan account
+    /// 3. `[]` The plan authority
+    /// 4. `[writable]` The mint's escrow account, or the subscription account for SOL plans
+    /// 5. `[writable]` The plan payout account for the subscription mint
+    /// 6. `[writable]` The subscriber's token account, or their wallet for SOL plans
+    /// 7. `[]` The subscription mint
+    /// 8. `[]` The mint's token program
+    /// 9. `[]` SOL plans only: the rent sysvar
+    ResolveDispute {
+        /// part of the escrowed amount returned to the subscriber, the rest goes to the merchant
+        refund_amount: u64,
+    },
+    /// Sends a subscription's escrowed claims whose dispute window has passed to the payout account. Anyone can
+    /// release them, and claims release them too before holding the new claim in escrow.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription account
+    /// 1. `[]` The subscription plan account
+    /// 2. `[]` The plan authority
+    /// 3. `[writable]` The mint's escrow account, or the subscription account for SOL plans
+    /// 4. `[writable]` The plan payout account for the subscription mint
+    /// 5. `[]` The subscription mint
+    /// 6. `[]` The mint's token program
+    /// 7. `[]` The clock sysvar
+    /// 8. `[]` SOL plans only: the rent sysvar
+    ReleaseEscrow {},
+    /// Collects token claims in a settlement vault per mint instead of paying them out one by one, so subscribers
+    /// claimed in the same slot don't all write to the payout account. SOL claims keep paying out directly. Mints
+    /// added later need this instruction to run again before they can be claimed.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[signer, writable]` The plan owner, pays for settlement vaults created here
+    /// 2. `[]` With a settlement interval only: the system program
+    /// 3. ..3+3N With a settlement interval only: for each accepted mint other than SOL, in the order the plan lists
+    ///    them
+    ///    `[writable]` The mint's settlement vault, the PDA of `SETTLEMENT_VAULT_SEED`, the plan and the mint. It is
+    ///    created as a token account owned by the plan authority unless the plan already has it
+    ///    `[]` The mint
+    ///    `[]` The mint's token program
+    SetSettlementSchedule {
+        /// seconds between settlements, 0 to pay claims out directly
+        settlement_interval: i64,
+    },
+    /// Sweeps a mint's settlement vault to its payout account once the settlement interval has passed. Anyone can
+    /// settle.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[]` The plan authority
+    /// 2. `[writable]` The mint's settlement vault
+    /// 3. `[writable]` The plan payout account for the mint
+    /// 4. `[]` The mint
+    /// 5. `[]` The mint's token program
+    /// 6. `[]` The clock sysvar
+    Settle {},
+    /// Claims many subscriptions of one plan and mint in a single transaction. Each subscription is claimed on its
+    /// own, one that can't be claimed is skipped and logged without failing the others. The return data holds one
+    /// byte per subscription, 1 when it was claimed and 0 when it was skipped.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` The plan owner, or any keeper when the plan pays keeper tips, earning one per claim
+    /// 1. `[writable]` The subscription plan account
+    /// 2. `[]` The plan authority
+    /// 3. `[writable]` The plan payout account for the mint
+    /// 4. `[]` The mint every subscription in the batch pays with
+    /// 5. `[]` The mint's token program
+    /// 6. `[]` The clock sysvar
+    /// 7. `[writable]` Token plans with a dispute window only: the mint's escrow account.
+    ///    Token plans with a settlement interval and no dispute window only: the mint's settlement vault
+    /// 8. `[]` SOL plans only: the rent sysvar. USD priced mints only: the plan's price feed
+    /// 9. ..9+3N Three accounts per subscription, following whichever optional accounts above apply:
+    ///    `[writable]` The subscription account
+    ///    `[writable]` Its source: the subscriber's token account, or the subscription's vault in escrow mode
+    ///    `[writable]` The subscriber's profile PDA
+    ClaimBatch {},
+    /// Lets any keeper claim the plan's subscriptions for a tip per claim. Tips are paid from lamports sent to the
+    /// subscription plan account, above what keeps it rent exempt.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[signer]` The plan owner
+    SetKeeperTip {
+        /// lamports paid per claim, 0 so only the plan owner can claim
+        keeper_tip: u64,
+    },
+    /// Rewrites a subscription plan or subscription from an older layout version into the current one, growing the
+    /// account and topping up its rent. Anyone can migrate an account, the result only depends on what the account
+    /// already records. A legacy version 0 plan has to be migrated before its subscriptions.
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable, signer]` The payer of the extra rent
+    /// 1. `[writable]` The subscription plan or subscription account to migrate
+    /// 2. `[]` The system program
+    ///
+    /// For a version 0 subscription plan:
+    /// 3. `[]` The token account the plan pays out to
+    /// 4. `[]` Its mint, which becomes the plan's accepted mint
+    ///
+    /// For a version 0 subscription:
+    /// 3. `[]` The subscription plan account, already migrated
+    /// 4. `[]` The subscriber's token account the subscription pays from, its owner becomes the subscriber
+    MigrateAccount {},
+    /// Sets the name, off-chain JSON URI and external ID wallets show for the plan, empty strings clear them
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` The subscription plan account
+    /// 1. `[signer]` The plan owner
+    UpdatePlanMetadata {
+        /// display name, at most `MAX_PLAN_NAME_LEN` bytes
+        name: String,
+        /// URI of the off-chain JSON describing the plan, at most `MAX_PLAN_URI_LEN` bytes
+        uri: String,
+        /// merchant's ID of the plan in their product catalog, at most `MAX_PLAN_EXTERNAL_ID_LEN` bytes
+        external_id: String,
+    },
 }
 
 impl RecurringPaymentsInstruction {
-    /// Unpacks a byte buffer
+    /// Unpacks a byte buffer, a u8 tag in declaration order followed by the variant's fields
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let (tag, src) = input.split_first().ok_or(RecurringPaymentsError::InvalidInstruction)?;
-
-        Ok(match tag {
-            0 => {
-                let (&nonce, src) = src.split_first().ok_or(RecurringPaymentsError::InvalidInstruction)?;
-                let (subscription_timeframe, src) = Self::unpack_u64(src)?;
-                let (max_amount, _src) = Self::unpack_u64(src)?;
-
-                Self::CreateSubscriptionPlan {
-                    nonce,
-                    subscription_timeframe,
-                    max_amount,
-                }
-            }
-            1 => {
-                let (subscription_timeframe, src) = Self::unpack_u64(src)?;
-                let (max_amount, _src) = Self::unpack_u64(src)?;
-
-                Self::CreateSubscription {
-                    subscription_timeframe,
-                    max_amount,
-                }
-            }
-            2 => Self::Claim {},
-            _ => return Err(RecurringPaymentsError::InvalidInstruction.into()),
-        })
-    }
-
-    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
-        if input.len() >= 8 {
-            let (amount, src) = input.split_at(8);
-            let amount = amount
-                .get(..8)
-                .and_then(|slice| slice.try_into().ok())
-                .map(u64::from_le_bytes)
-                .ok_or(RecurringPaymentsError::InvalidInstruction)?;
-            Ok((amount, src))
-        } else {
-            Err(RecurringPaymentsError::InvalidInstruction.into())
+        Self::try_from_slice(input).map_err(|_| RecurringPaymentsError::InvalidInstruction.into())
+    }
+
+    /// Packs the instruction into the byte buffer `unpack` reads
+    pub fn pack(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("instructions serialize into a growable buffer")
+    }
+}
+
+/// Creates a `CreateSubscriptionPlan` instruction, `price_feed` prices `max_amount` in USD cents
+#[allow(clippy::too_many_arguments)]
+pub fn create_subscription_plan(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    owner: &Pubkey,
+    authority: &Pubkey,
+    mint: &Pubkey,
+    payout_account: &Pubkey,
+    price_feed: Option<&Pubkey>,
+    nonce: u8,
+    subscription_timeframe: CycleInterval,
+    max_amount: u64,
+    setup_fee: u64,
+    max_cycles: u64,
+    end_timestamp: UnixTimestamp,
+    charge_upfront: bool,
+    billing_anchor_day: u8,
+) -> Instruction {
+    let data = RecurringPaymentsInstruction::CreateSubscriptionPlan {
+        nonce,
+        subscription_timeframe,
+        max_amount,
+        setup_fee,
+        max_cycles,
+        end_timestamp,
+        charge_upfront,
+        billing_anchor_day,
+    }
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*payout_account, false),
+    ];
+    accounts.extend(price_feed.map(|price_feed| AccountMeta::new_readonly(*price_feed, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `CreateSubscription` instruction. `vault_account` is the subscription vault PDA for token escrow mode,
+/// see `Processor::subscription_vault_id`, `payout_account` for plans that charge upfront or a setup fee and
+/// `price_feed` for those of them priced in USD cents.
+#[allow(clippy::too_many_arguments)]
+pub fn create_subscription(
+    program_id: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    subscriber: &Pubkey,
+    token_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    vault_account: Option<&Pubkey>,
+    payout_account: Option<&Pubkey>,
+    price_feed: Option<&Pubkey>,
+    subscription_timeframe: CycleInterval,
+    max_amount: u64,
+    prepaid_cycles: u64,
+    customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
+) -> Instruction {
+    let data = RecurringPaymentsInstruction::CreateSubscription {
+        subscription_timeframe,
+        max_amount,
+        prepaid_cycles,
+        customer_reference,
+    }
+    .pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*subscription_plan, false),
+        AccountMeta::new(*subscriber, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if let Some(vault_account) = vault_account {
+        accounts.push(AccountMeta::new(*vault_account, false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    }
+    accounts.extend(payout_account.map(|payout_account| AccountMeta::new(*payout_account, false)));
+    accounts.extend(price_feed.map(|price_feed| AccountMeta::new_readonly(*price_feed, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    }
+}
+
+/// Creates a `Claim` instruction. `claim_vault` is the mint's escrow account or settlement vault when the plan has
+/// one, `rent_or_price_feed` the rent sysvar for SOL plans or the price feed of USD priced mints.
+#[allow(clippy::too_many_arguments)]
+pub fn claim(
+    program_id: &Pubkey,
+    claimer: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    source: &Pubkey,
+    payout_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    subscriber_profile: &Pubkey,
+    claim_vault: Option<&Pubkey>,
+    rent_or_price_feed: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*claimer, true),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new(*source, false),
+        AccountMeta::new(*payout_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new(*subscriber_profile, false),
+    ];
+    accounts.extend(claim_vault.map(|claim_vault| AccountMeta::new(*claim_vault, false)));
+    accounts.extend(rent_or_price_feed.map(|rent_or_price_feed| AccountMeta::new_readonly(*rent_or_price_feed, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::Claim {}.pack(),
+    }
+}
+
+/// Creates a `TopUp` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn top_up(
+    program_id: &Pubkey,
+    source_owner: &Pubkey,
+    subscription: &Pubkey,
+    source: &Pubkey,
+    vault_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*source_owner, true),
+            AccountMeta::new_readonly(*subscription, false),
+            AccountMeta::new(*source, false),
+            AccountMeta::new(*vault_account, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: RecurringPaymentsInstruction::TopUp { amount }.pack(),
+    }
+}
+
+/// Creates a `WithdrawUnused` instruction, pass `native` for SOL plans to include the rent sysvar
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_unused(
+    program_id: &Pubkey,
+    subscriber: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    vault_account: &Pubkey,
+    destination: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    native: bool,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*subscriber, true),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*subscription_plan, false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new(*vault_account, false),
+        AccountMeta::new(*destination, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if native {
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::WithdrawUnused { amount }.pack(),
+    }
+}
+
+/// Creates an `AddAcceptedMint` instruction, `price_feed` prices `max_amount` in USD cents
+#[allow(clippy::too_many_arguments)]
+pub fn add_accepted_mint(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    payout_account: &Pubkey,
+    price_feed: Option<&Pubkey>,
+    max_amount: u64,
+    setup_fee: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*payout_account, false),
+    ];
+    accounts.extend(price_feed.map(|price_feed| AccountMeta::new_readonly(*price_feed, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::AddAcceptedMint { max_amount, setup_fee }.pack(),
+    }
+}
+
+/// Creates a `CloseSubscription` instruction, `vault_account` is for token escrow mode
+pub fn close_subscription(
+    program_id: &Pubkey,
+    subscription: &Pubkey,
+    subscriber: &Pubkey,
+    vault_account: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new(*subscriber, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    accounts.extend(vault_account.map(|vault_account| AccountMeta::new_readonly(*vault_account, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::CloseSubscription {}.pack(),
+    }
+}
+
+/// Creates a `SetSpendingCap` instruction
+pub fn set_spending_cap(
+    program_id: &Pubkey,
+    subscriber_profile: &Pubkey,
+    subscriber: &Pubkey,
+    mint: &Pubkey,
+    monthly_cap: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*subscriber_profile, false),
+            AccountMeta::new(*subscriber, true),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: RecurringPaymentsInstruction::SetSpendingCap { monthly_cap }.pack(),
+    }
+}
+
+/// Creates a `Refund` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn refund(
+    program_id: &Pubkey,
+    payout_authority: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    payout_account: &Pubkey,
+    destination: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+    restore_allowance: bool,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payout_authority, true),
+            AccountMeta::new(*subscription, false),
+            AccountMeta::new_readonly(*subscription_plan, false),
+            AccountMeta::new(*payout_account, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: RecurringPaymentsInstruction::Refund {
+            amount,
+            restore_allowance,
+        }
+        .pack(),
+    }
+}
+
+/// Creates a `SetDisputeSettings` instruction. `escrow_accounts` are the escrow account PDA, mint and token program
+/// of each of the plan's non-SOL mints in order, see `Processor::plan_vault_id`
+pub fn set_dispute_settings(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    owner: &Pubkey,
+    arbiter: &Pubkey,
+    escrow_accounts: &[(Pubkey, Pubkey, Pubkey)],
+    dispute_window: i64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new(*owner, true),
+        AccountMeta::new_readonly(*arbiter, false),
+    ];
+    if dispute_window > 0 {
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        for (escrow_account, mint, token_program) in escrow_accounts {
+            accounts.push(AccountMeta::new(*escrow_account, false));
+            accounts.push(AccountMeta::new_readonly(*mint, false));
+            accounts.push(AccountMeta::new_readonly(*token_program, false));
+        }
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::SetDisputeSettings { dispute_window }.pack(),
+    }
+}
+
+/// Creates an `OpenDispute` instruction
+pub fn open_dispute(
+    program_id: &Pubkey,
+    subscriber: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*subscriber, true),
+            AccountMeta::new(*subscription, false),
+            AccountMeta::new_readonly(*subscription_plan, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: RecurringPaymentsInstruction::OpenDispute {}.pack(),
+    }
+}
+
+/// Creates a `ResolveDispute` instruction, pass `native` for SOL plans to include the rent sysvar
+#[allow(clippy::too_many_arguments)]
+pub fn resolve_dispute(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    escrow_account: &Pubkey,
+    payout_account: &Pubkey,
+    destination: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    native: bool,
+    refund_amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*arbiter, true),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*subscription_plan, false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new(*payout_account, false),
+        AccountMeta::new(*destination, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+    ];
+    if native {
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::ResolveDispute { refund_amount }.pack(),
+    }
+}
+
+/// Creates a `ReleaseEscrow` instruction, pass `native` for SOL plans to include the rent sysvar
+#[allow(clippy::too_many_arguments)]
+pub fn release_escrow(
+    program_id: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    escrow_account: &Pubkey,
+    payout_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    native: bool,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*subscription_plan, false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new(*escrow_account, false),
+        AccountMeta::new(*payout_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if native {
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::ReleaseEscrow {}.pack(),
+    }
+}
+
+/// Creates a `SetSettlementSchedule` instruction. `settlement_vaults` are the settlement vault PDA, mint and token
+/// program of each of the plan's non-SOL mints in order, see `Processor::plan_vault_id`
+pub fn set_settlement_schedule(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    owner: &Pubkey,
+    settlement_vaults: &[(Pubkey, Pubkey, Pubkey)],
+    settlement_interval: i64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new(*owner, true),
+    ];
+    if settlement_interval > 0 {
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        for (settlement_vault, mint, token_program) in settlement_vaults {
+            accounts.push(AccountMeta::new(*settlement_vault, false));
+            accounts.push(AccountMeta::new_readonly(*mint, false));
+            accounts.push(AccountMeta::new_readonly(*token_program, false));
         }
     }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::SetSettlementSchedule { settlement_interval }.pack(),
+    }
+}
+
+/// Creates a `Settle` instruction
+pub fn settle(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    settlement_vault: &Pubkey,
+    payout_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*subscription_plan, false),
+            AccountMeta::new_readonly(*authority, false),
+            AccountMeta::new(*settlement_vault, false),
+            AccountMeta::new(*payout_account, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+        ],
+        data: RecurringPaymentsInstruction::Settle {}.pack(),
+    }
+}
+
+/// Creates a `ClaimBatch` instruction. `claims` holds a (subscription, source, subscriber profile) triple per
+/// subscription, the optional accounts are the same as for `claim`.
+#[allow(clippy::too_many_arguments)]
+pub fn claim_batch(
+    program_id: &Pubkey,
+    claimer: &Pubkey,
+    subscription_plan: &Pubkey,
+    authority: &Pubkey,
+    payout_account: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    claim_vault: Option<&Pubkey>,
+    rent_or_price_feed: Option<&Pubkey>,
+    claims: &[(Pubkey, Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*claimer, true),
+        AccountMeta::new(*subscription_plan, false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new(*payout_account, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    accounts.extend(claim_vault.map(|claim_vault| AccountMeta::new(*claim_vault, false)));
+    accounts.extend(rent_or_price_feed.map(|rent_or_price_feed| AccountMeta::new_readonly(*rent_or_price_feed, false)));
+    for (subscription, source, subscriber_profile) in claims {
+        accounts.push(AccountMeta::new(*subscription, false));
+        accounts.push(AccountMeta::new(*source, false));
+        accounts.push(AccountMeta::new(*subscriber_profile, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: RecurringPaymentsInstruction::ClaimBatch {}.pack(),
+    }
+}
+
+/// Creates a `MigrateAccount` instruction for a subscription plan in the legacy layout
+pub fn migrate_subscription_plan(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    subscription_plan: &Pubkey,
+    payout_account: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*subscription_plan, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*payout_account, false),
+            AccountMeta::new_readonly(*mint, false),
+        ],
+        data: RecurringPaymentsInstruction::MigrateAccount {}.pack(),
+    }
+}
+
+/// Creates a `MigrateAccount` instruction for a subscription in the legacy layout
+pub fn migrate_subscription(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    subscription: &Pubkey,
+    subscription_plan: &Pubkey,
+    token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*subscription, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*subscription_plan, false),
+            AccountMeta::new_readonly(*token_account, false),
+        ],
+        data: RecurringPaymentsInstruction::MigrateAccount {}.pack(),
+    }
+}
+
+/// Creates a `MigrateAccount` instruction for an account that needs no accounts beyond itself, any plan or
+/// subscription past version 0
+pub fn migrate_account(program_id: &Pubkey, payer: &Pubkey, account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: RecurringPaymentsInstruction::MigrateAccount {}.pack(),
+    }
+}
+
+/// Creates an `UpdatePlanMetadata` instruction
+pub fn update_plan_metadata(
+    program_id: &Pubkey,
+    subscription_plan: &Pubkey,
+    owner: &Pubkey,
+    name: &str,
+    uri: &str,
+    external_id: &str,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*subscription_plan, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: RecurringPaymentsInstruction::UpdatePlanMetadata {
+            name: name.to_string(),
+            uri: uri.to_string(),
+            external_id: external_id.to_string(),
+        }
+        .pack(),
+    }
+}
+
+/// Creates a `SetKeeperTip` instruction
+pub fn set_keeper_tip(program_id: &Pubkey, subscription_plan: &Pubkey, owner: &Pubkey, keeper_tip: u64) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*subscription_plan, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: RecurringPaymentsInstruction::SetKeeperTip { keeper_tip }.pack(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One of every instruction, in declaration order
+    fn every_instruction() -> Vec<RecurringPaymentsInstruction> {
+        use RecurringPaymentsInstruction::*;
+        vec![
+            CreateSubscriptionPlan {
+                nonce: 255,
+                subscription_timeframe: CycleInterval::CalendarMonths(1),
+                max_amount: 1_000,
+                setup_fee: 50,
+                max_cycles: 12,
+                end_timestamp: 1_700_000_000,
+                charge_upfront: true,
+                billing_anchor_day: 28,
+            },
+            CreateSubscription {
+                subscription_timeframe: CycleInterval::Weeks(2),
+                max_amount: 1_000,
+                prepaid_cycles: 3,
+                customer_reference: [7; CUSTOMER_REFERENCE_LEN],
+            },
+            Claim {},
+            TopUp { amount: 1 },
+            WithdrawUnused { amount: 2 },
+            AddAcceptedMint {
+                max_amount: 3,
+                setup_fee: 4,
+            },
+            CloseSubscription {},
+            SetSpendingCap { monthly_cap: 5 },
+            Refund {
+                amount: 6,
+                restore_allowance: true,
+            },
+            SetDisputeSettings { dispute_window: -7 },
+            OpenDispute {},
+            ResolveDispute { refund_amount: 8 },
+            ReleaseEscrow {},
+            SetSettlementSchedule { settlement_interval: 9 },
+            Settle {},
+            ClaimBatch {},
+            SetKeeperTip { keeper_tip: 10 },
+            MigrateAccount {},
+            UpdatePlanMetadata {
+                name: "Pro".to_string(),
+                uri: "https://example.com/pro.json".to_string(),
+                external_id: String::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        for (tag, instruction) in every_instruction().into_iter().enumerate() {
+            let data = instruction.pack();
+            assert_eq!(data[0] as usize, tag, "{:?}", instruction);
+            assert_eq!(RecurringPaymentsInstruction::unpack(&data), Ok(instruction));
+        }
+    }
+
+    #[test]
+    fn original_instruction_tags_are_stable() {
+        let instructions = every_instruction();
+        assert_eq!(instructions[0].pack()[0], 0);
+        assert!(matches!(
+            instructions[0],
+            RecurringPaymentsInstruction::CreateSubscriptionPlan { .. }
+        ));
+        assert_eq!(instructions[1].pack()[0], 1);
+        assert!(matches!(
+            instructions[1],
+            RecurringPaymentsInstruction::CreateSubscription { .. }
+        ));
+        assert_eq!(instructions[2].pack(), vec![2]);
+        assert!(matches!(instructions[2], RecurringPaymentsInstruction::Claim {}));
+    }
+
+    #[test]
+    fn malformed_data_is_an_invalid_instruction() {
+        let invalid = Err(RecurringPaymentsError::InvalidInstruction.into());
+        assert_eq!(RecurringPaymentsInstruction::unpack(&[]), invalid);
+        assert_eq!(
+            RecurringPaymentsInstruction::unpack(&[every_instruction().len() as u8]),
+            invalid
+        );
+
+        let mut data = RecurringPaymentsInstruction::TopUp { amount: 1 }.pack();
+        data.pop();
+        assert_eq!(RecurringPaymentsInstruction::unpack(&data), invalid);
+        data.extend_from_slice(&[1, 0]);
+        assert_eq!(RecurringPaymentsInstruction::unpack(&data), invalid);
+    }
 }
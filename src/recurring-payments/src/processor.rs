@@ -1,19 +1,36 @@
+use crate::constants::{
+    CUSTOMER_REFERENCE_LEN, LEGACY_SUBSCRIPTION_PLAN_SIZE, LEGACY_SUBSCRIPTION_SIZE, MAX_ACCEPTED_MINTS,
+    PLAN_ESCROW_SEED, SETTLEMENT_VAULT_SEED, SUBSCRIBER_PROFILE_SEED, SUBSCRIPTION_PLAN_RESERVED_SIZE,
+    SUBSCRIPTION_PLAN_V1_SIZE, SUBSCRIPTION_PLAN_V2_SIZE, SUBSCRIPTION_PLAN_VERSION, SUBSCRIPTION_RESERVED_SIZE,
+    SUBSCRIPTION_V1_SIZE, SUBSCRIPTION_VAULT_SEED, SUBSCRIPTION_VERSION,
+};
 use crate::error::RecurringPaymentsError;
 use crate::instruction::RecurringPaymentsInstruction;
-use crate::state::{Subscription, SubscriptionPlan};
+use crate::state::{
+    AcceptedMint, AccountType, CycleInterval, LegacySubscription, LegacySubscriptionPlan, PaymentMode, PlanMetadata,
+    PriceFeed, PricingMode, SubscriberProfile, Subscription, SubscriptionPlan, SubscriptionPlanV1, SubscriptionPlanV2,
+    SubscriptionV1,
+};
 use num_traits::FromPrimitive;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
-    clock::{Clock, UnixTimestamp},
+    clock::{Clock, Epoch, UnixTimestamp},
     decode_error::DecodeError,
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::{PrintProgramError, ProgramError},
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, system_program,
     sysvar::Sysvar,
 };
+use spl_token_2022::{
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::{Account, Mint},
+};
 
 /// Program state handler.
 pub struct Processor {}
@@ -28,22 +45,90 @@ impl Processor {
                 nonce,
                 subscription_timeframe,
                 max_amount,
-            } => {
-                Self::process_create_subscription_plan(accounts, nonce, subscription_timeframe, max_amount, program_id)
-            }
+                setup_fee,
+                max_cycles,
+                end_timestamp,
+                charge_upfront,
+                billing_anchor_day,
+            } => Self::process_create_subscription_plan(
+                accounts,
+                nonce,
+                subscription_timeframe,
+                max_amount,
+                setup_fee,
+                max_cycles,
+                end_timestamp,
+                charge_upfront,
+                billing_anchor_day,
+                program_id,
+            ),
             RecurringPaymentsInstruction::CreateSubscription {
                 subscription_timeframe,
                 max_amount,
-            } => Self::process_create_subscription(accounts, subscription_timeframe, max_amount, program_id),
+                prepaid_cycles,
+                customer_reference,
+            } => Self::process_create_subscription(
+                accounts,
+                subscription_timeframe,
+                max_amount,
+                prepaid_cycles,
+                customer_reference,
+                program_id,
+            ),
             RecurringPaymentsInstruction::Claim {} => Self::process_claim(accounts, program_id),
+            RecurringPaymentsInstruction::TopUp { amount } => Self::process_top_up(accounts, amount, program_id),
+            RecurringPaymentsInstruction::WithdrawUnused { amount } => {
+                Self::process_withdraw_unused(accounts, amount, program_id)
+            }
+            RecurringPaymentsInstruction::AddAcceptedMint { max_amount, setup_fee } => {
+                Self::process_add_accepted_mint(accounts, max_amount, setup_fee, program_id)
+            }
+            RecurringPaymentsInstruction::CloseSubscription {} => {
+                Self::process_close_subscription(accounts, program_id)
+            }
+            RecurringPaymentsInstruction::SetSpendingCap { monthly_cap } => {
+                Self::process_set_spending_cap(accounts, monthly_cap, program_id)
+            }
+            RecurringPaymentsInstruction::Refund {
+                amount,
+                restore_allowance,
+            } => Self::process_refund(accounts, amount, restore_allowance, program_id),
+            RecurringPaymentsInstruction::SetDisputeSettings { dispute_window } => {
+                Self::process_set_dispute_settings(accounts, dispute_window, program_id)
+            }
+            RecurringPaymentsInstruction::OpenDispute {} => Self::process_open_dispute(accounts, program_id),
+            RecurringPaymentsInstruction::ResolveDispute { refund_amount } => {
+                Self::process_resolve_dispute(accounts, refund_amount, program_id)
+            }
+            RecurringPaymentsInstruction::ReleaseEscrow {} => Self::process_release_escrow(accounts, program_id),
+            RecurringPaymentsInstruction::SetSettlementSchedule { settlement_interval } => {
+                Self::process_set_settlement_schedule(accounts, settlement_interval, program_id)
+            }
+            RecurringPaymentsInstruction::Settle {} => Self::process_settle(accounts, program_id),
+            RecurringPaymentsInstruction::ClaimBatch {} => Self::process_claim_batch(accounts, program_id),
+            RecurringPaymentsInstruction::SetKeeperTip { keeper_tip } => {
+                Self::process_set_keeper_tip(accounts, keeper_tip, program_id)
+            }
+            RecurringPaymentsInstruction::MigrateAccount {} => Self::process_migrate_account(accounts, program_id),
+            RecurringPaymentsInstruction::UpdatePlanMetadata {
+                name,
+                uri,
+                external_id,
+            } => Self::process_update_plan_metadata(accounts, &name, &uri, &external_id, program_id),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_create_subscription_plan(
         accounts: &[AccountInfo],
         nonce: u8,
-        subscription_timeframe: u64,
+        subscription_timeframe: CycleInterval,
         max_amount: u64,
+        setup_fee: u64,
+        max_cycles: u64,
+        end_timestamp: UnixTimestamp,
+        charge_upfront: bool,
+        billing_anchor_day: u8,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -52,168 +137,2383 @@ impl Processor {
         let owner_info = next_account_info(account_info_iter)?;
         let authority_info = next_account_info(account_info_iter)?;
         let token_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let price_feed_info = account_info_iter.next();
+
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
 
         if *authority_info.key != Self::authority_id(program_id, subscription_plan_account_info.key, nonce)? {
             return Err(RecurringPaymentsError::InvalidProgramAddress.into());
         }
 
+        subscription_timeframe.check_duration()?;
+        if billing_anchor_day != 0 {
+            subscription_timeframe.check_anchor_day(billing_anchor_day)?;
+        }
+
+        let mut price_feed_owner = Pubkey::default();
+        let accepted_mint = Self::accepted_mint_from_accounts(
+            token_info,
+            payout_account_info,
+            price_feed_info,
+            &mut price_feed_owner,
+            max_amount,
+            setup_fee,
+        )?;
+
         pack_subscription_plan(
             subscription_plan_account_info,
             nonce,
             *owner_info.key,
             *authority_info.key,
-            *token_info.key,
             subscription_timeframe,
-            max_amount,
+            max_cycles,
+            end_timestamp,
+            charge_upfront,
+            billing_anchor_day,
+            price_feed_owner,
+            accepted_mint,
         )?;
 
         Ok(())
     }
 
+    fn process_add_accepted_mint(
+        accounts: &[AccountInfo],
+        max_amount: u64,
+        setup_fee: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let token_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let price_feed_info = account_info_iter.next();
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if !owner_info.is_signer || *owner_info.key != subscription_plan.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if subscription_plan.accepted_mint(token_info.key).is_ok() {
+            return Err(RecurringPaymentsError::MintAlreadyAccepted.into());
+        }
+
+        if subscription_plan.accepted_mints.len() >= MAX_ACCEPTED_MINTS {
+            return Err(RecurringPaymentsError::TooManyAcceptedMints.into());
+        }
+
+        let accepted_mint = Self::accepted_mint_from_accounts(
+            token_info,
+            payout_account_info,
+            price_feed_info,
+            &mut subscription_plan.price_feed_owner,
+            max_amount,
+            setup_fee,
+        )?;
+        subscription_plan.accepted_mints.push(accepted_mint);
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
     fn process_create_subscription(
         accounts: &[AccountInfo],
-        subscription_timeframe: u64,
+        subscription_timeframe: CycleInterval,
         max_amount: u64,
-        _program_id: &Pubkey,
+        prepaid_cycles: u64,
+        customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
+        program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
         let subscription_account_info = next_account_info(account_info_iter)?;
         let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let subscriber_info = next_account_info(account_info_iter)?;
         let token_account_info = next_account_info(account_info_iter)?;
-        let _token_program_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
         let clock_sysvar_info = next_account_info(account_info_iter)?;
-        let fee_account_info = next_account_info(account_info_iter)?;
         let clock = &Clock::from_account_info(clock_sysvar_info)?;
         let cycle_start = clock.unix_timestamp;
 
-        let subscription_plan = SubscriptionPlan::unpack(&subscription_plan_account_info.data.borrow())?;
+        if !subscriber_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
 
-        // TODO: 8?
-        if subscription_plan.subscription_timeframe >> 8 != subscription_timeframe {
+        if subscription_plan.subscription_timeframe != subscription_timeframe {
             return Err(RecurringPaymentsError::InvalidSubscriptionTimeframe.into());
         }
 
-        // TODO: 8?
-        if subscription_plan.max_amount >> 8 != max_amount {
+        // The subscriber picks which of the plan's mints to pay in by passing it
+        let accepted_mint = subscription_plan.accepted_mint(mint_info.key)?;
+
+        if accepted_mint.max_amount != max_amount {
             return Err(RecurringPaymentsError::InvalidMaxAmount.into());
         }
 
+        if subscription_plan.end_timestamp != 0 && cycle_start >= subscription_plan.end_timestamp {
+            return Err(RecurringPaymentsError::TermCompleted.into());
+        }
+
+        // A USD price turns into a different token amount every cycle, which a fixed deposit cannot cover
+        if accepted_mint.pricing_mode == PricingMode::UsdCents && prepaid_cycles != 0 {
+            return Err(RecurringPaymentsError::OraclePricingRequiresDelegate.into());
+        }
+
+        let (payment_mode, vault_account) = if accepted_mint.is_native() {
+            if prepaid_cycles == 0 {
+                return Err(RecurringPaymentsError::NativePlanRequiresEscrow.into());
+            }
+            if *token_program_info.key != system_program::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            // Refunds and dispute resolutions pay the recorded wallet, it has to be the subscriber's own
+            if token_account_info.key != subscriber_info.key {
+                return Err(RecurringPaymentsError::InvalidSubscriber.into());
+            }
+
+            let deposit = prepaid_cycles
+                .checked_mul(max_amount)
+                .ok_or(RecurringPaymentsError::Overflow)?;
+            invoke(
+                &system_instruction::transfer(subscriber_info.key, subscription_account_info.key, deposit),
+                &[
+                    subscriber_info.clone(),
+                    subscription_account_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+
+            (PaymentMode::NativeEscrow, *subscription_account_info.key)
+        } else {
+            if *token_program_info.key != accepted_mint.token_program {
+                return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+            }
+
+            let token_account = Self::unpack_token_account(token_account_info, token_program_info.key)?;
+            if token_account.mint != accepted_mint.mint {
+                return Err(RecurringPaymentsError::InvalidMint.into());
+            }
+            // Claims are drawn from the account the subscription records, it has to be the subscriber's own
+            if token_account.owner != *subscriber_info.key {
+                return Err(RecurringPaymentsError::InvalidSubscriber.into());
+            }
+
+            if prepaid_cycles == 0 {
+                (PaymentMode::Delegate, Pubkey::default())
+            } else {
+                let vault_account_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let (vault_account, bump) = Self::subscription_vault_id(
+                    program_id,
+                    subscription_plan_account_info.key,
+                    subscription_account_info.key,
+                );
+                if *vault_account_info.key != vault_account {
+                    return Err(RecurringPaymentsError::InvalidVaultAccount.into());
+                }
+                Self::create_token_vault(
+                    vault_account_info,
+                    &[
+                        SUBSCRIPTION_VAULT_SEED,
+                        &subscription_plan_account_info.key.to_bytes(),
+                        &subscription_account_info.key.to_bytes(),
+                        &[bump],
+                    ],
+                    &subscription_plan.authority,
+                    subscriber_info,
+                    mint_info,
+                    token_program_info,
+                    system_program_info,
+                )?;
+
+                // The subscriber pays the Token-2022 transfer fee on top, so every prepaid cycle can be claimed
+                let deposit = prepaid_cycles
+                    .checked_mul(max_amount)
+                    .ok_or(RecurringPaymentsError::Overflow)?;
+                Self::check_mint(mint_info, accepted_mint)?;
+                Self::token_transfer_by_owner(
+                    token_program_info.clone(),
+                    token_account_info.clone(),
+                    mint_info.clone(),
+                    vault_account_info.clone(),
+                    subscriber_info.clone(),
+                    Self::gross_up_transfer_fee(mint_info, clock.epoch, deposit)?,
+                    accepted_mint.decimals,
+                )?;
+                let vault_account = Self::unpack_token_account(vault_account_info, token_program_info.key)?;
+                if vault_account.amount < deposit {
+                    return Err(RecurringPaymentsError::DepositShortAfterTransferFee.into());
+                }
+
+                (PaymentMode::Escrow, *vault_account_info.key)
+            }
+        };
+
+        // Billing anchored to a day of the month starts with a prorated period up to the first anchor
+        let (cycle_anchor, first_period_amount) = if subscription_plan.billing_anchor_day == 0 {
+            (cycle_start, max_amount)
+        } else {
+            let timeframe = &subscription_plan.subscription_timeframe;
+            let cycle_anchor = timeframe.next_anchor_day(cycle_start, subscription_plan.billing_anchor_day)?;
+            (cycle_anchor, timeframe.prorate(max_amount, cycle_anchor, cycle_start)?)
+        };
+
+        // The setup fee and an upfront first period go out in a single transfer
+        let upfront_amount = if subscription_plan.charge_upfront {
+            first_period_amount
+        } else {
+            0
+        };
+        let initial_charge = accepted_mint
+            .setup_fee
+            .checked_add(upfront_amount)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+
+        if initial_charge > 0 {
+            let payout_account_info = next_account_info(account_info_iter)?;
+            if *payout_account_info.key != accepted_mint.payout_account {
+                return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+            }
 
-        // TODO: Checks
+            if accepted_mint.is_native() {
+                invoke(
+                    &system_instruction::transfer(subscriber_info.key, payout_account_info.key, initial_charge),
+                    &[
+                        subscriber_info.clone(),
+                        payout_account_info.clone(),
+                        token_program_info.clone(),
+                    ],
+                )?;
+            } else {
+                Self::check_mint(mint_info, accepted_mint)?;
+                let amount = match accepted_mint.pricing_mode {
+                    PricingMode::Fixed => initial_charge,
+                    PricingMode::UsdCents => Self::usd_cents_to_token_amount(
+                        next_account_info(account_info_iter)?,
+                        &subscription_plan,
+                        accepted_mint,
+                        initial_charge,
+                        cycle_start,
+                    )?,
+                };
+                Self::token_transfer_by_owner(
+                    token_program_info.clone(),
+                    token_account_info.clone(),
+                    mint_info.clone(),
+                    payout_account_info.clone(),
+                    subscriber_info.clone(),
+                    amount,
+                    accepted_mint.decimals,
+                )?;
+            }
+            msg!(
+                "Charged a setup fee of {} and {} upfront",
+                accepted_mint.setup_fee,
+                upfront_amount
+            );
+        }
 
+        // The first period's cap is what is left of max_amount after proration and the upfront charge
+        let withdrawn_amount = if subscription_plan.charge_upfront {
+            max_amount
+        } else {
+            max_amount - first_period_amount
+        };
 
         pack_subscription(
             subscription_account_info,
             *subscription_plan_account_info.key,
             *token_account_info.key,
-            *fee_account_info.key,
-            subscription_plan.owner,
+            *subscriber_info.key,
+            cycle_anchor,
             cycle_start,
-            subscription_timeframe,
-            max_amount,
+            withdrawn_amount,
+            &subscription_plan,
+            accepted_mint,
+            payment_mode,
+            vault_account,
+            customer_reference,
         )?;
 
         Ok(())
     }
 
-    fn process_claim(_accounts: &[AccountInfo], _program_id: &Pubkey) -> ProgramResult {
-        
+    fn process_claim(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let claimer_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+        let subscriber_profile_info = next_account_info(account_info_iter)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let (accepted_mint, keeper_tip) = Self::check_claim_accounts(
+            &subscription_plan,
+            claimer_info,
+            authority_info,
+            payout_account_info,
+            mint_info,
+            token_program_info,
+        )?;
+        let destination_info =
+            Self::claim_destination(&subscription_plan, accepted_mint, payout_account_info, account_info_iter)?;
+        let rent_or_price_feed_info =
+            if accepted_mint.is_native() || accepted_mint.pricing_mode == PricingMode::UsdCents {
+                Some(next_account_info(account_info_iter)?)
+            } else {
+                None
+            };
+
+        Self::claim_subscription(
+            subscription_plan_account_info,
+            &subscription_plan,
+            accepted_mint,
+            subscription_account_info,
+            source_info,
+            subscriber_profile_info,
+            authority_info,
+            destination_info,
+            payout_account_info,
+            mint_info,
+            token_program_info,
+            rent_or_price_feed_info,
+            clock,
+            program_id,
+        )?;
+
+        Self::pay_keeper_tip(subscription_plan_account_info, claimer_info, keeper_tip)
+    }
+
+    fn process_claim_batch(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let claimer_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let (accepted_mint, keeper_tip) = Self::check_claim_accounts(
+            &subscription_plan,
+            claimer_info,
+            authority_info,
+            payout_account_info,
+            mint_info,
+            token_program_info,
+        )?;
+        let destination_info =
+            Self::claim_destination(&subscription_plan, accepted_mint, payout_account_info, account_info_iter)?;
+        let rent_or_price_feed_info =
+            if accepted_mint.is_native() || accepted_mint.pricing_mode == PricingMode::UsdCents {
+                Some(next_account_info(account_info_iter)?)
+            } else {
+                None
+            };
+
+        let items = account_info_iter.as_slice().chunks_exact(3);
+        if items.len() == 0 || !items.remainder().is_empty() {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // One byte per subscription, 1 when it was claimed, the logs say why the others were skipped
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            let (subscription_account_info, source_info, subscriber_profile_info) = (&item[0], &item[1], &item[2]);
+            // The tip is checked up front, a claim that already moved funds can't be skipped when its tip fails
+            let result = Self::check_keeper_tip(subscription_plan_account_info, keeper_tip)
+                .and_then(|()| {
+                    Self::claim_subscription(
+                        subscription_plan_account_info,
+                        &subscription_plan,
+                        accepted_mint,
+                        subscription_account_info,
+                        source_info,
+                        subscriber_profile_info,
+                        authority_info,
+                        destination_info,
+                        payout_account_info,
+                        mint_info,
+                        token_program_info,
+                        rent_or_price_feed_info,
+                        clock,
+                        program_id,
+                    )
+                })
+                .and_then(|()| Self::pay_keeper_tip(subscription_plan_account_info, claimer_info, keeper_tip));
+            if let Err(error) = &result {
+                msg!("Skipped subscription {}", subscription_account_info.key);
+                error.print::<RecurringPaymentsError>();
+            }
+            results.push(result.is_ok() as u8);
+        }
+        msg!(
+            "Claimed {} of {} subscriptions",
+            results.iter().filter(|claimed| **claimed == 1).count(),
+            results.len()
+        );
+        set_return_data(&results);
 
         Ok(())
     }
 
-    /// Calculates the authority id by generating a program address.
-    pub fn authority_id(program_id: &Pubkey, my_info: &Pubkey, nonce: u8) -> Result<Pubkey, RecurringPaymentsError> {
-        Pubkey::create_program_address(&[&my_info.to_bytes()[..32], &[nonce]], program_id)
-            .or(Err(RecurringPaymentsError::InvalidProgramAddress))
+    fn process_top_up(accounts: &[AccountInfo], amount: u64, program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let source_owner_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let vault_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        Self::check_subscription_vault(vault_account_info, subscription_account_info.key, &subscription, program_id)?;
+
+        match subscription.payment_mode {
+            PaymentMode::Delegate => return Err(RecurringPaymentsError::InvalidPaymentMode.into()),
+            PaymentMode::NativeEscrow => {
+                if *token_program_info.key != system_program::id() {
+                    return Err(ProgramError::IncorrectProgramId);
+                }
+                return invoke(
+                    &system_instruction::transfer(source_info.key, vault_account_info.key, amount),
+                    &[
+                        source_info.clone(),
+                        vault_account_info.clone(),
+                        token_program_info.clone(),
+                    ],
+                );
+            }
+            PaymentMode::Escrow => {}
+        }
+
+        // The vault was checked against the plan token on creation, so its mint and owner are authoritative
+        let vault_account = Self::unpack_token_account(vault_account_info, token_program_info.key)?;
+        if *mint_info.key != vault_account.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+
+        // The source pays the Token-2022 transfer fee on top, so the vault receives `amount` in full
+        let mint = Self::unpack_mint(mint_info, token_program_info.key)?;
+        Self::token_transfer_by_owner(
+            token_program_info.clone(),
+            source_info.clone(),
+            mint_info.clone(),
+            vault_account_info.clone(),
+            source_owner_info.clone(),
+            Self::gross_up_transfer_fee(mint_info, Clock::get()?.epoch, amount)?,
+            mint.decimals,
+        )?;
+        let deposited = Self::unpack_token_account(vault_account_info, token_program_info.key)?
+            .amount
+            .checked_sub(vault_account.amount)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        if deposited < amount {
+            return Err(RecurringPaymentsError::DepositShortAfterTransferFee.into());
+        }
+        Ok(())
     }
 
-    
+    fn process_withdraw_unused(accounts: &[AccountInfo], amount: u64, program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscriber_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let vault_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
 
-    /// Unpacks a spl_token `Account`.
-    pub fn unpack_token_account(
-        account_info: &AccountInfo,
-        token_program_id: &Pubkey,
-    ) -> Result<spl_token::state::Account, RecurringPaymentsError> {
-        if account_info.owner != token_program_id {
-            Err(RecurringPaymentsError::IncorrectTokenProgramId)
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if subscription.subscription_plan_account != *subscription_plan_account_info.key {
+            return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+        }
+
+        if !subscriber_info.is_signer || *subscriber_info.key != subscription.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        Self::check_subscription_vault(vault_account_info, subscription_account_info.key, &subscription, program_id)?;
+
+        if *authority_info.key != subscription_plan.authority {
+            return Err(RecurringPaymentsError::InvalidProgramAddress.into());
+        }
+
+        if *mint_info.key != subscription.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+        let accepted_mint = subscription_plan.accepted_mint(&subscription.mint)?;
+
+        if subscription.payment_mode == PaymentMode::Escrow && *token_program_info.key != accepted_mint.token_program {
+            return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+        }
+
+        // The part of the current cycle the merchant has not claimed yet stays in the vault
+        subscription.roll_cycle(clock.unix_timestamp)?;
+        let reserved = if subscription.is_approved && !subscription.is_term_complete(clock.unix_timestamp)? {
+            subscription.claimable_amount()
         } else {
-            spl_token::state::Account::unpack(&account_info.data.borrow())
-                .map_err(|_| RecurringPaymentsError::ExpectedAccount)
+            0
+        };
+        // Lamports claimed during a dispute window stay in the subscription account until they are released
+        let reserved = if subscription.payment_mode == PaymentMode::NativeEscrow {
+            reserved.saturating_add(subscription.escrowed_amount)
+        } else {
+            reserved
+        };
+
+        if subscription.payment_mode == PaymentMode::NativeEscrow {
+            let rent = &Rent::from_account_info(next_account_info(account_info_iter)?)?;
+            let balance = Self::lamport_balance(vault_account_info, rent);
+            if balance.saturating_sub(reserved) < amount {
+                return Err(RecurringPaymentsError::InsufficientVaultFunds.into());
+            }
+
+            Self::lamport_transfer(vault_account_info, destination_info, rent, amount)?;
+        } else {
+            let vault_account = Self::unpack_token_account(vault_account_info, token_program_info.key)?;
+            if vault_account.amount.saturating_sub(reserved) < amount {
+                return Err(RecurringPaymentsError::InsufficientVaultFunds.into());
+            }
+
+            Self::check_mint(mint_info, accepted_mint)?;
+            Self::token_transfer(
+                subscription_plan_account_info.key,
+                token_program_info.clone(),
+                vault_account_info.clone(),
+                mint_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                subscription_plan.nonce,
+                amount,
+                accepted_mint.decimals,
+            )?;
         }
+
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
     }
-}
 
-impl PrintProgramError for RecurringPaymentsError {
-    fn print<E>(&self)
-    where
-        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
-    {
-        match self {
-            RecurringPaymentsError::InvalidInstruction => msg!("Error: Invalid instruction"),
-            RecurringPaymentsError::InvalidMaxAmount => msg!("Error: Invalid max amount"),
-            RecurringPaymentsError::InvalidSubscriptionTimeframe => msg!("Error: Invalid subscription timeframe"),
-            RecurringPaymentsError::InvalidProgramAddress => {
-                msg!("Error: Invalid program address generated from nonce and key")
+    fn process_close_subscription(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscriber_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if *subscriber_info.key != subscription.owner {
+            return Err(RecurringPaymentsError::InvalidSubscriber.into());
+        }
+
+        if !subscription.is_term_complete(clock.unix_timestamp)? {
+            return Err(RecurringPaymentsError::TermNotComplete.into());
+        }
+
+        if subscription.escrowed_amount != 0 {
+            return Err(RecurringPaymentsError::EscrowNotReleased.into());
+        }
+
+        // Closing would strand the vault, which only the subscription can withdraw from
+        if subscription.payment_mode == PaymentMode::Escrow {
+            let vault_account_info = next_account_info(account_info_iter)?;
+            if *vault_account_info.key != subscription.vault_account {
+                return Err(RecurringPaymentsError::InvalidVaultAccount.into());
             }
-            RecurringPaymentsError::IncorrectTokenProgramId => {
-                msg!("Error: The provided token program does not match the expected token program")
+            Self::check_token_program(vault_account_info.owner)?;
+            let vault_account = Self::unpack_token_account(vault_account_info, vault_account_info.owner)?;
+            if vault_account.amount != 0 {
+                return Err(RecurringPaymentsError::VaultNotEmpty.into());
             }
-            RecurringPaymentsError::ExpectedAccount => msg!("Error: Deserialized account is not an SPL Token account"),
+        }
+
+        let lamports = subscription_account_info.lamports();
+        **subscription_account_info.lamports.borrow_mut() = 0;
+        **subscriber_info.lamports.borrow_mut() = subscriber_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        subscription_account_info.data.borrow_mut().fill(0);
+
+        Ok(())
+    }
+
+    fn process_set_spending_cap(accounts: &[AccountInfo], monthly_cap: u64, program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscriber_profile_info = next_account_info(account_info_iter)?;
+        let subscriber_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+        let rent_sysvar_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_sysvar_info)?;
+
+        if !subscriber_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let (subscriber_profile_key, bump) = Self::subscriber_profile_id(program_id, subscriber_info.key);
+        if *subscriber_profile_info.key != subscriber_profile_key {
+            return Err(RecurringPaymentsError::InvalidSubscriberProfile.into());
+        }
+
+        if subscriber_profile_info.owner != program_id {
+            Self::create_pda_account(
+                subscriber_info,
+                subscriber_profile_info,
+                system_program_info,
+                rent,
+                SubscriberProfile::LEN,
+                program_id,
+                &[SUBSCRIBER_PROFILE_SEED, &subscriber_info.key.to_bytes(), &[bump]],
+            )?;
+        }
+
+        let mut subscriber_profile = SubscriberProfile::unpack_unchecked(&subscriber_profile_info.data.borrow())?;
+        if !subscriber_profile.is_initialized() {
+            subscriber_profile.account_type = AccountType::SubscriberProfile;
+            subscriber_profile.is_initialized = true;
+            subscriber_profile.bump = bump;
+            subscriber_profile.owner = *subscriber_info.key;
+            subscriber_profile.period_anchor = clock.unix_timestamp;
+            subscriber_profile.period_start = clock.unix_timestamp;
+        } else if subscriber_profile.mint != *mint_info.key {
+            subscriber_profile.roll_period(clock.unix_timestamp)?;
+            subscriber_profile.spent_this_period = 0;
+        }
+        subscriber_profile.mint = *mint_info.key;
+        subscriber_profile.monthly_cap = monthly_cap;
+
+        SubscriberProfile::pack(subscriber_profile, &mut subscriber_profile_info.data.borrow_mut())
+    }
+
+    fn process_refund(
+        accounts: &[AccountInfo],
+        amount: u64,
+        restore_allowance: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let refund_authority_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if subscription.subscription_plan_account != *subscription_plan_account_info.key {
+            return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+        }
+
+        if *mint_info.key != subscription.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+        let accepted_mint = subscription_plan.accepted_mint(&subscription.mint)?;
+
+        if *payout_account_info.key != accepted_mint.payout_account {
+            return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+        }
+
+        if *destination_info.key != subscription.token_account {
+            return Err(RecurringPaymentsError::InvalidDestinationAccount.into());
+        }
+
+        if restore_allowance && accepted_mint.pricing_mode != PricingMode::Fixed {
+            return Err(RecurringPaymentsError::CannotRestoreUsdAllowance.into());
+        }
+
+        // The token or system program still requires the signer to be able to move the payout funds
+        if accepted_mint.is_native() {
+            if !refund_authority_info.is_signer || *refund_authority_info.key != *payout_account_info.key {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if *token_program_info.key != system_program::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            invoke(
+                &system_instruction::transfer(payout_account_info.key, destination_info.key, amount),
+                &[
+                    payout_account_info.clone(),
+                    destination_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        } else {
+            if *token_program_info.key != accepted_mint.token_program {
+                return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+            }
+            let payout_account = Self::unpack_token_account(payout_account_info, token_program_info.key)?;
+            if !refund_authority_info.is_signer
+                || (*refund_authority_info.key != payout_account.owner
+                    && payout_account.delegate != COption::Some(*refund_authority_info.key))
+            {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            Self::check_mint(mint_info, accepted_mint)?;
+            Self::token_transfer_by_owner(
+                token_program_info.clone(),
+                payout_account_info.clone(),
+                mint_info.clone(),
+                destination_info.clone(),
+                refund_authority_info.clone(),
+                amount,
+                accepted_mint.decimals,
+            )?;
+        }
+
+        subscription.refunded_amount = subscription
+            .refunded_amount
+            .checked_add(amount)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        if restore_allowance {
+            subscription.roll_cycle(clock.unix_timestamp)?;
+            subscription.withdrawn_amount = subscription.withdrawn_amount.saturating_sub(amount);
+        }
+        msg!("Refunded {}, {} in total", amount, subscription.refunded_amount);
+
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
+    }
+
+    fn process_set_dispute_settings(
+        accounts: &[AccountInfo],
+        dispute_window: i64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let arbiter_info = next_account_info(account_info_iter)?;
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if !owner_info.is_signer || *owner_info.key != subscription_plan.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if dispute_window < 0 {
+            return Err(RecurringPaymentsError::InvalidDisputeWindow.into());
+        }
+
+        if dispute_window > 0 {
+            Self::create_plan_vaults(
+                subscription_plan_account_info.key,
+                &mut subscription_plan,
+                PLAN_ESCROW_SEED,
+                owner_info,
+                account_info_iter,
+                program_id,
+            )?;
+        }
+
+        subscription_plan.dispute_window = dispute_window;
+        subscription_plan.arbiter = *arbiter_info.key;
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
+    fn process_open_dispute(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscriber_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if subscription.subscription_plan_account != *subscription_plan_account_info.key {
+            return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+        }
+
+        if !subscriber_info.is_signer || *subscriber_info.key != subscription.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if subscription_plan.arbiter == Pubkey::default() {
+            return Err(RecurringPaymentsError::InvalidArbiter.into());
+        }
+
+        if subscription.escrowed_amount == 0 || clock.unix_timestamp >= subscription.escrow_window_end() {
+            return Err(RecurringPaymentsError::DisputeWindowClosed.into());
+        }
+
+        subscription.is_disputed = true;
+        msg!("Disputed {}", subscription.escrowed_amount);
+
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
+    }
+
+    fn process_resolve_dispute(accounts: &[AccountInfo], refund_amount: u64, program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let arbiter_info = next_account_info(account_info_iter)?;
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let escrow_account_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if !arbiter_info.is_signer || *arbiter_info.key != subscription_plan.arbiter {
+            return Err(RecurringPaymentsError::InvalidArbiter.into());
+        }
+
+        if !subscription.is_disputed {
+            return Err(RecurringPaymentsError::NotDisputed.into());
+        }
+
+        if refund_amount > subscription.escrowed_amount {
+            return Err(RecurringPaymentsError::RefundExceedsEscrow.into());
+        }
+
+        if *destination_info.key != subscription.token_account {
+            return Err(RecurringPaymentsError::InvalidDestinationAccount.into());
+        }
+
+        let accepted_mint = Self::check_escrow_accounts(
+            &subscription_plan,
+            &subscription,
+            subscription_plan_account_info,
+            subscription_account_info,
+            authority_info,
+            escrow_account_info,
+            payout_account_info,
+            mint_info,
+            token_program_info,
+        )?;
+        let rent = if accepted_mint.is_native() {
+            Some(Rent::from_account_info(next_account_info(account_info_iter)?)?)
+        } else {
+            None
+        };
+
+        let payout_amount = subscription.escrowed_amount - refund_amount;
+        for (destination_info, amount) in [(destination_info, refund_amount), (payout_account_info, payout_amount)] {
+            Self::escrow_transfer(
+                subscription_plan_account_info.key,
+                &subscription_plan,
+                accepted_mint,
+                escrow_account_info,
+                destination_info,
+                authority_info,
+                mint_info,
+                token_program_info,
+                rent.as_ref(),
+                amount,
+            )?;
+        }
+        msg!("Dispute resolved, refunded {} and released {}", refund_amount, payout_amount);
+
+        subscription.refunded_amount = subscription
+            .refunded_amount
+            .checked_add(refund_amount)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        subscription.clear_escrow();
+        subscription.is_disputed = false;
+
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
+    }
+
+    fn process_release_escrow(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_account_info = next_account_info(account_info_iter)?;
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let escrow_account_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if subscription.is_disputed {
+            return Err(RecurringPaymentsError::SubscriptionDisputed.into());
+        }
+
+        if subscription.escrowed_amount == 0 {
+            return Err(RecurringPaymentsError::NothingToClaim.into());
+        }
+
+        let released = subscription.release_matured_escrow(clock.unix_timestamp);
+        if released == 0 {
+            return Err(RecurringPaymentsError::DisputeWindowOpen.into());
+        }
+
+        let accepted_mint = Self::check_escrow_accounts(
+            &subscription_plan,
+            &subscription,
+            subscription_plan_account_info,
+            subscription_account_info,
+            authority_info,
+            escrow_account_info,
+            payout_account_info,
+            mint_info,
+            token_program_info,
+        )?;
+        let rent = if accepted_mint.is_native() {
+            Some(Rent::from_account_info(next_account_info(account_info_iter)?)?)
+        } else {
+            None
+        };
+
+        // Anyone can release, the funds can only go to the payout account
+        Self::escrow_transfer(
+            subscription_plan_account_info.key,
+            &subscription_plan,
+            accepted_mint,
+            escrow_account_info,
+            payout_account_info,
+            authority_info,
+            mint_info,
+            token_program_info,
+            rent.as_ref(),
+            released,
+        )?;
+        msg!("Released {}", released);
+
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
+    }
+
+    fn process_set_settlement_schedule(
+        accounts: &[AccountInfo],
+        settlement_interval: i64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if !owner_info.is_signer || *owner_info.key != subscription_plan.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if settlement_interval < 0 {
+            return Err(RecurringPaymentsError::InvalidSettlementInterval.into());
+        }
+
+        if settlement_interval > 0 {
+            Self::create_plan_vaults(
+                subscription_plan_account_info.key,
+                &mut subscription_plan,
+                SETTLEMENT_VAULT_SEED,
+                owner_info,
+                account_info_iter,
+                program_id,
+            )?;
+        }
+
+        subscription_plan.settlement_interval = settlement_interval;
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
+    fn process_set_keeper_tip(accounts: &[AccountInfo], keeper_tip: u64, program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if !owner_info.is_signer || *owner_info.key != subscription_plan.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        subscription_plan.keeper_tip = keeper_tip;
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
+    fn process_migrate_account(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let payer_info = next_account_info(account_info_iter)?;
+        let account_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if account_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        // Every layout version has its own size, legacy accounts predate the account type and version bytes
+        match account_info.data_len() {
+            LEGACY_SUBSCRIPTION_PLAN_SIZE => {
+                let payout_account_info = next_account_info(account_info_iter)?;
+                let mint_info = next_account_info(account_info_iter)?;
+                let legacy = LegacySubscriptionPlan::unpack(&account_info.data.borrow())?;
+
+                if *payout_account_info.key != legacy.token {
+                    return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+                }
+                let subscription_timeframe = CycleInterval::Days(legacy.subscription_timeframe);
+                subscription_timeframe.check_duration()?;
+                let accepted_mint = Self::accepted_mint_from_accounts(
+                    mint_info,
+                    payout_account_info,
+                    None,
+                    &mut Pubkey::default(),
+                    legacy.max_amount,
+                    0,
+                )?;
+
+                let subscription_plan = SubscriptionPlan {
+                    account_type: AccountType::SubscriptionPlan,
+                    version: SUBSCRIPTION_PLAN_VERSION,
+                    is_initialized: true,
+                    nonce: legacy.nonce,
+                    owner: legacy.owner,
+                    authority: legacy.authority,
+                    subscription_timeframe,
+                    max_cycles: 0,
+                    end_timestamp: 0,
+                    charge_upfront: false,
+                    billing_anchor_day: 0,
+                    dispute_window: 0,
+                    arbiter: Pubkey::default(),
+                    settlement_interval: 0,
+                    keeper_tip: 0,
+                    metadata: PlanMetadata::default(),
+                    price_feed_owner: Pubkey::default(),
+                    reserved: [0; SUBSCRIPTION_PLAN_RESERVED_SIZE],
+                    accepted_mints: vec![accepted_mint],
+                };
+                Self::resize_account(account_info, payer_info, system_program_info, SubscriptionPlan::LEN)?;
+                SubscriptionPlan::pack(subscription_plan, &mut account_info.data.borrow_mut())
+            }
+            LEGACY_SUBSCRIPTION_SIZE => {
+                let subscription_plan_account_info = next_account_info(account_info_iter)?;
+                let token_account_info = next_account_info(account_info_iter)?;
+                let legacy = LegacySubscription::unpack(&account_info.data.borrow())?;
+
+                if *subscription_plan_account_info.key != legacy.subscription_plan_account {
+                    return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+                }
+                let subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+                // A migrated plan keeps its legacy mint first, ahead of any mints added since
+                let accepted_mint = subscription_plan
+                    .accepted_mints
+                    .first()
+                    .ok_or(RecurringPaymentsError::InvalidMint)?;
+
+                // Legacy subscriptions recorded the plan owner, the subscriber is whoever owns the paying account
+                if *token_account_info.key != legacy.token_account {
+                    return Err(RecurringPaymentsError::InvalidSourceAccount.into());
+                }
+                let token_account = Self::unpack_token_account(token_account_info, &accepted_mint.token_program)?;
+                if token_account.mint != accepted_mint.mint {
+                    return Err(RecurringPaymentsError::InvalidMint.into());
+                }
+
+                let subscription = Subscription {
+                    account_type: AccountType::Subscription,
+                    version: SUBSCRIPTION_VERSION,
+                    is_initialized: true,
+                    is_approved: legacy.is_approved,
+                    subscription_plan_account: legacy.subscription_plan_account,
+                    token_account: legacy.token_account,
+                    mint: accepted_mint.mint,
+                    owner: token_account.owner,
+                    cycle_anchor: legacy.cycle_start,
+                    cycle_start: legacy.cycle_start,
+                    // The billing terms come from the migrated plan, as they do for new subscriptions
+                    subscription_timeframe: subscription_plan.subscription_timeframe,
+                    max_cycles: subscription_plan.max_cycles,
+                    end_timestamp: subscription_plan.end_timestamp,
+                    max_amount: accepted_mint.max_amount,
+                    withdrawn_amount: legacy.withdrawn_amount,
+                    refunded_amount: 0,
+                    payment_mode: PaymentMode::Delegate,
+                    vault_account: Pubkey::default(),
+                    escrowed_amount: 0,
+                    escrow_release: 0,
+                    is_disputed: false,
+                    recent_escrowed_amount: 0,
+                    recent_escrow_release: 0,
+                    customer_reference: [0; CUSTOMER_REFERENCE_LEN],
+                    reserved: [0; SUBSCRIPTION_RESERVED_SIZE],
+                };
+                Self::resize_account(account_info, payer_info, system_program_info, Subscription::LEN)?;
+                Subscription::pack(subscription, &mut account_info.data.borrow_mut())
+            }
+            SUBSCRIPTION_PLAN_V1_SIZE => {
+                let subscription_plan: SubscriptionPlan =
+                    SubscriptionPlanV1::unpack(&account_info.data.borrow())?.into();
+                Self::resize_account(account_info, payer_info, system_program_info, SubscriptionPlan::LEN)?;
+                SubscriptionPlan::pack(subscription_plan, &mut account_info.data.borrow_mut())
+            }
+            SUBSCRIPTION_PLAN_V2_SIZE => {
+                let subscription_plan: SubscriptionPlan =
+                    SubscriptionPlanV2::unpack(&account_info.data.borrow())?.into();
+                Self::resize_account(account_info, payer_info, system_program_info, SubscriptionPlan::LEN)?;
+                SubscriptionPlan::pack(subscription_plan, &mut account_info.data.borrow_mut())
+            }
+            SUBSCRIPTION_V1_SIZE => {
+                let subscription: Subscription = SubscriptionV1::unpack(&account_info.data.borrow())?.into();
+                Self::resize_account(account_info, payer_info, system_program_info, Subscription::LEN)?;
+                Subscription::pack(subscription, &mut account_info.data.borrow_mut())
+            }
+            _ => Err(RecurringPaymentsError::NothingToMigrate.into()),
+        }
+    }
+
+    fn process_update_plan_metadata(
+        accounts: &[AccountInfo],
+        name: &str,
+        uri: &str,
+        external_id: &str,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if !owner_info.is_signer || *owner_info.key != subscription_plan.owner {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        subscription_plan.metadata = PlanMetadata::new(name, uri, external_id)?;
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
+    fn process_settle(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let subscription_plan_account_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let settlement_vault_info = next_account_info(account_info_iter)?;
+        let payout_account_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let clock_sysvar_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_sysvar_info)?;
+
+        let mut subscription_plan = Self::unpack_subscription_plan(subscription_plan_account_info, program_id)?;
+
+        if *authority_info.key != subscription_plan.authority {
+            return Err(RecurringPaymentsError::InvalidProgramAddress.into());
+        }
+
+        let settlement_interval = subscription_plan.settlement_interval;
+        let nonce = subscription_plan.nonce;
+        let accepted_mint = subscription_plan
+            .accepted_mints
+            .iter_mut()
+            .find(|accepted_mint| accepted_mint.mint == *mint_info.key)
+            .ok_or(RecurringPaymentsError::InvalidMint)?;
+
+        if accepted_mint.settlement_vault == Pubkey::default()
+            || *settlement_vault_info.key != accepted_mint.settlement_vault
+        {
+            return Err(RecurringPaymentsError::InvalidSettlementVault.into());
+        }
+        if *payout_account_info.key != accepted_mint.payout_account {
+            return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+        }
+        if *token_program_info.key != accepted_mint.token_program {
+            return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+        }
+        Self::check_mint(mint_info, accepted_mint)?;
+
+        // Anything left over after settlement was turned off can be swept right away
+        let next_settlement = accepted_mint
+            .last_settlement
+            .checked_add(settlement_interval)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        if settlement_interval > 0 && clock.unix_timestamp < next_settlement {
+            return Err(RecurringPaymentsError::SettlementNotDue.into());
+        }
+
+        let amount = Self::unpack_token_account(settlement_vault_info, &accepted_mint.token_program)?.amount;
+        if amount == 0 {
+            return Err(RecurringPaymentsError::NothingToClaim.into());
+        }
+
+        // Anyone can settle, the funds can only go to the payout account. Token-2022 withholds its transfer fee again
+        let fee = Self::transfer_fee(mint_info, clock.epoch, amount)?;
+        Self::token_transfer(
+            subscription_plan_account_info.key,
+            token_program_info.clone(),
+            settlement_vault_info.clone(),
+            mint_info.clone(),
+            payout_account_info.clone(),
+            authority_info.clone(),
+            nonce,
+            amount,
+            accepted_mint.decimals,
+        )?;
+        msg!(
+            "Settled {}, payout received {} after a transfer fee of {}",
+            amount,
+            amount.saturating_sub(fee),
+            fee
+        );
+
+        accepted_mint.last_settlement = clock.unix_timestamp;
+
+        SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
+    }
+
+    /// Calculates the authority id by generating a program address.
+    pub fn authority_id(program_id: &Pubkey, my_info: &Pubkey, nonce: u8) -> Result<Pubkey, RecurringPaymentsError> {
+        Pubkey::create_program_address(&[&my_info.to_bytes()[..32], &[nonce]], program_id)
+            .or(Err(RecurringPaymentsError::InvalidProgramAddress))
+    }
+
+    /// Finds the vault PDA a token escrow subscription holds its deposit in and its bump seed.
+    pub fn subscription_vault_id(
+        program_id: &Pubkey,
+        subscription_plan: &Pubkey,
+        subscription: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[SUBSCRIPTION_VAULT_SEED, &subscription_plan.to_bytes(), &subscription.to_bytes()],
+            program_id,
+        )
+    }
+
+    /// Checks an account is the vault of a subscription. Token vaults are PDAs of the plan and subscription, so
+    /// no two subscriptions share one and a withdrawal can only reach the subscriber's own deposit. Native escrow
+    /// subscriptions hold their deposit in the subscription account itself.
+    fn check_subscription_vault(
+        vault_account_info: &AccountInfo,
+        subscription_key: &Pubkey,
+        subscription: &Subscription,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let vault_account = match subscription.payment_mode {
+            PaymentMode::Delegate => return Err(RecurringPaymentsError::InvalidPaymentMode.into()),
+            PaymentMode::NativeEscrow => *subscription_key,
+            PaymentMode::Escrow => {
+                Self::subscription_vault_id(program_id, &subscription.subscription_plan_account, subscription_key).0
+            }
+        };
+        if *vault_account_info.key != vault_account || *vault_account_info.key != subscription.vault_account {
+            return Err(RecurringPaymentsError::InvalidVaultAccount.into());
+        }
+        Ok(())
+    }
+
+    /// Finds the escrow account or settlement vault PDA of a plan mint, by the seed of the kind of vault, and its bump
+    /// seed. Each kind has its own seed, so a vault can never stand in for another.
+    pub fn plan_vault_id(program_id: &Pubkey, seed: &[u8], subscription_plan: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[seed, &subscription_plan.to_bytes(), &mint.to_bytes()], program_id)
+    }
+
+    /// Gives every token mint of a plan its escrow account or settlement vault PDA, by the seed of the kind of vault,
+    /// creating the ones that don't exist yet. A vault already set may still hold funds, so it is never replaced.
+    fn create_plan_vaults<'a, 'b>(
+        subscription_plan_key: &Pubkey,
+        subscription_plan: &mut SubscriptionPlan,
+        seed: &[u8],
+        payer_info: &AccountInfo<'b>,
+        account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let system_program_info = next_account_info(account_info_iter)?;
+        let authority = subscription_plan.authority;
+        for accepted_mint in subscription_plan
+            .accepted_mints
+            .iter_mut()
+            .filter(|accepted_mint| !accepted_mint.is_native())
+        {
+            let vault_account_info = next_account_info(account_info_iter)?;
+            let mint_info = next_account_info(account_info_iter)?;
+            let token_program_info = next_account_info(account_info_iter)?;
+            if *token_program_info.key != accepted_mint.token_program {
+                return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+            }
+            Self::check_mint(mint_info, accepted_mint)?;
+
+            let (expected_vault_account, bump) =
+                Self::plan_vault_id(program_id, seed, subscription_plan_key, &accepted_mint.mint);
+            let (vault_account, error) = if seed == PLAN_ESCROW_SEED {
+                (&mut accepted_mint.escrow_account, RecurringPaymentsError::InvalidEscrowAccount)
+            } else {
+                (&mut accepted_mint.settlement_vault, RecurringPaymentsError::InvalidSettlementVault)
+            };
+            if *vault_account_info.key != expected_vault_account
+                || (*vault_account != Pubkey::default() && *vault_account != expected_vault_account)
+            {
+                return Err(error.into());
+            }
+            if *vault_account != Pubkey::default() {
+                continue;
+            }
+
+            Self::create_token_vault(
+                vault_account_info,
+                &[seed, &subscription_plan_key.to_bytes(), &mint_info.key.to_bytes(), &[bump]],
+                &authority,
+                payer_info,
+                mint_info,
+                token_program_info,
+                system_program_info,
+            )?;
+            *vault_account = expected_vault_account;
+        }
+        Ok(())
+    }
+
+    /// Creates a vault PDA as a token account owned by the plan authority, sized for the account extensions the mint
+    /// requires. Nobody else can move its funds or close it.
+    fn create_token_vault<'a>(
+        vault_account_info: &AccountInfo<'a>,
+        signer_seeds: &[&[u8]],
+        authority: &Pubkey,
+        payer_info: &AccountInfo<'a>,
+        mint_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let len = {
+            let mint_data = mint_info.data.borrow();
+            let mint = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+            let extension_types = ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+            ExtensionType::try_calculate_account_len::<Account>(&extension_types)?
+        };
+        Self::create_pda_account(
+            payer_info,
+            vault_account_info,
+            system_program_info,
+            &Rent::get()?,
+            len,
+            token_program_info.key,
+            signer_seeds,
+        )?;
+        invoke(
+            &spl_token_2022::instruction::initialize_account3(
+                token_program_info.key,
+                vault_account_info.key,
+                mint_info.key,
+                authority,
+            )?,
+            &[vault_account_info.clone(), mint_info.clone(), token_program_info.clone()],
+        )
+    }
+
+    /// Creates a PDA of `len` bytes owned by `owner`, with the payer covering its rent. Anyone can send lamports to
+    /// the address beforehand, so the account is funded, allocated and assigned rather than created in one go.
+    pub fn create_pda_account<'a>(
+        payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+        len: usize,
+        owner: &Pubkey,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let rent_top_up = rent
+            .minimum_balance(len)
+            .saturating_sub(account_info.lamports());
+        if rent_top_up > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, rent_top_up),
+                &[payer_info.clone(), account_info.clone(), system_program_info.clone()],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(account_info.key, len as u64),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(account_info.key, owner),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )
+    }
+
+    /// Finds the subscriber profile PDA of a wallet and its bump seed.
+    pub fn subscriber_profile_id(program_id: &Pubkey, subscriber: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SUBSCRIBER_PROFILE_SEED, &subscriber.to_bytes()], program_id)
+    }
+
+    /// Counts a claim against the subscriber's spending cap, if they have set one. The cap is counted in a single
+    /// mint, so claims in any other mint are rejected rather than left uncapped. The charged profile is returned for
+    /// the claim to pack once its transfers went through, `None` when the wallet has no profile.
+    fn charge_spending_cap(
+        subscriber_profile_info: &AccountInfo,
+        subscription: &Subscription,
+        amount: u64,
+        now: UnixTimestamp,
+        program_id: &Pubkey,
+    ) -> Result<Option<SubscriberProfile>, ProgramError> {
+        let (subscriber_profile_key, _) = Self::subscriber_profile_id(program_id, &subscription.owner);
+        if *subscriber_profile_info.key != subscriber_profile_key {
+            return Err(RecurringPaymentsError::InvalidSubscriberProfile.into());
+        }
+
+        // Wallets that never set a cap have no profile account
+        if subscriber_profile_info.owner != program_id {
+            return Ok(None);
+        }
+        let mut subscriber_profile = SubscriberProfile::unpack(&subscriber_profile_info.data.borrow())?;
+        if subscriber_profile.mint != subscription.mint {
+            return Err(RecurringPaymentsError::SpendingCapMintMismatch.into());
+        }
+
+        subscriber_profile.spend(amount, now)?;
+        Ok(Some(subscriber_profile))
+    }
+
+    /// Issue a `TransferChecked` instruction signed by the plan authority.
+    #[allow(clippy::too_many_arguments)]
+    pub fn token_transfer<'a>(
+        subscription_plan: &Pubkey,
+        token_program: AccountInfo<'a>,
+        source: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        nonce: u8,
+        amount: u64,
+        decimals: u8,
+    ) -> Result<(), ProgramError> {
+        let subscription_plan_bytes = subscription_plan.to_bytes();
+        let authority_signature_seeds = [&subscription_plan_bytes[..32], &[nonce]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?;
+        invoke_signed(&ix, &[source, mint, destination, authority, token_program], signers)
+    }
+
+    /// Issue a `TransferChecked` instruction signed by the owner or delegate of the source account.
+    pub fn token_transfer_by_owner<'a>(
+        token_program: AccountInfo<'a>,
+        source: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        owner: AccountInfo<'a>,
+        amount: u64,
+        decimals: u8,
+    ) -> Result<(), ProgramError> {
+        let ix = spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            owner.key,
+            &[],
+            amount,
+            decimals,
+        )?;
+        invoke(&ix, &[source, mint, destination, owner, token_program])
+    }
+
+    /// Transfer fee settings of a Token-2022 mint, `None` for mints without the transfer fee extension.
+    fn transfer_fee_config(mint_info: &AccountInfo) -> Result<Option<TransferFeeConfig>, ProgramError> {
+        if *mint_info.owner != spl_token_2022::id() {
+            return Ok(None);
+        }
+        let mint_data = mint_info.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+        Ok(mint.get_extension::<TransferFeeConfig>().ok().copied())
+    }
+
+    /// Fee Token-2022 withholds from a transfer of `amount`, zero for mints without the transfer fee extension.
+    pub fn transfer_fee(mint_info: &AccountInfo, epoch: Epoch, amount: u64) -> Result<u64, ProgramError> {
+        match Self::transfer_fee_config(mint_info)? {
+            Some(transfer_fee_config) => Ok(transfer_fee_config
+                .calculate_epoch_fee(epoch, amount)
+                .ok_or(RecurringPaymentsError::Overflow)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Amount to transfer so `net_amount` arrives after the Token-2022 transfer fee, `net_amount` itself for mints
+    /// without the transfer fee extension.
+    pub fn gross_up_transfer_fee(mint_info: &AccountInfo, epoch: Epoch, net_amount: u64) -> Result<u64, ProgramError> {
+        match Self::transfer_fee_config(mint_info)? {
+            Some(transfer_fee_config) => Ok(transfer_fee_config
+                .calculate_inverse_epoch_fee(epoch, net_amount)
+                .and_then(|fee| net_amount.checked_add(fee))
+                .ok_or(RecurringPaymentsError::Overflow)?),
+            None => Ok(net_amount),
+        }
+    }
+
+    /// Lamports held by a program owned account above its rent exempt minimum.
+    pub fn lamport_balance(account_info: &AccountInfo, rent: &Rent) -> u64 {
+        account_info
+            .lamports()
+            .saturating_sub(rent.minimum_balance(account_info.data_len()))
+    }
+
+    /// Moves lamports out of a program owned account without letting it drop below rent exemption.
+    pub fn lamport_transfer(
+        source: &AccountInfo,
+        destination: &AccountInfo,
+        rent: &Rent,
+        amount: u64,
+    ) -> ProgramResult {
+        if Self::lamport_balance(source, rent) < amount {
+            return Err(RecurringPaymentsError::InsufficientVaultFunds.into());
+        }
+        let destination_lamports = destination
+            .lamports()
+            .checked_add(amount)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+
+        **source.try_borrow_mut_lamports()? -= amount;
+        **destination.try_borrow_mut_lamports()? = destination_lamports;
+        Ok(())
+    }
+
+    /// Grows a program owned account to `len` bytes, with the payer covering the rent of the extra space.
+    pub fn resize_account<'a>(
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        len: usize,
+    ) -> ProgramResult {
+        let rent_top_up = Rent::get()?
+            .minimum_balance(len)
+            .saturating_sub(account_info.lamports());
+        if rent_top_up > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, rent_top_up),
+                &[payer_info.clone(), account_info.clone(), system_program_info.clone()],
+            )?;
+        }
+        account_info.realloc(len, true)
+    }
+
+    /// Checks that a program id is either spl-token or Token-2022.
+    pub fn check_token_program(token_program_id: &Pubkey) -> Result<(), RecurringPaymentsError> {
+        if *token_program_id == spl_token::id() || *token_program_id == spl_token_2022::id() {
+            Ok(())
+        } else {
+            Err(RecurringPaymentsError::IncorrectTokenProgramId)
+        }
+    }
+
+    /// Unpacks the base state of a spl-token or Token-2022 `Account`.
+    pub fn unpack_token_account(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> Result<Account, RecurringPaymentsError> {
+        if account_info.owner != token_program_id {
+            Err(RecurringPaymentsError::IncorrectTokenProgramId)
+        } else {
+            StateWithExtensions::<Account>::unpack(&account_info.data.borrow())
+                .map(|account| account.base)
+                .map_err(|_| RecurringPaymentsError::ExpectedAccount)
+        }
+    }
+
+    /// Unpacks the base state of a spl-token or Token-2022 `Mint`, rejecting extensions claims can't work with.
+    pub fn unpack_mint(account_info: &AccountInfo, token_program_id: &Pubkey) -> Result<Mint, RecurringPaymentsError> {
+        if account_info.owner != token_program_id {
+            return Err(RecurringPaymentsError::IncorrectTokenProgramId);
+        }
+        let mint_data = account_info.data.borrow();
+        let mint = StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| RecurringPaymentsError::ExpectedMint)?;
+        // Transfer hooks need extra accounts on every transfer, non-transferable mints can't be claimed at all
+        let extension_types = mint
+            .get_extension_types()
+            .map_err(|_| RecurringPaymentsError::ExpectedMint)?;
+        if extension_types.iter().any(|extension_type| {
+            matches!(
+                extension_type,
+                ExtensionType::TransferHook | ExtensionType::NonTransferable
+            )
+        }) {
+            return Err(RecurringPaymentsError::UnsupportedMintExtension);
+        }
+        Ok(mint.base)
+    }
+
+    /// Unpacks an initialized `SubscriptionPlan` owned by this program.
+    pub fn unpack_subscription_plan(
+        account_info: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<SubscriptionPlan, ProgramError> {
+        if account_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        SubscriptionPlan::unpack(&account_info.data.borrow())
+    }
+
+    /// Unpacks an initialized `Subscription` owned by this program.
+    pub fn unpack_subscription(account_info: &AccountInfo, program_id: &Pubkey) -> Result<Subscription, ProgramError> {
+        if account_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Subscription::unpack(&account_info.data.borrow())
+    }
+
+    pub fn unpack_price_feed(account_info: &AccountInfo) -> Result<PriceFeed, ProgramError> {
+        PriceFeed::unpack(&account_info.data.borrow()).map_err(|_| RecurringPaymentsError::InvalidPriceFeed.into())
+    }
+
+    /// Checks the plan level accounts of a claim, returning the accepted mint being claimed and the tip owed to
+    /// the claimer for each subscription. Keepers can only claim into the accounts the plan records, so the claimer
+    /// never gets to pick where funds go.
+    fn check_claim_accounts<'p>(
+        subscription_plan: &'p SubscriptionPlan,
+        claimer_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        payout_account_info: &AccountInfo,
+        mint_info: &AccountInfo,
+        token_program_info: &AccountInfo,
+    ) -> Result<(&'p AcceptedMint, u64), ProgramError> {
+        let accepted_mint = subscription_plan.accepted_mint(mint_info.key)?;
+
+        if !claimer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let keeper_tip = if *claimer_info.key == subscription_plan.owner {
+            0
+        } else if subscription_plan.keeper_tip > 0 {
+            subscription_plan.keeper_tip
+        } else {
+            // Plans that don't pay keepers can only be claimed by their owner
+            return Err(ProgramError::MissingRequiredSignature);
+        };
+
+        if *authority_info.key != subscription_plan.authority {
+            return Err(RecurringPaymentsError::InvalidProgramAddress.into());
+        }
+
+        if *payout_account_info.key != accepted_mint.payout_account {
+            return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+        }
+
+        if !accepted_mint.is_native() {
+            if *token_program_info.key != accepted_mint.token_program {
+                return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+            }
+            Self::check_mint(mint_info, accepted_mint)?;
+        }
+
+        Ok((accepted_mint, keeper_tip))
+    }
+
+    /// Checks the plan account holds enough lamports above rent exemption to pay a keeper tip.
+    fn check_keeper_tip(subscription_plan_account_info: &AccountInfo, keeper_tip: u64) -> ProgramResult {
+        if keeper_tip > 0 && Self::lamport_balance(subscription_plan_account_info, &Rent::get()?) < keeper_tip {
+            return Err(RecurringPaymentsError::InsufficientKeeperTipFunds.into());
+        }
+        Ok(())
+    }
+
+    /// Pays a keeper's tip for one claim out of the lamports the plan account holds.
+    fn pay_keeper_tip(
+        subscription_plan_account_info: &AccountInfo,
+        keeper_info: &AccountInfo,
+        keeper_tip: u64,
+    ) -> ProgramResult {
+        if keeper_tip == 0 {
+            return Ok(());
+        }
+        Self::check_keeper_tip(subscription_plan_account_info, keeper_tip)?;
+        Self::lamport_transfer(subscription_plan_account_info, keeper_info, &Rent::get()?, keeper_tip)?;
+        msg!("Paid keeper {} a tip of {}", keeper_info.key, keeper_tip);
+        Ok(())
+    }
+
+    /// Picks the account claimed tokens are sent to, reading the plan's escrow or settlement vault when it has one.
+    fn claim_destination<'a, 'b>(
+        subscription_plan: &SubscriptionPlan,
+        accepted_mint: &AcceptedMint,
+        payout_account_info: &'a AccountInfo<'b>,
+        account_info_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+    ) -> Result<&'a AccountInfo<'b>, ProgramError> {
+        if accepted_mint.is_native() {
+            // SOL claims held during a dispute window stay in the subscription account
+            return Ok(payout_account_info);
+        }
+        if subscription_plan.dispute_window > 0 {
+            let escrow_account_info = next_account_info(account_info_iter)?;
+            if accepted_mint.escrow_account == Pubkey::default()
+                || *escrow_account_info.key != accepted_mint.escrow_account
+            {
+                return Err(RecurringPaymentsError::InvalidEscrowAccount.into());
+            }
+            Ok(escrow_account_info)
+        } else if subscription_plan.settlement_interval > 0 {
+            // Claims collect in the settlement vault so they don't all write to the payout account
+            let settlement_vault_info = next_account_info(account_info_iter)?;
+            if accepted_mint.settlement_vault == Pubkey::default()
+                || *settlement_vault_info.key != accepted_mint.settlement_vault
+            {
+                return Err(RecurringPaymentsError::InvalidSettlementVault.into());
+            }
+            Ok(settlement_vault_info)
+        } else {
+            Ok(payout_account_info)
+        }
+    }
+
+    /// Claims what a subscription owes for the current cycle. Every check runs before the first write, so a
+    /// failed claim leaves the accounts untouched and a batch can move on to the next subscription.
+    #[allow(clippy::too_many_arguments)]
+    fn claim_subscription<'a>(
+        subscription_plan_account_info: &AccountInfo<'a>,
+        subscription_plan: &SubscriptionPlan,
+        accepted_mint: &AcceptedMint,
+        subscription_account_info: &AccountInfo<'a>,
+        source_info: &AccountInfo<'a>,
+        subscriber_profile_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        payout_account_info: &AccountInfo<'a>,
+        mint_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        rent_or_price_feed_info: Option<&AccountInfo<'a>>,
+        clock: &Clock,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let mut subscription = Self::unpack_subscription(subscription_account_info, program_id)?;
+
+        if subscription.subscription_plan_account != *subscription_plan_account_info.key {
+            return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+        }
+
+        if *mint_info.key != subscription.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+
+        let escrow_claim = subscription_plan.dispute_window > 0;
+        if escrow_claim && subscription.is_disputed {
+            return Err(RecurringPaymentsError::SubscriptionDisputed.into());
+        }
+
+        match subscription.payment_mode {
+            PaymentMode::Delegate if *source_info.key != subscription.token_account => {
+                return Err(RecurringPaymentsError::InvalidSourceAccount.into());
+            }
+            PaymentMode::Delegate => {}
+            PaymentMode::Escrow | PaymentMode::NativeEscrow => Self::check_subscription_vault(
+                source_info,
+                subscription_account_info.key,
+                &subscription,
+                program_id,
+            )
+            .map_err(|_| RecurringPaymentsError::InvalidSourceAccount)?,
+        }
+
+        if !subscription.is_approved {
+            return Err(RecurringPaymentsError::SubscriptionNotApproved.into());
+        }
+
+        if subscription.is_term_complete(clock.unix_timestamp)? {
+            return Err(RecurringPaymentsError::TermCompleted.into());
+        }
+
+        subscription.roll_cycle(clock.unix_timestamp)?;
+
+        let amount = subscription.claimable_amount();
+        if amount == 0 {
+            return Err(RecurringPaymentsError::NothingToClaim.into());
+        }
+
+        // The charged profile is only written once the transfers went through
+        let subscriber_profile = if subscription.payment_mode == PaymentMode::NativeEscrow {
+            let rent = &Rent::from_account_info(rent_or_price_feed_info.ok_or(ProgramError::NotEnoughAccountKeys)?)?;
+            // Lamports held in escrow share the subscription account with the prepaid balance
+            if Self::lamport_balance(source_info, rent).saturating_sub(subscription.escrowed_amount) < amount {
+                return Err(RecurringPaymentsError::InsufficientVaultFunds.into());
+            }
+            // Escrowed lamports stay in the subscription account, only matured escrow goes out with the claim
+            let paid_out = if escrow_claim {
+                Self::escrow_claim(&mut subscription, subscription_plan, amount, clock.unix_timestamp)?
+            } else {
+                amount
+            };
+            subscription.withdrawn_amount = subscription
+                .withdrawn_amount
+                .checked_add(amount)
+                .ok_or(RecurringPaymentsError::Overflow)?;
+            let subscriber_profile = Self::charge_spending_cap(
+                subscriber_profile_info,
+                &subscription,
+                amount,
+                clock.unix_timestamp,
+                program_id,
+            )?;
+            if paid_out > 0 {
+                Self::lamport_transfer(source_info, destination_info, rent, paid_out)?;
+            }
+            subscriber_profile
+        } else {
+            let claimed = amount;
+            let amount = match accepted_mint.pricing_mode {
+                PricingMode::Fixed => amount,
+                PricingMode::UsdCents => Self::usd_cents_to_token_amount(
+                    rent_or_price_feed_info.ok_or(ProgramError::NotEnoughAccountKeys)?,
+                    subscription_plan,
+                    accepted_mint,
+                    amount,
+                    clock.unix_timestamp,
+                )?,
+            };
+            if amount == 0 {
+                return Err(RecurringPaymentsError::NothingToClaim.into());
+            }
+            Self::check_claim_source(source_info, &subscription, accepted_mint, authority_info.key, amount)?;
+            // The allowance is charged in full, Token-2022 withholds its transfer fee from what the merchant receives
+            let fee = Self::transfer_fee(mint_info, clock.epoch, amount)?;
+            let released = if escrow_claim {
+                let received = amount.saturating_sub(fee);
+                Self::escrow_claim(&mut subscription, subscription_plan, received, clock.unix_timestamp)?
+            } else {
+                0
+            };
+            subscription.withdrawn_amount = subscription
+                .withdrawn_amount
+                .checked_add(claimed)
+                .ok_or(RecurringPaymentsError::Overflow)?;
+            let subscriber_profile = Self::charge_spending_cap(
+                subscriber_profile_info,
+                &subscription,
+                amount,
+                clock.unix_timestamp,
+                program_id,
+            )?;
+            Self::token_transfer(
+                subscription_plan_account_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                mint_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                subscription_plan.nonce,
+                amount,
+                accepted_mint.decimals,
+            )?;
+            msg!(
+                "Claimed {}, merchant received {} after a transfer fee of {}",
+                amount,
+                amount.saturating_sub(fee),
+                fee
+            );
+            if released > 0 {
+                Self::escrow_transfer(
+                    subscription_plan_account_info.key,
+                    subscription_plan,
+                    accepted_mint,
+                    destination_info,
+                    payout_account_info,
+                    authority_info,
+                    mint_info,
+                    token_program_info,
+                    None,
+                    released,
+                )?;
+            }
+            subscriber_profile
+        };
+        if let Some(customer_reference) = subscription.customer_reference() {
+            msg!("Customer reference {}", customer_reference);
+        }
+
+        if let Some(subscriber_profile) = subscriber_profile {
+            SubscriberProfile::pack(subscriber_profile, &mut subscriber_profile_info.data.borrow_mut())?;
+        }
+        Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
+    }
+
+    /// Checks a claim's token transfer will go through, since a failed transfer would abort a whole batch.
+    fn check_claim_source(
+        source_info: &AccountInfo,
+        subscription: &Subscription,
+        accepted_mint: &AcceptedMint,
+        authority: &Pubkey,
+        amount: u64,
+    ) -> ProgramResult {
+        let source = Self::unpack_token_account(source_info, &accepted_mint.token_program)?;
+        let available = match subscription.payment_mode {
+            PaymentMode::Delegate if source.delegate == COption::Some(*authority) => source.delegated_amount,
+            PaymentMode::Delegate => 0,
+            PaymentMode::Escrow | PaymentMode::NativeEscrow => source.amount,
+        };
+        if source.is_frozen() || available.min(source.amount) < amount {
+            return Err(RecurringPaymentsError::InsufficientSourceFunds.into());
+        }
+        Ok(())
+    }
+
+    /// Holds a claimed amount in escrow until the plan's dispute window has passed. Escrow that has matured since
+    /// the last claim is released first, the returned amount is for the claim to pay out.
+    fn escrow_claim(
+        subscription: &mut Subscription,
+        subscription_plan: &SubscriptionPlan,
+        amount: u64,
+        now: UnixTimestamp,
+    ) -> Result<u64, ProgramError> {
+        let released = subscription.release_matured_escrow(now);
+        let release = now
+            .checked_add(subscription_plan.dispute_window)
+            .ok_or(RecurringPaymentsError::Overflow)?;
+        subscription.hold_in_escrow(amount, release)?;
+        msg!("Holding {} in escrow until {}, released {}", amount, release, released);
+        Ok(released)
+    }
+
+    /// Checks the accounts escrowed funds of a subscription move between, returning the subscription's mint.
+    #[allow(clippy::too_many_arguments)]
+    fn check_escrow_accounts<'p>(
+        subscription_plan: &'p SubscriptionPlan,
+        subscription: &Subscription,
+        subscription_plan_account_info: &AccountInfo,
+        subscription_account_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        escrow_account_info: &AccountInfo,
+        payout_account_info: &AccountInfo,
+        mint_info: &AccountInfo,
+        token_program_info: &AccountInfo,
+    ) -> Result<&'p AcceptedMint, ProgramError> {
+        if subscription.subscription_plan_account != *subscription_plan_account_info.key {
+            return Err(RecurringPaymentsError::InvalidSubscriptionPlan.into());
+        }
+        if *authority_info.key != subscription_plan.authority {
+            return Err(RecurringPaymentsError::InvalidProgramAddress.into());
+        }
+        if *mint_info.key != subscription.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+        let accepted_mint = subscription_plan.accepted_mint(&subscription.mint)?;
+        if *payout_account_info.key != accepted_mint.payout_account {
+            return Err(RecurringPaymentsError::InvalidPayoutAccount.into());
+        }
+
+        let escrow_account = if accepted_mint.is_native() {
+            *subscription_account_info.key
+        } else {
+            if *token_program_info.key != accepted_mint.token_program {
+                return Err(RecurringPaymentsError::IncorrectTokenProgramId.into());
+            }
+            Self::check_mint(mint_info, accepted_mint)?;
+            accepted_mint.escrow_account
+        };
+        if *escrow_account_info.key != escrow_account {
+            return Err(RecurringPaymentsError::InvalidEscrowAccount.into());
+        }
+        Ok(accepted_mint)
+    }
+
+    /// Moves escrowed funds out of the plan escrow, or out of the subscription account for SOL. Escrowed tokens were
+    /// already net of the Token-2022 transfer fee on the way in, and the fee is withheld again on the way out, so the
+    /// destination receives `amount` less a second fee.
+    #[allow(clippy::too_many_arguments)]
+    fn escrow_transfer<'a>(
+        subscription_plan_key: &Pubkey,
+        subscription_plan: &SubscriptionPlan,
+        accepted_mint: &AcceptedMint,
+        escrow_account_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        mint_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        rent: Option<&Rent>,
+        amount: u64,
+    ) -> ProgramResult {
+        if amount == 0 {
+            return Ok(());
+        }
+        match rent {
+            Some(rent) => Self::lamport_transfer(escrow_account_info, destination_info, rent, amount),
+            None => {
+                let fee = Self::transfer_fee(mint_info, Clock::get()?.epoch, amount)?;
+                Self::token_transfer(
+                    subscription_plan_key,
+                    token_program_info.clone(),
+                    escrow_account_info.clone(),
+                    mint_info.clone(),
+                    destination_info.clone(),
+                    authority_info.clone(),
+                    subscription_plan.nonce,
+                    amount,
+                    accepted_mint.decimals,
+                )?;
+                msg!(
+                    "Sent {} out of escrow, {} received after a transfer fee of {}",
+                    amount,
+                    amount.saturating_sub(fee),
+                    fee
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Converts a USD cents price into token units with the accepted mint's price feed. The feed has to still be
+    /// owned by the program that owned it when the mint was added, so a closed and recreated feed isn't trusted.
+    fn usd_cents_to_token_amount(
+        price_feed_info: &AccountInfo,
+        subscription_plan: &SubscriptionPlan,
+        accepted_mint: &AcceptedMint,
+        cents: u64,
+        now: UnixTimestamp,
+    ) -> Result<u64, ProgramError> {
+        if *price_feed_info.key != accepted_mint.price_feed {
+            return Err(RecurringPaymentsError::InvalidPriceFeed.into());
+        }
+        if *price_feed_info.owner != subscription_plan.price_feed_owner {
+            return Err(RecurringPaymentsError::InvalidPriceFeed.into());
+        }
+        let price_feed = Self::unpack_price_feed(price_feed_info)?;
+        price_feed.check(now)?;
+        let token_amount = price_feed.usd_cents_to_token_amount(cents, accepted_mint.decimals)?;
+        msg!("Converted {} USD cents to {} tokens", cents, token_amount);
+        Ok(token_amount)
+    }
+
+    /// Validates a mint and its payout account before they are accepted by a plan. Passing a price feed makes
+    /// `max_amount` a price in USD cents, the first feed a plan accepts pins `price_feed_owner` for the rest.
+    fn accepted_mint_from_accounts(
+        token_info: &AccountInfo,
+        payout_account_info: &AccountInfo,
+        price_feed_info: Option<&AccountInfo>,
+        price_feed_owner: &mut Pubkey,
+        max_amount: u64,
+        setup_fee: u64,
+    ) -> Result<AcceptedMint, ProgramError> {
+        if max_amount == 0 {
+            return Err(RecurringPaymentsError::InvalidMaxAmount.into());
+        }
+
+        // The mint's owner decides whether payments go through spl-token or Token-2022
+        let token_program = *token_info.owner;
+        Self::check_token_program(&token_program)?;
+        let mint = Self::unpack_mint(token_info, &token_program)?;
+
+        // SOL payments go to a plain wallet
+        if *token_info.key != spl_token::native_mint::id() {
+            let payout_account = Self::unpack_token_account(payout_account_info, &token_program)?;
+            if payout_account.mint != *token_info.key {
+                return Err(RecurringPaymentsError::InvalidMint.into());
+            }
+        }
+
+        let (pricing_mode, price_feed) = match price_feed_info {
+            None => (PricingMode::Fixed, Pubkey::default()),
+            Some(price_feed_info) => {
+                if *token_info.key == spl_token::native_mint::id() {
+                    return Err(RecurringPaymentsError::NativePlanRequiresEscrow.into());
+                }
+                Self::unpack_price_feed(price_feed_info)?;
+                if *price_feed_owner == Pubkey::default() {
+                    *price_feed_owner = *price_feed_info.owner;
+                } else if *price_feed_owner != *price_feed_info.owner {
+                    return Err(RecurringPaymentsError::InvalidPriceFeed.into());
+                }
+                (PricingMode::UsdCents, *price_feed_info.key)
+            }
+        };
+
+        Ok(AcceptedMint {
+            mint: *token_info.key,
+            token_program,
+            decimals: mint.decimals,
+            payout_account: *payout_account_info.key,
+            max_amount,
+            setup_fee,
+            pricing_mode,
+            price_feed,
+            escrow_account: Pubkey::default(),
+            settlement_vault: Pubkey::default(),
+            last_settlement: 0,
+        })
+    }
+
+    /// Checks a mint account against an accepted mint and the decimals recorded when it was added to the plan.
+    fn check_mint(mint_info: &AccountInfo, accepted_mint: &AcceptedMint) -> ProgramResult {
+        if *mint_info.key != accepted_mint.mint {
+            return Err(RecurringPaymentsError::InvalidMint.into());
+        }
+        let mint = Self::unpack_mint(mint_info, &accepted_mint.token_program)?;
+        if mint.decimals != accepted_mint.decimals {
+            return Err(RecurringPaymentsError::InvalidMintDecimals.into());
+        }
+        Ok(())
+    }
+}
+
+impl PrintProgramError for RecurringPaymentsError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        match self {
+            RecurringPaymentsError::InvalidInstruction => msg!("Error: Invalid instruction"),
+            RecurringPaymentsError::InvalidMaxAmount => msg!("Error: Invalid max amount"),
+            RecurringPaymentsError::InvalidSubscriptionTimeframe => msg!("Error: Invalid subscription timeframe"),
+            RecurringPaymentsError::InvalidProgramAddress => {
+                msg!("Error: Invalid program address generated from nonce and key")
+            }
+            RecurringPaymentsError::IncorrectTokenProgramId => {
+                msg!("Error: The provided token program does not match the expected token program")
+            }
+            RecurringPaymentsError::ExpectedAccount => msg!("Error: Deserialized account is not an SPL Token account"),
+            RecurringPaymentsError::InvalidMint => {
+                msg!("Error: Token account mint does not match the subscription plan token")
+            }
+            RecurringPaymentsError::InvalidPayoutAccount => {
+                msg!("Error: Payout account does not match the subscription plan")
+            }
+            RecurringPaymentsError::InvalidSubscriptionPlan => {
+                msg!("Error: Subscription does not belong to the provided subscription plan")
+            }
+            RecurringPaymentsError::InvalidSourceAccount => {
+                msg!("Error: Source account does not match the subscription")
+            }
+            RecurringPaymentsError::InvalidVaultAccount => {
+                msg!("Error: Vault account is not the vault PDA of the subscription")
+            }
+            RecurringPaymentsError::InvalidPaymentMode => {
+                msg!("Error: Instruction is not supported by the subscription payment mode")
+            }
+            RecurringPaymentsError::SubscriptionNotApproved => msg!("Error: Subscription is not approved"),
+            RecurringPaymentsError::NothingToClaim => msg!("Error: Nothing to claim in the current cycle"),
+            RecurringPaymentsError::InsufficientVaultFunds => {
+                msg!("Error: Vault does not hold enough unreserved funds")
+            }
+            RecurringPaymentsError::Overflow => msg!("Error: Calculation overflow"),
+            RecurringPaymentsError::InvalidSubscriber => msg!("Error: Account is not the subscriber"),
+            RecurringPaymentsError::NativePlanRequiresEscrow => {
+                msg!("Error: Native SOL plans only support native escrow subscriptions")
+            }
+            RecurringPaymentsError::ExpectedMint => msg!("Error: Deserialized account is not an SPL Token mint"),
+            RecurringPaymentsError::UnsupportedMintExtension => {
+                msg!("Error: Mint uses a Token-2022 extension that subscriptions do not support")
+            }
+            RecurringPaymentsError::DepositShortAfterTransferFee => {
+                msg!("Error: Vault received less than the deposit after the Token-2022 transfer fee")
+            }
+            RecurringPaymentsError::InvalidMintDecimals => {
+                msg!("Error: Mint decimals do not match the subscription plan")
+            }
+            RecurringPaymentsError::MintAlreadyAccepted => msg!("Error: Mint is already accepted by the plan"),
+            RecurringPaymentsError::TooManyAcceptedMints => {
+                msg!("Error: Subscription plan accepts the maximum number of mints")
+            }
+            RecurringPaymentsError::InvalidPriceFeed => {
+                msg!("Error: Price feed does not match the plan or holds no usable price")
+            }
+            RecurringPaymentsError::StalePriceFeed => {
+                msg!("Error: Price feed has not been updated recently enough")
+            }
+            RecurringPaymentsError::PriceConfidenceTooWide => {
+                msg!("Error: Price feed confidence interval is too wide")
+            }
+            RecurringPaymentsError::OraclePricingRequiresDelegate => {
+                msg!("Error: USD priced mints only support delegate subscriptions")
+            }
+            RecurringPaymentsError::PriceFeedFromFuture => {
+                msg!("Error: Price feed was published after the current time")
+            }
+            RecurringPaymentsError::CycleTooShort => msg!("Error: Billing cycle is shorter than the minimum duration"),
+            RecurringPaymentsError::CycleTooLong => msg!("Error: Billing cycle is longer than the maximum duration"),
+            RecurringPaymentsError::TermCompleted => msg!("Error: Subscription term is complete"),
+            RecurringPaymentsError::TermNotComplete => msg!("Error: Subscription term is not complete yet"),
+            RecurringPaymentsError::VaultNotEmpty => msg!("Error: Vault still holds funds"),
+            RecurringPaymentsError::InvalidBillingAnchor => {
+                msg!("Error: Billing anchor day must be within the month and needs a calendar month cycle")
+            }
+            RecurringPaymentsError::InvalidSubscriberProfile => {
+                msg!("Error: Subscriber profile is not the PDA of the subscriber")
+            }
+            RecurringPaymentsError::SpendingCapExceeded => {
+                msg!("Error: Claim would exceed the subscriber's monthly spending cap")
+            }
+            RecurringPaymentsError::SpendingCapMintMismatch => {
+                msg!("Error: Subscription is not paid in the mint of the subscriber's spending cap")
+            }
+            RecurringPaymentsError::InvalidDestinationAccount => {
+                msg!("Error: Destination account does not match the subscription")
+            }
+            RecurringPaymentsError::CannotRestoreUsdAllowance => {
+                msg!("Error: Only fixed price subscriptions can have their allowance restored by a refund")
+            }
+            RecurringPaymentsError::InvalidDisputeWindow => msg!("Error: Dispute window cannot be negative"),
+            RecurringPaymentsError::InvalidEscrowAccount => {
+                msg!("Error: Escrow account does not match the subscription plan")
+            }
+            RecurringPaymentsError::SubscriptionDisputed => msg!("Error: Subscription has an open dispute"),
+            RecurringPaymentsError::DisputeWindowClosed => {
+                msg!("Error: Nothing is held in escrow that can still be disputed")
+            }
+            RecurringPaymentsError::DisputeWindowOpen => msg!("Error: Dispute window has not passed yet"),
+            RecurringPaymentsError::NotDisputed => msg!("Error: Subscription has no open dispute"),
+            RecurringPaymentsError::InvalidArbiter => msg!("Error: Signer is not the plan arbiter"),
+            RecurringPaymentsError::RefundExceedsEscrow => msg!("Error: Refund is larger than the escrowed amount"),
+            RecurringPaymentsError::EscrowNotReleased => {
+                msg!("Error: Subscription still has funds held in escrow")
+            }
+            RecurringPaymentsError::InvalidSettlementInterval => msg!("Error: Settlement interval cannot be negative"),
+            RecurringPaymentsError::InvalidSettlementVault => {
+                msg!("Error: Settlement vault does not match the subscription plan")
+            }
+            RecurringPaymentsError::SettlementNotDue => msg!("Error: Settlement interval has not passed yet"),
+            RecurringPaymentsError::InsufficientSourceFunds => {
+                msg!("Error: Subscriber's token account cannot cover the claim")
+            }
+            RecurringPaymentsError::InsufficientKeeperTipFunds => {
+                msg!("Error: Subscription plan account cannot fund the keeper tip")
+            }
+            RecurringPaymentsError::WrongAccountType => msg!("Error: Account is of the wrong type"),
+            RecurringPaymentsError::UnsupportedAccountVersion => {
+                msg!("Error: Account layout version is not supported, it needs to be migrated")
+            }
+            RecurringPaymentsError::NothingToMigrate => msg!("Error: Account is not in a layout that can be migrated"),
+            RecurringPaymentsError::InvalidPlanMetadata => msg!("Error: Plan metadata is too long or not valid text"),
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn pack_subscription_plan(
     subscription_plan_account_info: &AccountInfo,
     nonce: u8,
     owner: Pubkey,
     authority: Pubkey,
-    token: Pubkey,
-    subscription_timeframe: u64,
-    max_amount: u64,
+    subscription_timeframe: CycleInterval,
+    max_cycles: u64,
+    end_timestamp: UnixTimestamp,
+    charge_upfront: bool,
+    billing_anchor_day: u8,
+    price_feed_owner: Pubkey,
+    accepted_mint: AcceptedMint,
 ) -> ProgramResult {
     let mut subscription_plan = SubscriptionPlan::unpack_unchecked(&subscription_plan_account_info.data.borrow())?;
     if subscription_plan.is_initialized() {
         return Err(ProgramError::AccountAlreadyInitialized);
     }
 
-    subscription_plan.is_initialized = true; // TODO: check
+    subscription_plan.account_type = AccountType::SubscriptionPlan;
+    subscription_plan.version = SUBSCRIPTION_PLAN_VERSION;
+    subscription_plan.is_initialized = true;
     subscription_plan.nonce = nonce;
     subscription_plan.owner = owner;
     subscription_plan.authority = authority;
-    subscription_plan.token = token;
     subscription_plan.subscription_timeframe = subscription_timeframe;
-    subscription_plan.max_amount = max_amount;
+    subscription_plan.max_cycles = max_cycles;
+    subscription_plan.end_timestamp = end_timestamp;
+    subscription_plan.charge_upfront = charge_upfront;
+    subscription_plan.billing_anchor_day = billing_anchor_day;
+    subscription_plan.dispute_window = 0;
+    subscription_plan.arbiter = Pubkey::default();
+    subscription_plan.settlement_interval = 0;
+    subscription_plan.keeper_tip = 0;
+    subscription_plan.metadata = PlanMetadata::default();
+    subscription_plan.price_feed_owner = price_feed_owner;
+    subscription_plan.accepted_mints = vec![accepted_mint];
 
     SubscriptionPlan::pack(subscription_plan, &mut subscription_plan_account_info.data.borrow_mut())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn pack_subscription(
     subscription_account_info: &AccountInfo,
     subscription_plan_account: Pubkey,
     token_account: Pubkey,
-    _fee_account: Pubkey,
     owner: Pubkey,
+    cycle_anchor: UnixTimestamp,
     cycle_start: UnixTimestamp,
-    subscription_timeframe: u64,
-    max_amount: u64,
+    withdrawn_amount: u64,
+    subscription_plan: &SubscriptionPlan,
+    accepted_mint: &AcceptedMint,
+    payment_mode: PaymentMode,
+    vault_account: Pubkey,
+    customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
 ) -> ProgramResult {
     let mut subscription = Subscription::unpack_unchecked(&subscription_account_info.data.borrow())?;
     if subscription.is_initialized() {
         return Err(ProgramError::AccountAlreadyInitialized);
     }
 
+    subscription.account_type = AccountType::Subscription;
+    subscription.version = SUBSCRIPTION_VERSION;
     subscription.is_initialized = true;
-    subscription.is_approved = true; // TODO: check
+    subscription.is_approved = true;
     subscription.subscription_plan_account = subscription_plan_account;
     subscription.token_account = token_account;
+    subscription.mint = accepted_mint.mint;
     subscription.owner = owner;
+    subscription.cycle_anchor = cycle_anchor;
     subscription.cycle_start = cycle_start;
-    subscription.subscription_timeframe = subscription_timeframe;
-    subscription.max_amount = max_amount;
-    subscription.withdrawn_amount = 0;
+    subscription.subscription_timeframe = subscription_plan.subscription_timeframe;
+    subscription.max_cycles = subscription_plan.max_cycles;
+    subscription.end_timestamp = subscription_plan.end_timestamp;
+    subscription.max_amount = accepted_mint.max_amount;
+    subscription.withdrawn_amount = withdrawn_amount;
+    subscription.refunded_amount = 0;
+    subscription.payment_mode = payment_mode;
+    subscription.vault_account = vault_account;
+    subscription.escrowed_amount = 0;
+    subscription.escrow_release = 0;
+    subscription.is_disputed = false;
+    subscription.recent_escrowed_amount = 0;
+    subscription.recent_escrow_release = 0;
+    subscription.customer_reference = customer_reference;
 
     Subscription::pack(subscription, &mut subscription_account_info.data.borrow_mut())
 }
@@ -1,4 +1,17 @@
-pub use self::{subscription::*, subscription_plan::*};
+pub use self::{
+  accepted_mint::*, account_type::*, cycle_interval::*, layout::*, legacy::*, plan_metadata::*, price_feed::*,
+  subscriber_profile::*, subscription::*, subscription_plan::*,
+};
 
+#[macro_use]
+pub mod layout;
+
+pub mod accepted_mint;
+pub mod account_type;
+pub mod cycle_interval;
+pub mod legacy;
+pub mod plan_metadata;
+pub mod price_feed;
+pub mod subscriber_profile;
 pub mod subscription;
 pub mod subscription_plan;
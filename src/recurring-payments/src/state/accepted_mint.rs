@@ -0,0 +1,42 @@
+use crate::state::MaxEncodedSize;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{clock::UnixTimestamp, pubkey::Pubkey};
+
+/// What unit an accepted mint's price is set in
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum PricingMode {
+  /// `max_amount` is in token units
+  #[default]
+  Fixed,
+  /// `max_amount` is in USD cents, converted to token units with the price feed at claim time
+  UsdCents,
+}
+
+impl MaxEncodedSize for PricingMode {
+  const MAX_ENCODED_SIZE: usize = 1;
+}
+
+encoded_struct! {
+  /// A mint a subscription plan can be paid in, with its own price and payout account
+  #[derive(Clone, Debug, Default, PartialEq)]
+  pub struct AcceptedMint {
+    pub mint: Pubkey,                   // token mint, the native mint for SOL
+    pub token_program: Pubkey,          // spl-token or Token-2022, whichever owns the mint
+    pub decimals: u8,                   // mint decimals, read from the mint when it is added to the plan
+    pub payout_account: Pubkey,         // token account that receives claimed funds, a wallet for SOL
+    pub max_amount: u64,                // max amount that can be withdrawn in one timeframe
+    pub setup_fee: u64,                 // charged once when subscribing, in the same unit as max_amount
+    pub pricing_mode: PricingMode,
+    pub price_feed: Pubkey,             // token/USD price feed, only used with USD cents pricing
+    pub escrow_account: Pubkey,         // escrow PDA claims are held in during the dispute window, zero until set
+    pub settlement_vault: Pubkey,       // vault PDA claims collect in until the next settlement, zero until one is set
+    pub last_settlement: UnixTimestamp, // time the settlement vault was last swept to the payout account
+  }
+}
+
+impl AcceptedMint {
+  /// Whether payments are made in native SOL rather than an SPL token
+  pub fn is_native(&self) -> bool {
+    self.mint == spl_token::native_mint::id()
+  }
+}
@@ -0,0 +1,30 @@
+use crate::{error::RecurringPaymentsError, state::MaxEncodedSize};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::program_error::ProgramError;
+
+/// First byte of every account layout, so an account of one kind can't be passed where another is expected
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum AccountType {
+  /// Zeroed account that hasn't been initialized yet
+  #[default]
+  Uninitialized,
+  SubscriptionPlan,
+  Subscription,
+  SubscriberProfile,
+  PriceFeed,
+}
+
+impl AccountType {
+  /// Checks a decoded account is of the `expected` type, only an account waiting to be initialized may have none
+  pub fn check(self, expected: AccountType, is_initialized: bool) -> Result<(), ProgramError> {
+    match self {
+      account_type if account_type == expected => Ok(()),
+      AccountType::Uninitialized if !is_initialized => Ok(()),
+      _ => Err(RecurringPaymentsError::WrongAccountType.into()),
+    }
+  }
+}
+
+impl MaxEncodedSize for AccountType {
+  const MAX_ENCODED_SIZE: usize = 1;
+}
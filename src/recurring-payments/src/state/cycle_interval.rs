@@ -0,0 +1,354 @@
+use crate::{
+  constants::{MAX_BILLING_ANCHOR_DAY, MAX_CYCLE_DURATION, MIN_CYCLE_DURATION, SECONDS_PER_DAY},
+  error::RecurringPaymentsError,
+  state::MaxEncodedSize,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{clock::UnixTimestamp, program_error::ProgramError};
+use std::convert::TryFrom;
+
+/// Length of a billing cycle. Cycles are counted from an anchor timestamp, so calendar intervals keep landing on
+/// the anchor's day of the month instead of drifting.
+///
+/// This is the only place cycle lengths are turned into seconds, callers work with anchors and cycle counts.
+#[derive(Clone, Copy, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum CycleInterval {
+  Seconds(u64),
+  Days(u64),
+  Weeks(u64),
+  /// Same day of the month as the anchor, or the last day of shorter months
+  CalendarMonths(u64),
+  /// Same date as the anchor, February 28th in non leap years for an anchor on February 29th
+  Years(u64),
+}
+
+impl CycleInterval {
+  fn count(&self) -> u64 {
+    match *self {
+      CycleInterval::Seconds(count)
+      | CycleInterval::Days(count)
+      | CycleInterval::Weeks(count)
+      | CycleInterval::CalendarMonths(count)
+      | CycleInterval::Years(count) => count,
+    }
+  }
+
+  /// Length in seconds of fixed intervals, `None` for calendar intervals
+  fn fixed_seconds(&self) -> Option<Result<i64, ProgramError>> {
+    let unit = match *self {
+      CycleInterval::Seconds(_) => 1,
+      CycleInterval::Days(_) => SECONDS_PER_DAY,
+      CycleInterval::Weeks(_) => 7 * SECONDS_PER_DAY,
+      CycleInterval::CalendarMonths(_) | CycleInterval::Years(_) => return None,
+    };
+    Some(
+      i64::try_from(self.count())
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+        .ok_or_else(|| RecurringPaymentsError::Overflow.into()),
+    )
+  }
+
+  /// Number of calendar months per cycle, `None` for fixed intervals
+  fn calendar_months(&self) -> Option<Result<i64, ProgramError>> {
+    let months_per_unit = match *self {
+      CycleInterval::CalendarMonths(_) => 1,
+      CycleInterval::Years(_) => 12,
+      _ => return None,
+    };
+    Some(
+      i64::try_from(self.count())
+        .ok()
+        .and_then(|count| count.checked_mul(months_per_unit))
+        .ok_or_else(|| RecurringPaymentsError::Overflow.into()),
+    )
+  }
+
+  fn check(&self) -> Result<(), ProgramError> {
+    if self.count() == 0 {
+      return Err(RecurringPaymentsError::InvalidSubscriptionTimeframe.into());
+    }
+    Ok(())
+  }
+
+  /// Shortest and longest a single cycle can be, in seconds. Whole years of a calendar interval count 365 to 366
+  /// days and the months left over 28 to 31 days, the same units `MAX_CYCLE_DURATION` is stated in.
+  pub fn duration_range(&self) -> Result<(i64, i64), ProgramError> {
+    self.check()?;
+    if let Some(seconds) = self.fixed_seconds() {
+      let seconds = seconds?;
+      return Ok((seconds, seconds));
+    }
+
+    let months = self
+      .calendar_months()
+      .ok_or(RecurringPaymentsError::InvalidSubscriptionTimeframe)??;
+    let (years, months) = (months / 12, months % 12);
+    let duration = |days_per_year: i64, days_per_month: i64| {
+      years
+        .checked_mul(days_per_year)
+        .and_then(|days| days.checked_add(months * days_per_month))
+        .and_then(|days| days.checked_mul(SECONDS_PER_DAY))
+    };
+    match (duration(365, 28), duration(366, 31)) {
+      (Some(shortest), Some(longest)) => Ok((shortest, longest)),
+      _ => Err(RecurringPaymentsError::Overflow.into()),
+    }
+  }
+
+  /// Checks the interval lies within `MIN_CYCLE_DURATION` and `MAX_CYCLE_DURATION`
+  pub fn check_duration(&self) -> Result<(), ProgramError> {
+    self.check()?;
+    // Only an interval too long to express in seconds can fail from here
+    let (shortest, longest) = self.duration_range().map_err(|_| RecurringPaymentsError::CycleTooLong)?;
+    if shortest < MIN_CYCLE_DURATION {
+      return Err(RecurringPaymentsError::CycleTooShort.into());
+    }
+    if longest > MAX_CYCLE_DURATION {
+      return Err(RecurringPaymentsError::CycleTooLong.into());
+    }
+    Ok(())
+  }
+
+  /// Start of the cycle `cycles` intervals after `anchor`
+  pub fn cycle_start(&self, anchor: UnixTimestamp, cycles: u64) -> Result<UnixTimestamp, ProgramError> {
+    self.check()?;
+    let cycles = i64::try_from(cycles).map_err(|_| RecurringPaymentsError::Overflow)?;
+    if let Some(seconds) = self.fixed_seconds() {
+      return seconds?
+        .checked_mul(cycles)
+        .and_then(|offset| anchor.checked_add(offset))
+        .ok_or_else(|| RecurringPaymentsError::Overflow.into());
+    }
+
+    let months = self
+      .calendar_months()
+      .ok_or(RecurringPaymentsError::InvalidSubscriptionTimeframe)??
+      .checked_mul(cycles)
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    add_months(anchor, months).ok_or_else(|| RecurringPaymentsError::Overflow.into())
+  }
+
+  /// Number of whole cycles between `anchor` and `now`
+  pub fn elapsed_cycles(&self, anchor: UnixTimestamp, now: UnixTimestamp) -> Result<u64, ProgramError> {
+    self.check()?;
+    if now <= anchor {
+      return Ok(0);
+    }
+    if let Some(seconds) = self.fixed_seconds() {
+      return Ok(((now - anchor) / seconds?) as u64);
+    }
+
+    let months_per_cycle = self
+      .calendar_months()
+      .ok_or(RecurringPaymentsError::InvalidSubscriptionTimeframe)??;
+    let (anchor_year, anchor_month, _) = civil_from_days(anchor.div_euclid(SECONDS_PER_DAY));
+    let (now_year, now_month, _) = civil_from_days(now.div_euclid(SECONDS_PER_DAY));
+    let months = (now_year - anchor_year) * 12 + now_month - anchor_month;
+
+    // The cycle starting in the current month may not have begun yet
+    let cycles = (months / months_per_cycle) as u64;
+    if cycles > 0 && self.cycle_start(anchor, cycles)? > now {
+      Ok(cycles - 1)
+    } else {
+      Ok(cycles)
+    }
+  }
+
+  /// Checks billing can be anchored to `day` of the month, which only calendar month cycles support
+  pub fn check_anchor_day(&self, day: u8) -> Result<(), ProgramError> {
+    match self {
+      CycleInterval::CalendarMonths(_) if (1..=MAX_BILLING_ANCHOR_DAY).contains(&day) => Ok(()),
+      _ => Err(RecurringPaymentsError::InvalidBillingAnchor.into()),
+    }
+  }
+
+  /// Midnight UTC of the first `day` of the month at or after `now`
+  pub fn next_anchor_day(&self, now: UnixTimestamp, day: u8) -> Result<UnixTimestamp, ProgramError> {
+    self.check_anchor_day(day)?;
+    let (year, month, _) = civil_from_days(now.div_euclid(SECONDS_PER_DAY));
+    let this_month = days_from_civil(year, month, day as i64)
+      .and_then(|days| days.checked_mul(SECONDS_PER_DAY))
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    if this_month >= now {
+      return Ok(this_month);
+    }
+    add_months(this_month, 1).ok_or_else(|| RecurringPaymentsError::Overflow.into())
+  }
+
+  /// Share of `amount` owed for the partial period from `now` up to `anchor`, measured against the full cycle ending
+  /// at `anchor`. For `CalendarMonths(n)` that is all n months, a two week stub on a quarterly plan owes about a
+  /// sixth of the price rather than half
+  pub fn prorate(&self, amount: u64, anchor: UnixTimestamp, now: UnixTimestamp) -> Result<u64, ProgramError> {
+    let months = self
+      .calendar_months()
+      .ok_or(RecurringPaymentsError::InvalidBillingAnchor)??;
+    let previous_anchor = months
+      .checked_neg()
+      .and_then(|months| add_months(anchor, months))
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    let period = (anchor - now).max(0) as u128;
+    let cycle = (anchor - previous_anchor) as u128;
+
+    // The period is never longer than the cycle, so the result fits back into a u64
+    Ok(((amount as u128) * period.min(cycle) / cycle) as u64)
+  }
+}
+
+/// Moves a timestamp by whole calendar months, clamping the day to the end of shorter months
+fn add_months(timestamp: UnixTimestamp, months: i64) -> Option<UnixTimestamp> {
+  let days = timestamp.div_euclid(SECONDS_PER_DAY);
+  let seconds_of_day = timestamp.rem_euclid(SECONDS_PER_DAY);
+  let (year, month, day) = civil_from_days(days);
+
+  let total_months = year.checked_mul(12)?.checked_add(month - 1)?.checked_add(months)?;
+  let year = total_months.div_euclid(12);
+  let month = total_months.rem_euclid(12) + 1;
+  let day = day.min(days_in_month(year, month));
+
+  days_from_civil(year, month, day)?
+    .checked_mul(SECONDS_PER_DAY)?
+    .checked_add(seconds_of_day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+  year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+  match month {
+    2 if is_leap_year(year) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, see <http://howardhinnant.github.io/date_algorithms.html>
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era.checked_mul(146_097)?.checked_add(day_of_era - 719_468)
+}
+
+/// Inverse of `days_from_civil`, returns (year, month, day)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days - era * 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let shifted_month = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+  let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+  let year = year_of_era + era * 400;
+  (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl MaxEncodedSize for CycleInterval {
+  // unit tag, then the count
+  const MAX_ENCODED_SIZE: usize = 1 + 8;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Midnight UTC of a date, offset by `seconds`
+  fn at(year: i64, month: i64, day: i64, seconds: i64) -> UnixTimestamp {
+    days_from_civil(year, month, day).unwrap() * SECONDS_PER_DAY + seconds
+  }
+
+  #[test]
+  fn month_end_anchors_clamp_without_drifting() {
+    let monthly = CycleInterval::CalendarMonths(1);
+    let anchor = at(2024, 1, 31, 3_600);
+    assert_eq!(monthly.cycle_start(anchor, 1), Ok(at(2024, 2, 29, 3_600)));
+    assert_eq!(monthly.cycle_start(anchor, 2), Ok(at(2024, 3, 31, 3_600)));
+    assert_eq!(monthly.cycle_start(anchor, 3), Ok(at(2024, 4, 30, 3_600)));
+    assert_eq!(monthly.cycle_start(anchor, 13), Ok(at(2025, 2, 28, 3_600)));
+
+    assert_eq!(monthly.elapsed_cycles(anchor, at(2024, 2, 29, 3_599)), Ok(0));
+    assert_eq!(monthly.elapsed_cycles(anchor, at(2024, 2, 29, 3_600)), Ok(1));
+    assert_eq!(monthly.elapsed_cycles(anchor, at(2024, 3, 30, 0)), Ok(1));
+    assert_eq!(monthly.elapsed_cycles(anchor, at(2024, 3, 31, 3_600)), Ok(2));
+  }
+
+  #[test]
+  fn february_29th_anniversaries_fall_on_february_28th_outside_leap_years() {
+    let yearly = CycleInterval::Years(1);
+    let anchor = at(2024, 2, 29, 0);
+    assert_eq!(yearly.cycle_start(anchor, 1), Ok(at(2025, 2, 28, 0)));
+    assert_eq!(yearly.cycle_start(anchor, 3), Ok(at(2027, 2, 28, 0)));
+    assert_eq!(yearly.cycle_start(anchor, 4), Ok(at(2028, 2, 29, 0)));
+    assert_eq!(yearly.cycle_start(anchor, 100), Ok(at(2124, 2, 29, 0)));
+    assert_eq!(yearly.cycle_start(anchor, 76), Ok(at(2100, 2, 28, 0)));
+
+    assert_eq!(yearly.elapsed_cycles(anchor, at(2025, 2, 27, 0)), Ok(0));
+    assert_eq!(yearly.elapsed_cycles(anchor, at(2025, 2, 28, 0)), Ok(1));
+    assert_eq!(yearly.elapsed_cycles(anchor, at(2028, 2, 28, 0)), Ok(3));
+    assert_eq!(yearly.elapsed_cycles(anchor, at(2028, 2, 29, 0)), Ok(4));
+  }
+
+  #[test]
+  fn anchors_before_1970_keep_their_time_of_day() {
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    assert_eq!(civil_from_days(days_from_civil(1900, 2, 28).unwrap()), (1900, 2, 28));
+
+    let monthly = CycleInterval::CalendarMonths(1);
+    let anchor = at(1969, 12, 31, 43_200);
+    assert!(anchor < 0);
+    assert_eq!(monthly.cycle_start(anchor, 1), Ok(at(1970, 1, 31, 43_200)));
+    assert_eq!(monthly.cycle_start(anchor, 2), Ok(at(1970, 2, 28, 43_200)));
+    assert_eq!(monthly.elapsed_cycles(anchor, at(1970, 1, 31, 43_199)), Ok(0));
+    assert_eq!(monthly.elapsed_cycles(anchor, at(1970, 2, 28, 43_200)), Ok(2));
+
+    // 1960 was a leap year, 1900 was not
+    let anchor = at(1960, 1, 31, 0);
+    assert_eq!(monthly.cycle_start(anchor, 1), Ok(at(1960, 2, 29, 0)));
+    assert_eq!(CycleInterval::Years(1).cycle_start(at(1896, 2, 29, 0), 4), Ok(at(1900, 2, 28, 0)));
+  }
+
+  #[test]
+  fn durations_are_bounded_in_366_day_years_and_31_day_months() {
+    assert_eq!(CycleInterval::Years(10).check_duration(), Ok(()));
+    assert_eq!(CycleInterval::CalendarMonths(120).check_duration(), Ok(()));
+    assert_eq!(CycleInterval::Days(10 * 366).check_duration(), Ok(()));
+    assert_eq!(CycleInterval::Years(11).check_duration(), Err(RecurringPaymentsError::CycleTooLong.into()));
+    assert_eq!(CycleInterval::CalendarMonths(121).check_duration(), Err(RecurringPaymentsError::CycleTooLong.into()));
+    assert_eq!(CycleInterval::Days(10 * 366 + 1).check_duration(), Err(RecurringPaymentsError::CycleTooLong.into()));
+    assert_eq!(CycleInterval::Years(u64::MAX).check_duration(), Err(RecurringPaymentsError::CycleTooLong.into()));
+
+    assert_eq!(CycleInterval::Seconds(3_599).check_duration(), Err(RecurringPaymentsError::CycleTooShort.into()));
+    assert_eq!(CycleInterval::Seconds(3_600).check_duration(), Ok(()));
+    assert_eq!(CycleInterval::Years(1).duration_range(), Ok((365 * SECONDS_PER_DAY, 366 * SECONDS_PER_DAY)));
+    assert_eq!(CycleInterval::CalendarMonths(13).duration_range(), Ok((393 * SECONDS_PER_DAY, 397 * SECONDS_PER_DAY)));
+  }
+
+  #[test]
+  fn stubs_are_prorated_against_the_whole_cycle() {
+    let now = at(2024, 1, 15, 0);
+    let monthly = CycleInterval::CalendarMonths(1);
+    let anchor = monthly.next_anchor_day(now, 1).unwrap();
+    assert_eq!(anchor, at(2024, 2, 1, 0));
+    // 17 of January's 31 days
+    assert_eq!(monthly.prorate(3_100, anchor, now), Ok(1_700));
+
+    // The same 17 days on a quarterly plan, out of the 92 from November 1st to February 1st
+    let quarterly = CycleInterval::CalendarMonths(3);
+    assert_eq!(quarterly.next_anchor_day(now, 1), Ok(anchor));
+    assert_eq!(quarterly.prorate(9_200, anchor, now), Ok(1_700));
+
+    // Signing up on the anchor day itself owes nothing for the stub
+    assert_eq!(monthly.next_anchor_day(at(2024, 2, 1, 0), 1), Ok(anchor));
+    assert_eq!(monthly.prorate(3_100, anchor, anchor), Ok(0));
+    assert_eq!(
+      CycleInterval::Days(30).next_anchor_day(now, 1),
+      Err(RecurringPaymentsError::InvalidBillingAnchor.into())
+    );
+    assert_eq!(monthly.check_anchor_day(29), Err(RecurringPaymentsError::InvalidBillingAnchor.into()));
+  }
+}
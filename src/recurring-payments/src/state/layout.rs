@@ -0,0 +1,68 @@
+//! Account layouts are Borsh encodings of the state structs, fields in declaration order. Fields evolve like this:
+//!
+//! - Never reorder, resize or change the meaning of an existing field, live accounts keep their bytes.
+//! - A small new field is carved out of the front of the struct's `reserved` bytes: declare it just before
+//!   `reserved` and shrink the reserved size constant by its encoded size, so the account size stays the same.
+//! - Reserved bytes are zero in every account, so a carved out field must read zero as "not set" and keep the
+//!   behaviour accounts had before it existed. No migration is needed.
+//! - A field that doesn't fit the reserved bytes, or can't default to zero, bumps the layout version in
+//!   `constants.rs` and teaches MigrateAccount to rewrite accounts of the previous version.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::mem::size_of;
+
+/// Largest number of bytes a value takes up once Borsh encoded. Account sizes are summed from their field types at
+/// compile time, so a new field can't be left out of the size the way a hand counted layout could.
+pub trait MaxEncodedSize {
+  const MAX_ENCODED_SIZE: usize;
+}
+
+macro_rules! impl_max_encoded_size {
+  ($($ty:ty),*) => {
+    $(impl MaxEncodedSize for $ty {
+      const MAX_ENCODED_SIZE: usize = size_of::<$ty>();
+    })*
+  };
+}
+
+impl_max_encoded_size!(bool, u8, i32, u64, i64, Pubkey);
+
+impl<T: MaxEncodedSize, const N: usize> MaxEncodedSize for [T; N] {
+  const MAX_ENCODED_SIZE: usize = N * T::MAX_ENCODED_SIZE;
+}
+
+/// Declares a struct that is Borsh encoded field by field, in declaration order, implementing `MaxEncodedSize` as
+/// the sum of its field types
+macro_rules! encoded_struct {
+  (
+    $(#[$attr:meta])*
+    pub struct $name:ident {
+      $($(#[$field_attr:meta])* pub $field:ident: $ty:ty,)*
+    }
+  ) => {
+    $(#[$attr])*
+    #[derive(::borsh::BorshSerialize, ::borsh::BorshDeserialize)]
+    pub struct $name {
+      $($(#[$field_attr])* pub $field: $ty,)*
+    }
+
+    impl $crate::state::MaxEncodedSize for $name {
+      const MAX_ENCODED_SIZE: usize = 0 $(+ <$ty as $crate::state::MaxEncodedSize>::MAX_ENCODED_SIZE)*;
+    }
+  };
+}
+
+/// Decodes an account, the bytes after the encoding are left over space and ignored
+pub fn unpack_encoded<T: BorshDeserialize>(src: &[u8]) -> Result<T, ProgramError> {
+  T::deserialize(&mut &src[..]).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// Encodes an account, zeroing the bytes after the encoding
+pub fn pack_encoded<T: BorshSerialize>(value: &T, dst: &mut [u8]) {
+  dst.fill(0);
+  // `Pack::pack` only hands over buffers of `Pack::LEN` bytes, the largest encoding of every account
+  value
+    .serialize(&mut &mut dst[..])
+    .expect("account buffer is smaller than its largest encoding");
+}
@@ -0,0 +1,439 @@
+use crate::{
+  constants::{
+    CUSTOMER_REFERENCE_LEN, LEGACY_SUBSCRIPTION_PLAN_SIZE, LEGACY_SUBSCRIPTION_SIZE, SUBSCRIPTION_PLAN_RESERVED_SIZE,
+    SUBSCRIPTION_PLAN_V1_SIZE, SUBSCRIPTION_PLAN_V2_SIZE, SUBSCRIPTION_PLAN_VERSION, SUBSCRIPTION_RESERVED_SIZE,
+    SUBSCRIPTION_V1_SIZE, SUBSCRIPTION_VERSION,
+  },
+  error::RecurringPaymentsError,
+  state::{
+    pack_encoded, unpack_encoded, AcceptedMint, AccountType, CycleInterval, PaymentMode, PlanMetadata, Subscription,
+    SubscriptionPlan,
+  },
+};
+use solana_program::{
+  clock::UnixTimestamp,
+  program_error::ProgramError,
+  program_pack::{IsInitialized, Pack, Sealed},
+  pubkey::Pubkey,
+};
+
+encoded_struct! {
+  /// Version 0 subscription plan, written before accounts had a type and version. MigrateAccount rewrites it into
+  /// the current `SubscriptionPlan` layout.
+  #[derive(Debug)]
+  pub struct LegacySubscriptionPlan {
+    pub is_initialized: bool,
+    pub nonce: u8,
+    pub owner: Pubkey,
+    pub authority: Pubkey,
+    pub token: Pubkey,               // owner's token account that receives claimed funds
+    pub subscription_timeframe: u64, // length of a billing cycle in days
+    pub max_amount: u64,             // max amount that can be withdrawn in one timeframe
+  }
+}
+
+encoded_struct! {
+  /// Version 0 subscription, rewritten into the current `Subscription` layout by MigrateAccount
+  #[derive(Debug)]
+  pub struct LegacySubscription {
+    pub is_initialized: bool,
+    pub is_approved: bool,
+    pub subscription_plan_account: Pubkey,
+    pub token_account: Pubkey,
+    pub owner: Pubkey,               // owner of the plan, not the subscriber
+    pub cycle_start: UnixTimestamp,  // start of the subscription cycle
+    pub subscription_timeframe: u64, // length of a billing cycle in days
+    pub max_amount: u64,             // max amount that can be withdrawn in one timeframe
+    pub withdrawn_amount: u64,       // amount that has been withdrawn so far this timeframe
+  }
+}
+
+encoded_struct! {
+  /// Version 1 subscription plan, the first layout with a type and version, before reserved bytes were added
+  #[derive(Debug)]
+  pub struct SubscriptionPlanV1 {
+    pub account_type: AccountType,
+    pub version: u8,
+    pub is_initialized: bool,
+    pub nonce: u8,
+    pub owner: Pubkey,
+    pub authority: Pubkey,
+    pub subscription_timeframe: CycleInterval,
+    pub max_cycles: u64,
+    pub end_timestamp: UnixTimestamp,
+    pub charge_upfront: bool,
+    pub billing_anchor_day: u8,
+    pub dispute_window: i64,
+    pub arbiter: Pubkey,
+    pub settlement_interval: i64,
+    pub keeper_tip: u64,
+    pub price_feed_owner: Pubkey,
+    pub accepted_mints: Vec<AcceptedMint>,
+  }
+}
+
+encoded_struct! {
+  /// Version 2 subscription plan, the current layout without the plan metadata
+  #[derive(Debug)]
+  pub struct SubscriptionPlanV2 {
+    pub account_type: AccountType,
+    pub version: u8,
+    pub is_initialized: bool,
+    pub nonce: u8,
+    pub owner: Pubkey,
+    pub authority: Pubkey,
+    pub subscription_timeframe: CycleInterval,
+    pub max_cycles: u64,
+    pub end_timestamp: UnixTimestamp,
+    pub charge_upfront: bool,
+    pub billing_anchor_day: u8,
+    pub dispute_window: i64,
+    pub arbiter: Pubkey,
+    pub settlement_interval: i64,
+    pub keeper_tip: u64,
+    pub price_feed_owner: Pubkey,
+    pub reserved: [u8; SUBSCRIPTION_PLAN_RESERVED_SIZE],
+    pub accepted_mints: Vec<AcceptedMint>,
+  }
+}
+
+encoded_struct! {
+  /// Version 1 subscription, the current layout before reserved bytes were added
+  #[derive(Debug)]
+  pub struct SubscriptionV1 {
+    pub account_type: AccountType,
+    pub version: u8,
+    pub is_initialized: bool,
+    pub is_approved: bool,
+    pub subscription_plan_account: Pubkey,
+    pub token_account: Pubkey,
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub cycle_anchor: UnixTimestamp,
+    pub cycle_start: UnixTimestamp,
+    pub subscription_timeframe: CycleInterval,
+    pub max_cycles: u64,
+    pub end_timestamp: UnixTimestamp,
+    pub max_amount: u64,
+    pub withdrawn_amount: u64,
+    pub refunded_amount: u64,
+    pub payment_mode: PaymentMode,
+    pub vault_account: Pubkey,
+    pub escrowed_amount: u64,
+    pub escrow_release: UnixTimestamp,
+    pub is_disputed: bool,
+    pub recent_escrowed_amount: u64,
+    pub recent_escrow_release: UnixTimestamp,
+  }
+}
+
+impl From<SubscriptionPlanV1> for SubscriptionPlan {
+  fn from(subscription_plan: SubscriptionPlanV1) -> Self {
+    SubscriptionPlan {
+      account_type: AccountType::SubscriptionPlan,
+      version: SUBSCRIPTION_PLAN_VERSION,
+      is_initialized: subscription_plan.is_initialized,
+      nonce: subscription_plan.nonce,
+      owner: subscription_plan.owner,
+      authority: subscription_plan.authority,
+      subscription_timeframe: subscription_plan.subscription_timeframe,
+      max_cycles: subscription_plan.max_cycles,
+      end_timestamp: subscription_plan.end_timestamp,
+      charge_upfront: subscription_plan.charge_upfront,
+      billing_anchor_day: subscription_plan.billing_anchor_day,
+      dispute_window: subscription_plan.dispute_window,
+      arbiter: subscription_plan.arbiter,
+      settlement_interval: subscription_plan.settlement_interval,
+      keeper_tip: subscription_plan.keeper_tip,
+      metadata: PlanMetadata::default(),
+      price_feed_owner: subscription_plan.price_feed_owner,
+      reserved: [0; SUBSCRIPTION_PLAN_RESERVED_SIZE],
+      accepted_mints: subscription_plan.accepted_mints,
+    }
+  }
+}
+
+impl From<SubscriptionPlanV2> for SubscriptionPlan {
+  fn from(subscription_plan: SubscriptionPlanV2) -> Self {
+    SubscriptionPlan {
+      account_type: AccountType::SubscriptionPlan,
+      version: SUBSCRIPTION_PLAN_VERSION,
+      is_initialized: subscription_plan.is_initialized,
+      nonce: subscription_plan.nonce,
+      owner: subscription_plan.owner,
+      authority: subscription_plan.authority,
+      subscription_timeframe: subscription_plan.subscription_timeframe,
+      max_cycles: subscription_plan.max_cycles,
+      end_timestamp: subscription_plan.end_timestamp,
+      charge_upfront: subscription_plan.charge_upfront,
+      billing_anchor_day: subscription_plan.billing_anchor_day,
+      dispute_window: subscription_plan.dispute_window,
+      arbiter: subscription_plan.arbiter,
+      settlement_interval: subscription_plan.settlement_interval,
+      keeper_tip: subscription_plan.keeper_tip,
+      metadata: PlanMetadata::default(),
+      price_feed_owner: subscription_plan.price_feed_owner,
+      reserved: subscription_plan.reserved,
+      accepted_mints: subscription_plan.accepted_mints,
+    }
+  }
+}
+
+impl From<SubscriptionV1> for Subscription {
+  fn from(subscription: SubscriptionV1) -> Self {
+    Subscription {
+      account_type: AccountType::Subscription,
+      version: SUBSCRIPTION_VERSION,
+      is_initialized: subscription.is_initialized,
+      is_approved: subscription.is_approved,
+      subscription_plan_account: subscription.subscription_plan_account,
+      token_account: subscription.token_account,
+      mint: subscription.mint,
+      owner: subscription.owner,
+      cycle_anchor: subscription.cycle_anchor,
+      cycle_start: subscription.cycle_start,
+      subscription_timeframe: subscription.subscription_timeframe,
+      max_cycles: subscription.max_cycles,
+      end_timestamp: subscription.end_timestamp,
+      max_amount: subscription.max_amount,
+      withdrawn_amount: subscription.withdrawn_amount,
+      refunded_amount: subscription.refunded_amount,
+      payment_mode: subscription.payment_mode,
+      vault_account: subscription.vault_account,
+      escrowed_amount: subscription.escrowed_amount,
+      escrow_release: subscription.escrow_release,
+      is_disputed: subscription.is_disputed,
+      recent_escrowed_amount: subscription.recent_escrowed_amount,
+      recent_escrow_release: subscription.recent_escrow_release,
+      customer_reference: [0; CUSTOMER_REFERENCE_LEN],
+      reserved: [0; SUBSCRIPTION_RESERVED_SIZE],
+    }
+  }
+}
+
+impl Sealed for LegacySubscriptionPlan {}
+
+impl IsInitialized for LegacySubscriptionPlan {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for LegacySubscriptionPlan {
+  const LEN: usize = LEGACY_SUBSCRIPTION_PLAN_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    unpack_encoded(src)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+impl Sealed for LegacySubscription {}
+
+impl IsInitialized for LegacySubscription {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for LegacySubscription {
+  const LEN: usize = LEGACY_SUBSCRIPTION_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    unpack_encoded(src)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+impl Sealed for SubscriptionPlanV1 {}
+
+impl IsInitialized for SubscriptionPlanV1 {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for SubscriptionPlanV1 {
+  const LEN: usize = SUBSCRIPTION_PLAN_V1_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let subscription_plan: SubscriptionPlanV1 = unpack_encoded(src)?;
+    subscription_plan
+      .account_type
+      .check(AccountType::SubscriptionPlan, subscription_plan.is_initialized)?;
+    if subscription_plan.version != 1 {
+      return Err(RecurringPaymentsError::UnsupportedAccountVersion.into());
+    }
+    Ok(subscription_plan)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+impl Sealed for SubscriptionPlanV2 {}
+
+impl IsInitialized for SubscriptionPlanV2 {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for SubscriptionPlanV2 {
+  const LEN: usize = SUBSCRIPTION_PLAN_V2_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let subscription_plan: SubscriptionPlanV2 = unpack_encoded(src)?;
+    subscription_plan
+      .account_type
+      .check(AccountType::SubscriptionPlan, subscription_plan.is_initialized)?;
+    if subscription_plan.version != 2 {
+      return Err(RecurringPaymentsError::UnsupportedAccountVersion.into());
+    }
+    Ok(subscription_plan)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+impl Sealed for SubscriptionV1 {}
+
+impl IsInitialized for SubscriptionV1 {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for SubscriptionV1 {
+  const LEN: usize = SUBSCRIPTION_V1_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let subscription: SubscriptionV1 = unpack_encoded(src)?;
+    subscription.account_type.check(AccountType::Subscription, subscription.is_initialized)?;
+    if subscription.version != 1 {
+      return Err(RecurringPaymentsError::UnsupportedAccountVersion.into());
+    }
+    Ok(subscription)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::constants::{SUBSCRIPTION_PLAN_SIZE, SUBSCRIPTION_SIZE};
+
+  #[test]
+  fn layout_versions_have_distinct_sizes() {
+    // MigrateAccount tells the layout of an account by its size alone
+    let mut sizes = vec![
+      LEGACY_SUBSCRIPTION_PLAN_SIZE,
+      LEGACY_SUBSCRIPTION_SIZE,
+      SUBSCRIPTION_PLAN_V1_SIZE,
+      SUBSCRIPTION_PLAN_V2_SIZE,
+      SUBSCRIPTION_V1_SIZE,
+      SUBSCRIPTION_PLAN_SIZE,
+      SUBSCRIPTION_SIZE,
+    ];
+    sizes.sort_unstable();
+    sizes.dedup();
+    assert_eq!(sizes.len(), 7);
+  }
+
+  #[test]
+  fn version_1_plan_migrates() {
+    let accepted_mint = AcceptedMint {
+      mint: Pubkey::new_unique(),
+      max_amount: 1_000,
+      ..AcceptedMint::default()
+    };
+    let v1 = SubscriptionPlanV1 {
+      account_type: AccountType::SubscriptionPlan,
+      version: 1,
+      is_initialized: true,
+      nonce: 255,
+      owner: Pubkey::new_unique(),
+      authority: Pubkey::new_unique(),
+      subscription_timeframe: CycleInterval::CalendarMonths(1),
+      max_cycles: 12,
+      end_timestamp: 0,
+      charge_upfront: true,
+      billing_anchor_day: 1,
+      dispute_window: 0,
+      arbiter: Pubkey::default(),
+      settlement_interval: 0,
+      keeper_tip: 5_000,
+      price_feed_owner: Pubkey::default(),
+      accepted_mints: vec![accepted_mint.clone()],
+    };
+    let mut data = vec![0; SUBSCRIPTION_PLAN_V1_SIZE];
+    SubscriptionPlanV1::pack(v1, &mut data).unwrap();
+
+    let subscription_plan: SubscriptionPlan = SubscriptionPlanV1::unpack(&data).unwrap().into();
+    let mut data = vec![0; SUBSCRIPTION_PLAN_SIZE];
+    SubscriptionPlan::pack(subscription_plan, &mut data).unwrap();
+    let subscription_plan = SubscriptionPlan::unpack(&data).unwrap();
+
+    assert_eq!(subscription_plan.version, SUBSCRIPTION_PLAN_VERSION);
+    assert_eq!(subscription_plan.max_cycles, 12);
+    assert_eq!(subscription_plan.keeper_tip, 5_000);
+    assert_eq!(subscription_plan.metadata, PlanMetadata::default());
+    assert_eq!(subscription_plan.accepted_mints, vec![accepted_mint]);
+  }
+
+  #[test]
+  fn version_1_subscription_migrates() {
+    let owner = Pubkey::new_unique();
+    let v1 = SubscriptionV1 {
+      account_type: AccountType::Subscription,
+      version: 1,
+      is_initialized: true,
+      is_approved: true,
+      subscription_plan_account: Pubkey::new_unique(),
+      token_account: Pubkey::new_unique(),
+      mint: Pubkey::new_unique(),
+      owner,
+      cycle_anchor: 1_700_000_000,
+      cycle_start: 1_700_000_000,
+      subscription_timeframe: CycleInterval::Days(30),
+      max_cycles: 0,
+      end_timestamp: 0,
+      max_amount: 1_000,
+      withdrawn_amount: 400,
+      refunded_amount: 0,
+      payment_mode: PaymentMode::Delegate,
+      vault_account: Pubkey::default(),
+      escrowed_amount: 30,
+      escrow_release: 1_700_600_000,
+      is_disputed: false,
+      recent_escrowed_amount: 0,
+      recent_escrow_release: 0,
+    };
+    let mut data = vec![0; SUBSCRIPTION_V1_SIZE];
+    SubscriptionV1::pack(v1, &mut data).unwrap();
+
+    let subscription: Subscription = SubscriptionV1::unpack(&data).unwrap().into();
+    let mut data = vec![0; SUBSCRIPTION_SIZE];
+    Subscription::pack(subscription, &mut data).unwrap();
+    let subscription = Subscription::unpack(&data).unwrap();
+
+    assert_eq!(subscription.version, SUBSCRIPTION_VERSION);
+    assert_eq!(subscription.owner, owner);
+    assert_eq!(subscription.subscription_timeframe, CycleInterval::Days(30));
+    assert_eq!(subscription.withdrawn_amount, 400);
+    assert_eq!(subscription.escrowed_amount, 30);
+    assert_eq!(subscription.customer_reference(), None);
+  }
+}
@@ -0,0 +1,114 @@
+use crate::{
+  constants::{MAX_PLAN_EXTERNAL_ID_LEN, MAX_PLAN_NAME_LEN, MAX_PLAN_URI_LEN},
+  error::RecurringPaymentsError,
+};
+use solana_program::program_error::ProgramError;
+use std::str;
+
+encoded_struct! {
+  /// Human readable description of a plan for wallets to show at signup. Each field is UTF-8 padded with zeros to
+  /// its fixed size, all zeros when unset.
+  #[derive(Clone, Debug, PartialEq)]
+  pub struct PlanMetadata {
+    pub name: [u8; MAX_PLAN_NAME_LEN],               // display name of the plan
+    pub uri: [u8; MAX_PLAN_URI_LEN],                 // off-chain JSON with the full description, logo and terms
+    pub external_id: [u8; MAX_PLAN_EXTERNAL_ID_LEN], // merchant's own product or price ID in their catalog
+  }
+}
+
+impl PlanMetadata {
+  pub fn new(name: &str, uri: &str, external_id: &str) -> Result<Self, ProgramError> {
+    Ok(PlanMetadata {
+      name: pad(name)?,
+      uri: pad(uri)?,
+      external_id: pad(external_id)?,
+    })
+  }
+
+  pub fn name(&self) -> &str {
+    unpad(&self.name)
+  }
+
+  pub fn uri(&self) -> &str {
+    unpad(&self.uri)
+  }
+
+  pub fn external_id(&self) -> &str {
+    unpad(&self.external_id)
+  }
+}
+
+impl Default for PlanMetadata {
+  fn default() -> Self {
+    PlanMetadata {
+      name: [0; MAX_PLAN_NAME_LEN],
+      uri: [0; MAX_PLAN_URI_LEN],
+      external_id: [0; MAX_PLAN_EXTERNAL_ID_LEN],
+    }
+  }
+}
+
+/// Zero pads a string to a fixed size field, a zero byte inside it would cut it short when read back
+fn pad<const N: usize>(value: &str) -> Result<[u8; N], ProgramError> {
+  if value.len() > N || value.contains('\0') {
+    return Err(RecurringPaymentsError::InvalidPlanMetadata.into());
+  }
+  let mut field = [0; N];
+  field[..value.len()].copy_from_slice(value.as_bytes());
+  Ok(field)
+}
+
+fn unpad(field: &[u8]) -> &str {
+  let len = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+  str::from_utf8(&field[..len]).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::state::{pack_encoded, unpack_encoded, MaxEncodedSize};
+
+  #[test]
+  fn pad_rejects_long_values_and_zero_bytes() {
+    let longest = "n".repeat(MAX_PLAN_NAME_LEN);
+    assert_eq!(pad::<MAX_PLAN_NAME_LEN>(&longest).map(|field| field.to_vec()), Ok(longest.clone().into_bytes()));
+    assert_eq!(
+      pad::<MAX_PLAN_NAME_LEN>(&format!("{}n", longest)),
+      Err(RecurringPaymentsError::InvalidPlanMetadata.into())
+    );
+    assert_eq!(
+      pad::<MAX_PLAN_NAME_LEN>("Pro\0Plus"),
+      Err(RecurringPaymentsError::InvalidPlanMetadata.into())
+    );
+    // Lengths count bytes, not characters
+    assert_eq!(
+      PlanMetadata::new(&"é".repeat(MAX_PLAN_NAME_LEN / 2 + 1), "", ""),
+      Err(RecurringPaymentsError::InvalidPlanMetadata.into())
+    );
+  }
+
+  #[test]
+  fn metadata_round_trips() {
+    let metadata = PlanMetadata::new("Pro", "https://example.com/pro.json", "price_123").unwrap();
+    let mut data = [0xff; PlanMetadata::MAX_ENCODED_SIZE];
+    pack_encoded(&metadata, &mut data);
+    assert_eq!(borsh::to_vec(&metadata).unwrap(), data.to_vec());
+
+    let unpacked: PlanMetadata = unpack_encoded(&data).unwrap();
+    assert_eq!(unpacked, metadata);
+    assert_eq!(unpacked.name(), "Pro");
+    assert_eq!(unpacked.uri(), "https://example.com/pro.json");
+    assert_eq!(unpacked.external_id(), "price_123");
+
+    let unset: PlanMetadata = unpack_encoded(&[0; PlanMetadata::MAX_ENCODED_SIZE]).unwrap();
+    assert_eq!(unset, PlanMetadata::default());
+    assert_eq!(unset.name(), "");
+  }
+
+  #[test]
+  fn size_is_the_sum_of_the_fields() {
+    // The metadata is part of every plan account, its size only changes along with a migration
+    assert_eq!(PlanMetadata::MAX_ENCODED_SIZE, 296);
+    assert_eq!(borsh::to_vec(&PlanMetadata::default()).unwrap().len(), PlanMetadata::MAX_ENCODED_SIZE);
+  }
+}
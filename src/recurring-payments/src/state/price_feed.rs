@@ -0,0 +1,174 @@
+use crate::{
+  constants::{MAX_PRICE_CONFIDENCE_BPS, MAX_PRICE_FEED_AGE, PRICE_FEED_SIZE},
+  error::RecurringPaymentsError,
+  state::{pack_encoded, unpack_encoded, AccountType},
+};
+use solana_program::{
+  clock::UnixTimestamp,
+  program_error::ProgramError,
+  program_pack::{IsInitialized, Pack, Sealed},
+};
+use std::convert::TryInto;
+
+encoded_struct! {
+  /// Token/USD price account layout the program reads at claim time. One whole token is worth
+  /// `price * 10^exponent` USD, give or take `confidence` in the same units. Plans pin the feed's address and the
+  /// program that owns it, so whoever writes the account is trusted by the merchant that picked it. Writers start the
+  /// account with the `AccountType::PriceFeed` byte.
+  #[derive(Debug)]
+  pub struct PriceFeed {
+    pub account_type: AccountType,
+    pub is_initialized: bool,
+    pub price: i64,
+    pub confidence: u64,
+    pub exponent: i32,
+    pub publish_time: UnixTimestamp,
+  }
+}
+
+impl PriceFeed {
+  /// Checks the price is positive, recent and tight enough to charge with
+  pub fn check(&self, now: UnixTimestamp) -> Result<(), ProgramError> {
+    if self.price <= 0 {
+      return Err(RecurringPaymentsError::InvalidPriceFeed.into());
+    }
+    if self.publish_time > now {
+      return Err(RecurringPaymentsError::PriceFeedFromFuture.into());
+    }
+    if now - self.publish_time > MAX_PRICE_FEED_AGE {
+      return Err(RecurringPaymentsError::StalePriceFeed.into());
+    }
+    if (self.confidence as u128) * 10_000 > (self.price as u128) * (MAX_PRICE_CONFIDENCE_BPS as u128) {
+      return Err(RecurringPaymentsError::PriceConfidenceTooWide.into());
+    }
+    Ok(())
+  }
+
+  /// Converts USD cents into token units of a mint with `decimals`, rounding down
+  pub fn usd_cents_to_token_amount(&self, cents: u64, decimals: u8) -> Result<u64, ProgramError> {
+    // token units = cents * 10^decimals / (100 * price * 10^exponent)
+    let mut numerator = (cents as u128)
+      .checked_mul(
+        10u128
+          .checked_pow(decimals as u32)
+          .ok_or(RecurringPaymentsError::Overflow)?,
+      )
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    let mut denominator = (self.price as u128)
+      .checked_mul(100)
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    let scale = 10u128
+      .checked_pow(self.exponent.unsigned_abs())
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    if self.exponent < 0 {
+      numerator = numerator.checked_mul(scale).ok_or(RecurringPaymentsError::Overflow)?;
+    } else {
+      denominator = denominator.checked_mul(scale).ok_or(RecurringPaymentsError::Overflow)?;
+    }
+
+    (numerator / denominator)
+      .try_into()
+      .map_err(|_| RecurringPaymentsError::Overflow.into())
+  }
+}
+
+impl Sealed for PriceFeed {}
+
+impl IsInitialized for PriceFeed {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for PriceFeed {
+  const LEN: usize = PRICE_FEED_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let price_feed: PriceFeed = unpack_encoded(src)?;
+    price_feed.account_type.check(AccountType::PriceFeed, price_feed.is_initialized)?;
+    Ok(price_feed)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NOW: UnixTimestamp = 1_700_000_000;
+
+  /// Feed a mock oracle publishes, $1.50 per token with a tight confidence interval
+  fn mock_feed(publish_time: UnixTimestamp) -> PriceFeed {
+    PriceFeed {
+      account_type: AccountType::PriceFeed,
+      is_initialized: true,
+      price: 150_000_000,
+      confidence: 10_000,
+      exponent: -8,
+      publish_time,
+    }
+  }
+
+  #[test]
+  fn stale_feeds_are_rejected() {
+    assert_eq!(mock_feed(NOW - MAX_PRICE_FEED_AGE).check(NOW), Ok(()));
+    assert_eq!(
+      mock_feed(NOW - MAX_PRICE_FEED_AGE - 1).check(NOW),
+      Err(RecurringPaymentsError::StalePriceFeed.into())
+    );
+  }
+
+  #[test]
+  fn feeds_published_in_the_future_are_rejected() {
+    assert_eq!(mock_feed(NOW).check(NOW), Ok(()));
+    assert_eq!(
+      mock_feed(NOW + 1).check(NOW),
+      Err(RecurringPaymentsError::PriceFeedFromFuture.into())
+    );
+  }
+
+  #[test]
+  fn wide_confidence_intervals_are_rejected() {
+    let mut price_feed = mock_feed(NOW);
+    price_feed.confidence = price_feed.price as u64 * MAX_PRICE_CONFIDENCE_BPS / 10_000;
+    assert_eq!(price_feed.check(NOW), Ok(()));
+    price_feed.confidence += 1;
+    assert_eq!(
+      price_feed.check(NOW),
+      Err(RecurringPaymentsError::PriceConfidenceTooWide.into())
+    );
+
+    price_feed.price = 0;
+    assert_eq!(
+      price_feed.check(NOW),
+      Err(RecurringPaymentsError::InvalidPriceFeed.into())
+    );
+  }
+
+  #[test]
+  fn cents_convert_to_token_units_rounding_down() {
+    // $1.00 at $1.50 a token is 0.666666 of a 6 decimal token
+    assert_eq!(mock_feed(NOW).usd_cents_to_token_amount(100, 6), Ok(666_666));
+    assert_eq!(mock_feed(NOW).usd_cents_to_token_amount(150, 6), Ok(1_000_000));
+
+    // $1.00 at $300 a token, with a positive exponent
+    let mut price_feed = mock_feed(NOW);
+    price_feed.price = 3;
+    price_feed.exponent = 2;
+    assert_eq!(price_feed.usd_cents_to_token_amount(100, 9), Ok(3_333_333));
+  }
+
+  #[test]
+  fn feeds_round_trip_through_their_account_layout() {
+    let mut data = [0; PRICE_FEED_SIZE];
+    PriceFeed::pack(mock_feed(NOW), &mut data).unwrap();
+    let price_feed = PriceFeed::unpack(&data).unwrap();
+    assert_eq!(price_feed.price, 150_000_000);
+    assert_eq!(price_feed.confidence, 10_000);
+    assert_eq!(price_feed.exponent, -8);
+    assert_eq!(price_feed.publish_time, NOW);
+  }
+}
@@ -0,0 +1,108 @@
+use crate::{
+  constants::SUBSCRIBER_PROFILE_SIZE,
+  error::RecurringPaymentsError,
+  state::{pack_encoded, unpack_encoded, AccountType, CycleInterval},
+};
+use solana_program::{
+  clock::UnixTimestamp,
+  program_error::ProgramError,
+  program_pack::{IsInitialized, Pack, Sealed},
+  pubkey::Pubkey,
+};
+
+encoded_struct! {
+  /// Per-wallet spending cap, a PDA of the subscriber's wallet. Every claim against any of the wallet's
+  /// subscriptions counts towards the same monthly cap, which is counted in `mint`.
+  #[derive(Debug)]
+  pub struct SubscriberProfile {
+    pub account_type: AccountType,
+    pub is_initialized: bool,
+    pub bump: u8,
+    pub owner: Pubkey,                // subscriber wallet the profile belongs to
+    pub mint: Pubkey,                 // mint the cap is counted in, claims in other mints are rejected
+    pub monthly_cap: u64,             // max amount all subscriptions together can claim in one period
+    pub period_anchor: UnixTimestamp, // periods are calendar months counted from here
+    pub period_start: UnixTimestamp,  // start of the current period
+    pub spent_this_period: u64,       // amount claimed so far this period
+  }
+}
+
+impl SubscriberProfile {
+  const PERIOD: CycleInterval = CycleInterval::CalendarMonths(1);
+
+  /// Moves to the period containing `now`, resetting the spent amount if a new one has begun
+  pub fn roll_period(&mut self, now: UnixTimestamp) -> Result<(), ProgramError> {
+    let elapsed_periods = Self::PERIOD.elapsed_cycles(self.period_anchor, now)?;
+    let period_start = Self::PERIOD.cycle_start(self.period_anchor, elapsed_periods)?;
+    if period_start > self.period_start {
+      self.period_start = period_start;
+      self.spent_this_period = 0;
+    }
+    Ok(())
+  }
+
+  /// Counts `amount` towards the current period, failing if it would go over the cap
+  pub fn spend(&mut self, amount: u64, now: UnixTimestamp) -> Result<(), ProgramError> {
+    self.roll_period(now)?;
+    let spent_this_period = self
+      .spent_this_period
+      .checked_add(amount)
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    if spent_this_period > self.monthly_cap {
+      return Err(RecurringPaymentsError::SpendingCapExceeded.into());
+    }
+    self.spent_this_period = spent_this_period;
+    Ok(())
+  }
+}
+
+impl Sealed for SubscriberProfile {}
+
+impl IsInitialized for SubscriberProfile {
+  fn is_initialized(&self) -> bool {
+    self.is_initialized
+  }
+}
+
+impl Pack for SubscriberProfile {
+  const LEN: usize = SUBSCRIBER_PROFILE_SIZE;
+
+  fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+    let subscriber_profile: SubscriberProfile = unpack_encoded(src)?;
+    subscriber_profile.account_type.check(AccountType::SubscriberProfile, subscriber_profile.is_initialized)?;
+    Ok(subscriber_profile)
+  }
+
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::state::MaxEncodedSize;
+
+  #[test]
+  fn subscriber_profiles_round_trip() {
+    let subscriber_profile = SubscriberProfile {
+      account_type: AccountType::SubscriberProfile,
+      is_initialized: true,
+      bump: 253,
+      owner: Pubkey::new_unique(),
+      mint: Pubkey::new_unique(),
+      monthly_cap: 10_000,
+      period_anchor: 1_700_000_000,
+      period_start: 1_702_592_000,
+      spent_this_period: 2_500,
+    };
+    let encoded = borsh::to_vec(&subscriber_profile).unwrap();
+    assert_eq!(encoded.len(), SUBSCRIBER_PROFILE_SIZE);
+    assert_eq!(SUBSCRIBER_PROFILE_SIZE, SubscriberProfile::MAX_ENCODED_SIZE);
+
+    let mut data = [0xff; SUBSCRIBER_PROFILE_SIZE];
+    SubscriberProfile::pack(subscriber_profile, &mut data).unwrap();
+    let unpacked = SubscriberProfile::unpack(&data).unwrap();
+    assert_eq!(borsh::to_vec(&unpacked).unwrap(), encoded);
+  }
+}
@@ -1,26 +1,165 @@
-use crate::constants::SUBSCRIPTION_SIZE;
-use arrayref::{array_mut_ref, array_ref, mut_array_refs};
+use crate::{
+  constants::{CUSTOMER_REFERENCE_LEN, SUBSCRIPTION_RESERVED_SIZE, SUBSCRIPTION_SIZE, SUBSCRIPTION_VERSION},
+  error::RecurringPaymentsError,
+  state::{pack_encoded, unpack_encoded, AccountType, CycleInterval, MaxEncodedSize},
+};
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
   clock::UnixTimestamp,
   program_error::ProgramError,
   program_pack::{IsInitialized, Pack, Sealed},
   pubkey::Pubkey,
 };
-use std::convert::TryInto;
-
-#[derive(Debug)]
-pub struct Subscription {
-  pub is_initialized: bool,
-  pub is_approved: bool, // true if the subscription is active
-  pub subscription_plan_account: Pubkey,
-  pub token_account: Pubkey,
-  pub owner: Pubkey,
-  // pub customer: Pubkey,            // customer that allowed for withdraw
-  // pub payout_address: Pubkey,      // address of the Business that can withdraw
-  pub cycle_start: UnixTimestamp,  // start of the subscription cycle
-  pub subscription_timeframe: u64, // length of the subscription (1 Month ususally) in days
-  pub max_amount: u64,             // max amount that can be withdrawn in one timeframe
-  pub withdrawn_amount: u64,       // amount that has been withdrawn so far this timeframe
+
+/// How the subscriber funds the subscription
+#[derive(Clone, Copy, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum PaymentMode {
+  /// Claims pull from the subscriber's token account, which has approved the plan authority as delegate
+  Delegate,
+  /// Claims draw from a prepaid vault token account owned by the plan authority
+  Escrow,
+  /// Claims draw lamports prepaid into the subscription account itself, used by native SOL plans
+  NativeEscrow,
+}
+
+impl MaxEncodedSize for PaymentMode {
+  const MAX_ENCODED_SIZE: usize = 1;
+}
+
+encoded_struct! {
+  #[derive(Clone, Debug)]
+  pub struct Subscription {
+    pub account_type: AccountType,
+    pub version: u8, // layout version, see `SUBSCRIPTION_VERSION`
+    pub is_initialized: bool,
+    pub is_approved: bool, // true if the subscription is active
+    pub subscription_plan_account: Pubkey,
+    pub token_account: Pubkey,
+    pub mint: Pubkey, // the plan mint the subscriber chose to pay in
+    pub owner: Pubkey,
+    // pub customer: Pubkey,            // customer that allowed for withdraw
+    // pub payout_address: Pubkey,      // address of the Business that can withdraw
+    pub cycle_anchor: UnixTimestamp,           // timestamp cycles are counted from, the subscription start
+    pub cycle_start: UnixTimestamp,            // start of the subscription cycle
+    pub subscription_timeframe: CycleInterval, // length of a billing cycle, copied from the plan
+    pub max_cycles: u64,                       // number of cycles the subscription lasts, 0 for no limit
+    pub end_timestamp: UnixTimestamp,          // time the subscription ends, 0 for no end date
+    pub max_amount: u64,                       // max amount withdrawn per cycle, USD cents when oracle priced
+    pub withdrawn_amount: u64,                 // amount that has been withdrawn so far this timeframe
+    pub refunded_amount: u64,                  // token units the merchant refunded over the subscription's lifetime
+    pub payment_mode: PaymentMode,
+    pub vault_account: Pubkey, // prepaid vault, the subscription account itself in native escrow mode
+    pub escrowed_amount: u64,  // claimed token units held in the plan escrow, lamports kept in this account for SOL
+    pub escrow_release: UnixTimestamp, // time the escrowed amount, less the recent tranche, can be released
+    pub is_disputed: bool,             // true while a dispute freezes the escrowed amount
+    pub recent_escrowed_amount: u64,   // part of the escrowed amount claimed after escrow_release was set
+    pub recent_escrow_release: UnixTimestamp, // time the recent tranche can be released
+    pub customer_reference: [u8; CUSTOMER_REFERENCE_LEN], // merchant's ID of the customer, all zeros for none
+    pub reserved: [u8; SUBSCRIPTION_RESERVED_SIZE],
+  }
+}
+
+impl Subscription {
+  /// Moves `cycle_start` to the start of the cycle containing `now`, resetting the withdrawn amount if a new cycle
+  /// has begun. Missed cycles are skipped, not back-billed. Before the anchor the prorated first period is current.
+  pub fn roll_cycle(&mut self, now: UnixTimestamp) -> Result<(), ProgramError> {
+    if now < self.cycle_anchor {
+      return Ok(());
+    }
+    let elapsed_cycles = self.subscription_timeframe.elapsed_cycles(self.cycle_anchor, now)?;
+    let cycle_start = self.subscription_timeframe.cycle_start(self.cycle_anchor, elapsed_cycles)?;
+    if cycle_start > self.cycle_start {
+      self.cycle_start = cycle_start;
+      self.withdrawn_amount = 0;
+    }
+    Ok(())
+  }
+
+  /// When the subscription's term runs out, the earlier of its end date and the end of its last cycle. `None` for
+  /// open ended subscriptions.
+  pub fn term_end(&self) -> Result<Option<UnixTimestamp>, ProgramError> {
+    let cycles_end = if self.max_cycles == 0 {
+      None
+    } else {
+      Some(self.subscription_timeframe.cycle_start(self.cycle_anchor, self.max_cycles)?)
+    };
+    let end_timestamp = if self.end_timestamp == 0 {
+      None
+    } else {
+      Some(self.end_timestamp)
+    };
+    Ok(match (cycles_end, end_timestamp) {
+      (Some(cycles_end), Some(end_timestamp)) => Some(cycles_end.min(end_timestamp)),
+      (cycles_end, end_timestamp) => cycles_end.or(end_timestamp),
+    })
+  }
+
+  /// Whether the subscription's fixed term is over, after which nothing can be claimed and the account can be closed
+  pub fn is_term_complete(&self, now: UnixTimestamp) -> Result<bool, ProgramError> {
+    Ok(self.term_end()?.is_some_and(|term_end| now >= term_end))
+  }
+
+  /// Merchant's reference to the customer as hex, `None` if the subscription was created without one
+  pub fn customer_reference(&self) -> Option<String> {
+    if self.customer_reference.iter().all(|&byte| byte == 0) {
+      return None;
+    }
+    Some(self.customer_reference.iter().map(|byte| format!("{:02x}", byte)).collect())
+  }
+
+  /// Holds a claimed amount in escrow until `release`. Claims made while earlier escrow is still held go into a
+  /// recent tranche, so they push back their own release but never that of the earlier escrow.
+  pub fn hold_in_escrow(&mut self, amount: u64, release: UnixTimestamp) -> Result<(), ProgramError> {
+    if self.escrowed_amount == 0 {
+      self.escrow_release = release;
+    } else {
+      self.recent_escrowed_amount = self
+        .recent_escrowed_amount
+        .checked_add(amount)
+        .ok_or(RecurringPaymentsError::Overflow)?;
+      self.recent_escrow_release = release;
+    }
+    self.escrowed_amount = self
+      .escrowed_amount
+      .checked_add(amount)
+      .ok_or(RecurringPaymentsError::Overflow)?;
+    Ok(())
+  }
+
+  /// Takes the escrow whose dispute window has passed by `now` out of the subscription, returning its amount. The
+  /// recent tranche becomes the one released next.
+  pub fn release_matured_escrow(&mut self, now: UnixTimestamp) -> u64 {
+    let mut released = 0;
+    while self.escrowed_amount > 0 && now >= self.escrow_release {
+      released += self.escrowed_amount - self.recent_escrowed_amount;
+      self.escrowed_amount = self.recent_escrowed_amount;
+      self.escrow_release = self.recent_escrow_release;
+      self.recent_escrowed_amount = 0;
+      self.recent_escrow_release = 0;
+    }
+    released
+  }
+
+  /// Drops all escrow, once a dispute has paid it out
+  pub fn clear_escrow(&mut self) {
+    self.escrowed_amount = 0;
+    self.recent_escrowed_amount = 0;
+    self.recent_escrow_release = 0;
+  }
+
+  /// When the last of the escrowed amount can be released, disputes can be opened until then
+  pub fn escrow_window_end(&self) -> UnixTimestamp {
+    if self.recent_escrowed_amount > 0 {
+      self.recent_escrow_release
+    } else {
+      self.escrow_release
+    }
+  }
+
+  /// Amount that can still be claimed in the current cycle
+  pub fn claimable_amount(&self) -> u64 {
+    self.max_amount.saturating_sub(self.withdrawn_amount)
+  }
 }
 
 impl Sealed for Subscription {}
@@ -35,101 +174,97 @@ impl Pack for Subscription {
   const LEN: usize = SUBSCRIPTION_SIZE;
 
   fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-    let src = array_ref![src, 0, Subscription::LEN];
+    let subscription: Subscription = unpack_encoded(src)?;
+    subscription.account_type.check(AccountType::Subscription, subscription.is_initialized)?;
+    if subscription.is_initialized && subscription.version != SUBSCRIPTION_VERSION {
+      return Err(RecurringPaymentsError::UnsupportedAccountVersion.into());
+    }
+    Ok(subscription)
+  }
 
-    let (is_initialized, _src) = src.split_at(1);
-    let is_initialized = match is_initialized {
-      [0] => false,
-      [1] => true,
-      _ => return Err(ProgramError::InvalidAccountData),
-    };
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
 
-    let (is_approved, src) = src.split_at(1);
-    let is_approved = match is_approved {
-      [0] => false,
-      [1] => true,
-      _ => return Err(ProgramError::InvalidAccountData),
-    };
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::state::MaxEncodedSize;
 
-    let (subscription_plan_account, src) = src.split_at(32);
-    let subscription_plan_account = Pubkey::new_from_array(
-      subscription_plan_account
-        .try_into()
-        .map_err(|_| ProgramError::InvalidAccountData)?,
-    );
-    let (token_account, src) = src.split_at(32);
-    let token_account = Pubkey::new_from_array(token_account.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
-    let (owner, src) = src.split_at(32);
-    let owner = Pubkey::new_from_array(owner.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
-
-    let (cycle_start, src) = src.split_at(8);
-    let cycle_start =
-      UnixTimestamp::from_le_bytes(cycle_start.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
-
-    let (subscription_timeframe, src) = src.split_at(8);
-    let subscription_timeframe = u64::from_le_bytes(
-      subscription_timeframe
-        .try_into()
-        .map_err(|_| ProgramError::InvalidAccountData)?,
-    );
+  #[test]
+  fn size_fits_the_subscription_exactly() {
+    let subscription = Subscription::unpack_unchecked(&[0; SUBSCRIPTION_SIZE]).unwrap();
+    assert_eq!(SUBSCRIPTION_SIZE, Subscription::MAX_ENCODED_SIZE);
+    assert_eq!(borsh::to_vec(&subscription).unwrap().len(), SUBSCRIPTION_SIZE);
+    // Live subscription accounts are allocated at this size, it only changes along with a migration
+    assert_eq!(SUBSCRIPTION_SIZE, 327);
+  }
 
-    let (max_amount, src) = src.split_at(8);
-    let max_amount = u64::from_le_bytes(max_amount.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
+  #[test]
+  fn subscriptions_round_trip() {
+    let mut subscription = Subscription::unpack_unchecked(&[0; SUBSCRIPTION_SIZE]).unwrap();
+    subscription.account_type = AccountType::Subscription;
+    subscription.version = SUBSCRIPTION_VERSION;
+    subscription.is_initialized = true;
+    subscription.is_approved = true;
+    subscription.subscription_plan_account = Pubkey::new_unique();
+    subscription.token_account = Pubkey::new_unique();
+    subscription.mint = Pubkey::new_unique();
+    subscription.owner = Pubkey::new_unique();
+    subscription.cycle_anchor = 1_700_000_000;
+    subscription.cycle_start = 1_702_592_000;
+    subscription.subscription_timeframe = CycleInterval::Years(1);
+    subscription.max_cycles = 3;
+    subscription.end_timestamp = 1_800_000_000;
+    subscription.max_amount = 1_000;
+    subscription.withdrawn_amount = 400;
+    subscription.refunded_amount = 100;
+    subscription.payment_mode = PaymentMode::Escrow;
+    subscription.vault_account = Pubkey::new_unique();
+    subscription.hold_in_escrow(30, 1_700_600_000).unwrap();
+    subscription.hold_in_escrow(20, 1_700_700_000).unwrap();
+    subscription.is_disputed = true;
+    subscription.customer_reference[..8].copy_from_slice(b"cus_0042");
 
-    let (withdrawn_amount, _src) = src.split_at(8);
-    let withdrawn_amount = u64::from_le_bytes(
-      withdrawn_amount
-        .try_into()
-        .map_err(|_| ProgramError::InvalidAccountData)?,
+    let mut data = [0xff; SUBSCRIPTION_SIZE];
+    Subscription::pack(subscription.clone(), &mut data).unwrap();
+    let unpacked = Subscription::unpack(&data).unwrap();
+    assert_eq!(borsh::to_vec(&unpacked).unwrap(), borsh::to_vec(&subscription).unwrap());
+    assert_eq!(unpacked.recent_escrowed_amount, 20);
+    assert_eq!(
+      unpacked.customer_reference().unwrap(),
+      format!("{}{}", "6375735f30303432", "0".repeat(48))
     );
+  }
 
-    Ok(Subscription {
-      is_initialized,
-      is_approved,
-      subscription_plan_account,
-      token_account,
-      owner,
-      cycle_start,
-      subscription_timeframe,
-      max_amount,
-      withdrawn_amount,
-    })
+  #[test]
+  fn escrow_matures_when_cycles_are_shorter_than_the_dispute_window() {
+    // Daily claims held for a week
+    let window = 7;
+    let mut subscription = Subscription::unpack_unchecked(&[0; SUBSCRIPTION_SIZE]).unwrap();
+    let mut released = 0;
+    for day in 0..30 {
+      released += subscription.release_matured_escrow(day);
+      subscription.hold_in_escrow(10, day + window).unwrap();
+    }
+    assert!(released > 0);
+    assert_eq!(released + subscription.escrowed_amount, 300);
+    assert_eq!(subscription.escrow_window_end(), 29 + window);
   }
 
-  fn pack_into_slice(&self, dst: &mut [u8]) {
-    let dst = array_mut_ref![dst, 0, Subscription::LEN];
-    let (
-      is_initialized_dst,
-      is_approved_dst,
-      subscription_plan_account_dst,
-      token_account_dst,
-      owner_dst,
-      cycle_start_dst,
-      subscription_timeframe_dst,
-      max_amount_dst,
-      withdrawn_amount_dst,
-    ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 8, 8, 8, 8];
-
-    let &Subscription {
-      is_initialized,
-      is_approved,
-      ref subscription_plan_account,
-      ref token_account,
-      ref owner,
-      cycle_start,
-      subscription_timeframe,
-      max_amount,
-      withdrawn_amount,
-    } = self;
-
-    is_approved_dst[0] = is_approved as u8;
-    is_initialized_dst[0] = is_initialized as u8;
-    *subscription_plan_account_dst = subscription_plan_account.to_bytes();
-    *token_account_dst = token_account.to_bytes();
-    *owner_dst = owner.to_bytes();
-    *cycle_start_dst = cycle_start.to_le_bytes();
-    *subscription_timeframe_dst = subscription_timeframe.to_le_bytes();
-    *max_amount_dst = max_amount.to_le_bytes();
-    *withdrawn_amount_dst = withdrawn_amount.to_le_bytes();
+  #[test]
+  fn recent_claims_do_not_delay_earlier_escrow() {
+    let mut subscription = Subscription::unpack_unchecked(&[0; SUBSCRIPTION_SIZE]).unwrap();
+    subscription.hold_in_escrow(10, 100).unwrap();
+    subscription.hold_in_escrow(20, 150).unwrap();
+    subscription.hold_in_escrow(30, 200).unwrap();
+
+    assert_eq!(subscription.release_matured_escrow(99), 0);
+    assert_eq!(subscription.release_matured_escrow(100), 10);
+    assert_eq!(subscription.escrowed_amount, 50);
+    assert_eq!(subscription.escrow_release, 200);
+    assert_eq!(subscription.release_matured_escrow(200), 50);
+    assert_eq!(subscription.escrowed_amount, 0);
   }
 }
@@ -1,21 +1,55 @@
-use crate::constants::SUBSCRIPTION_PLAN_SIZE;
-use arrayref::{array_mut_ref, array_ref, mut_array_refs};
+use crate::{
+  constants::{MAX_ACCEPTED_MINTS, SUBSCRIPTION_PLAN_RESERVED_SIZE, SUBSCRIPTION_PLAN_SIZE, SUBSCRIPTION_PLAN_VERSION},
+  error::RecurringPaymentsError,
+  state::{pack_encoded, unpack_encoded, AcceptedMint, AccountType, CycleInterval, MaxEncodedSize, PlanMetadata},
+};
 use solana_program::{
+  clock::UnixTimestamp,
   program_error::ProgramError,
   program_pack::{IsInitialized, Pack, Sealed},
   pubkey::Pubkey,
 };
-use std::convert::TryInto;
 
-#[derive(Debug)]
-pub struct SubscriptionPlan {
-  pub is_initialized: bool,
-  pub nonce: u8,
-  pub owner: Pubkey,
-  pub authority: Pubkey,
-  pub token: Pubkey,
-  pub subscription_timeframe: u64, // length of the subscription (1 Month ususally) in days
-  pub max_amount: u64,             // max amount that can be withdrawn in one timeframe
+encoded_struct! {
+  #[derive(Clone, Debug)]
+  pub struct SubscriptionPlan {
+    pub account_type: AccountType,
+    pub version: u8, // layout version, see `SUBSCRIPTION_PLAN_VERSION`
+    pub is_initialized: bool,
+    pub nonce: u8,
+    pub owner: Pubkey,
+    pub authority: Pubkey,
+    pub subscription_timeframe: CycleInterval, // length of a billing cycle, usually one calendar month
+    pub max_cycles: u64,                       // number of cycles a subscription lasts, 0 for no limit
+    pub end_timestamp: UnixTimestamp,          // time every subscription ends, 0 for no end date
+    pub charge_upfront: bool,                  // whether the first period is charged when subscribing
+    pub billing_anchor_day: u8,                // day of the month cycles start on, 0 to start them on signup
+    pub dispute_window: i64,                   // seconds claims are held in escrow, 0 to pay out directly
+    pub arbiter: Pubkey,                       // resolves disputes opened during the dispute window
+    pub settlement_interval: i64,              // seconds between settlement vault payouts, 0 to pay out directly
+    pub keeper_tip: u64,                       // lamports paid to a keeper per claim, 0 so only the owner can claim
+    pub metadata: PlanMetadata,                // name and description wallets show at signup, set by the owner
+    pub price_feed_owner: Pubkey,              // program owning the feeds of USD priced mints, pinned by the first one
+    // Reserved bytes go ahead of the accepted mints, whose encoding varies in length
+    pub reserved: [u8; SUBSCRIPTION_PLAN_RESERVED_SIZE],
+    pub accepted_mints: Vec<AcceptedMint>,     // mints subscribers can pick from, at most MAX_ACCEPTED_MINTS
+  }
+}
+
+impl SubscriptionPlan {
+  /// Looks up the price and payout account for one of the plan's mints
+  pub fn accepted_mint(&self, mint: &Pubkey) -> Result<&AcceptedMint, ProgramError> {
+    self
+      .accepted_mints
+      .iter()
+      .find(|accepted_mint| accepted_mint.mint == *mint)
+      .ok_or_else(|| RecurringPaymentsError::InvalidMint.into())
+  }
+}
+
+/// Borsh length prefix, then room for every accepted mint a plan can hold
+impl MaxEncodedSize for Vec<AcceptedMint> {
+  const MAX_ENCODED_SIZE: usize = 4 + MAX_ACCEPTED_MINTS * AcceptedMint::MAX_ENCODED_SIZE;
 }
 
 impl Sealed for SubscriptionPlan {}
@@ -30,81 +64,108 @@ impl Pack for SubscriptionPlan {
   const LEN: usize = SUBSCRIPTION_PLAN_SIZE;
 
   fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-    let src = array_ref![src, 0, SubscriptionPlan::LEN];
-
-    let (is_initialized, _src) = src.split_at(1);
-    let is_initialized = match is_initialized {
-      [0] => false,
-      [1] => true,
-      _ => return Err(ProgramError::InvalidAccountData),
-    };
+    let subscription_plan: SubscriptionPlan = unpack_encoded(src)?;
+    subscription_plan
+      .account_type
+      .check(AccountType::SubscriptionPlan, subscription_plan.is_initialized)?;
+    if subscription_plan.is_initialized && subscription_plan.version != SUBSCRIPTION_PLAN_VERSION {
+      return Err(RecurringPaymentsError::UnsupportedAccountVersion.into());
+    }
+    if subscription_plan.accepted_mints.len() > MAX_ACCEPTED_MINTS {
+      return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(subscription_plan)
+  }
 
-    let (nonce, src) = src.split_at(1);
-    let nonce = nonce[0];
-    let (owner, src) = src.split_at(32);
-    let owner = Pubkey::new_from_array(owner.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
-    let (authority, src) = src.split_at(32);
-    let authority = Pubkey::new_from_array(authority.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
-    let (token, src) = src.split_at(32);
-    let token = Pubkey::new_from_array(token.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
+  fn pack_into_slice(&self, dst: &mut [u8]) {
+    pack_encoded(self, dst)
+  }
+}
 
-    let (subscription_timeframe, src) = src.split_at(8);
-    let subscription_timeframe = u64::from_le_bytes(
-      subscription_timeframe
-        .try_into()
-        .map_err(|_| ProgramError::InvalidAccountData)?,
-    );
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::state::PricingMode;
 
-    let (max_amount, _src) = src.split_at(8);
-    let max_amount = u64::from_le_bytes(max_amount.try_into().map_err(|_| ProgramError::InvalidAccountData)?);
+  /// Plan with every field set and as many accepted mints as it can hold, the largest encoding there is
+  fn full_plan() -> SubscriptionPlan {
+    SubscriptionPlan {
+      account_type: AccountType::SubscriptionPlan,
+      version: SUBSCRIPTION_PLAN_VERSION,
+      is_initialized: true,
+      nonce: 254,
+      owner: Pubkey::new_unique(),
+      authority: Pubkey::new_unique(),
+      subscription_timeframe: CycleInterval::CalendarMonths(1),
+      max_cycles: 12,
+      end_timestamp: 1_700_000_000,
+      charge_upfront: true,
+      billing_anchor_day: 28,
+      dispute_window: 7 * 86_400,
+      arbiter: Pubkey::new_unique(),
+      settlement_interval: 86_400,
+      keeper_tip: 5_000,
+      metadata: PlanMetadata::new("Pro", "https://example.com/pro.json", "price_123").unwrap(),
+      price_feed_owner: Pubkey::new_unique(),
+      reserved: [0; SUBSCRIPTION_PLAN_RESERVED_SIZE],
+      accepted_mints: (0..MAX_ACCEPTED_MINTS)
+        .map(|i| AcceptedMint {
+          mint: Pubkey::new_unique(),
+          token_program: Pubkey::new_unique(),
+          decimals: 6,
+          payout_account: Pubkey::new_unique(),
+          max_amount: 1_000 + i as u64,
+          setup_fee: 50,
+          pricing_mode: PricingMode::UsdCents,
+          price_feed: Pubkey::new_unique(),
+          escrow_account: Pubkey::new_unique(),
+          settlement_vault: Pubkey::new_unique(),
+          last_settlement: 1_700_000_000,
+        })
+        .collect(),
+    }
+  }
 
-    Ok(SubscriptionPlan {
-      is_initialized,
-      nonce,
-      owner,
-      authority,
-      token,
-      subscription_timeframe,
-      max_amount,
-    })
+  #[test]
+  fn size_fits_the_largest_plan_exactly() {
+    assert_eq!(SUBSCRIPTION_PLAN_SIZE, SubscriptionPlan::MAX_ENCODED_SIZE);
+    assert_eq!(borsh::to_vec(&full_plan()).unwrap().len(), SUBSCRIPTION_PLAN_SIZE);
+    // Live plan accounts are allocated at this size, it only changes along with a migration
+    assert_eq!(SUBSCRIPTION_PLAN_SIZE, 1419);
   }
 
-  fn pack_into_slice(&self, dst: &mut [u8]) {
-    let dst = array_mut_ref![dst, 0, SubscriptionPlan::LEN];
-    let (
-      is_initialized_dst,
-      nonce_dst,
-      owner_dst,
-      authority_dst,
-      token_dst,
-      subscription_timeframe_dst,
-      max_amount_dst,
-    ) = mut_array_refs![dst, 1, 1, 32, 32, 32, 8, 8];
+  #[test]
+  fn plans_round_trip() {
+    let mut subscription_plan = full_plan();
+    let mut data = [0xff; SUBSCRIPTION_PLAN_SIZE];
+    SubscriptionPlan::pack(subscription_plan.clone(), &mut data).unwrap();
+    let unpacked = SubscriptionPlan::unpack(&data).unwrap();
+    assert_eq!(borsh::to_vec(&unpacked).unwrap(), borsh::to_vec(&subscription_plan).unwrap());
 
-    let &SubscriptionPlan {
-      is_initialized,
-      nonce,
-      owner,
-      authority,
-      token,
-      subscription_timeframe,
-      max_amount,
-    } = self;
+    // Dropping mints zeroes the space they took up
+    subscription_plan.accepted_mints.truncate(1);
+    SubscriptionPlan::pack(subscription_plan.clone(), &mut data).unwrap();
+    let unpacked = SubscriptionPlan::unpack(&data).unwrap();
+    assert_eq!(unpacked.accepted_mints, subscription_plan.accepted_mints);
+    let encoded_len = borsh::to_vec(&subscription_plan).unwrap().len();
+    assert!(data[encoded_len..].iter().all(|byte| *byte == 0));
+  }
 
-    is_initialized_dst[0] = is_initialized as u8;
-    nonce_dst[0] = nonce;
-    *owner_dst = owner.to_bytes();
-    *authority_dst = authority.to_bytes();
-    *token_dst = token.to_bytes();
-    *subscription_timeframe_dst = subscription_timeframe.to_le_bytes();
-    *max_amount_dst = max_amount.to_le_bytes();
+  #[test]
+  fn unpack_rejects_other_versions_and_too_many_mints() {
+    let mut data = [0; SUBSCRIPTION_PLAN_SIZE];
+    let mut subscription_plan = full_plan();
+    subscription_plan.version = SUBSCRIPTION_PLAN_VERSION - 1;
+    pack_encoded(&subscription_plan, &mut data);
+    assert_eq!(
+      SubscriptionPlan::unpack(&data).map(|_| ()),
+      Err(RecurringPaymentsError::UnsupportedAccountVersion.into())
+    );
 
-    // is_initialized_dst[0] = *is_initialized as u8;
-    // nonce_dst[0] = *nonce;
-    // owner_dst.copy_from_slice(owner.as_ref());
-    // authority_dst.copy_from_slice(authority.as_ref());
-    // token_dst.copy_from_slice(token.as_ref());
-    // *subscription_timeframe_dst = subscription_timeframe.to_le_bytes();
-    // *max_amount_dst = max_amount.to_le_bytes();
+    let mut subscription_plan = full_plan();
+    subscription_plan.accepted_mints = vec![subscription_plan.accepted_mints[0].clone(); MAX_ACCEPTED_MINTS + 1];
+    let mut data = vec![0; SUBSCRIPTION_PLAN_SIZE + AcceptedMint::MAX_ENCODED_SIZE];
+    pack_encoded(&subscription_plan, &mut data);
+    assert_eq!(SubscriptionPlan::unpack_from_slice(&data).map(|_| ()), Err(ProgramError::InvalidAccountData));
   }
 }
@@ -0,0 +1,76 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{constants::MAX_ACCEPTED_MINTS, error::RecurringPaymentsError, state::CycleInterval};
+use solana_program::program_error::ProgramError;
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+#[test]
+fn subscribers_pay_in_the_mint_they_pick() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let usdt = test.accept_mint(&plan, 250).unwrap();
+    let subscriber = test.subscribe(&usdt, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+
+    assert_eq!(test.token_account(&subscriber.vault).mint, usdt.mint);
+    assert_eq!(test.token_balance(&subscriber.vault), 500);
+
+    test.process(claim(&program_id, &usdt, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&usdt.payout), 250);
+    assert_eq!(test.token_balance(&plan.payout), 0);
+
+    // The subscription is bound to its mint, the plan's first mint can't claim it
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidMint)));
+}
+
+#[test]
+fn subscriptions_pay_the_price_of_their_mint() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let usdt = test.accept_mint(&plan, 250).unwrap();
+
+    let underpaid = Plan {
+        max_amount: 100,
+        ..usdt.clone()
+    };
+    let result = test.subscribe(&underpaid, 1_000, 0);
+    assert_eq!(result.err(), Some(error(RecurringPaymentsError::InvalidMaxAmount)));
+
+    let unaccepted = Plan {
+        mint: test.create_mint(6),
+        ..plan.clone()
+    };
+    assert!(test.subscribe(&unaccepted, 1_000, 0).is_err());
+}
+
+#[test]
+fn only_the_owner_adds_mints_and_only_up_to_the_limit() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+
+    let mint = test.create_mint(6);
+    let impostor = test.create_wallet(LAMPORTS_PER_WALLET);
+    let accepted = Plan {
+        mint,
+        payout: test.create_token_account(&mint, &impostor, 0),
+        ..plan.clone()
+    };
+    let result = test.process(add_accepted_mint(&program_id, &accepted, &impostor));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = test.process(add_accepted_mint(&program_id, &plan, &plan.owner));
+    assert_eq!(result, Err(error(RecurringPaymentsError::MintAlreadyAccepted)));
+
+    for _ in 1..MAX_ACCEPTED_MINTS {
+        test.accept_mint(&plan, 100).unwrap();
+    }
+    let result = test.accept_mint(&plan, 100);
+    assert_eq!(result.err(), Some(error(RecurringPaymentsError::TooManyAcceptedMints)));
+}
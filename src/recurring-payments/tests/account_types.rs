@@ -0,0 +1,87 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    error::RecurringPaymentsError,
+    processor::Processor,
+    state::{AccountType, CycleInterval},
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+fn account_type(test: &ProgramTest, key: &Pubkey) -> u8 {
+    test.account(key).unwrap().data[0]
+}
+
+/// Overwrites the type byte an account starts with
+fn set_account_type(test: &mut ProgramTest, key: &Pubkey, account_type: AccountType) {
+    let mut account = test.account(key).unwrap().clone();
+    account.data[0] = account_type as u8;
+    test.set_account(*key, account);
+}
+
+#[test]
+fn accounts_start_with_their_type() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+    test.process(set_spending_cap(&program_id, &subscriber.owner, &plan.mint, 1_000))
+        .unwrap();
+
+    let subscriber_profile = Processor::subscriber_profile_id(&program_id, &subscriber.owner).0;
+    assert_eq!(account_type(&test, &plan.plan), AccountType::SubscriptionPlan as u8);
+    assert_eq!(
+        account_type(&test, &subscriber.subscription),
+        AccountType::Subscription as u8
+    );
+    assert_eq!(
+        account_type(&test, &subscriber_profile),
+        AccountType::SubscriberProfile as u8
+    );
+}
+
+#[test]
+fn accounts_of_another_type_are_rejected() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    set_account_type(&mut test, &plan.plan, AccountType::Subscription);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::WrongAccountType)));
+    set_account_type(&mut test, &plan.plan, AccountType::SubscriptionPlan);
+
+    set_account_type(&mut test, &subscriber.subscription, AccountType::SubscriptionPlan);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::WrongAccountType)));
+    set_account_type(&mut test, &subscriber.subscription, AccountType::Subscription);
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+}
+
+#[test]
+fn price_feeds_of_another_type_are_invalid() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let price_feed = test.create_price_feed(&oracle, 200, -2);
+    let plan = test.create_usd_plan(CycleInterval::Days(30), 500, &price_feed, PlanTerms::default());
+    let subscriber = test.subscribe(&plan, 10_000_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000_000);
+    let program_id = test.program_id;
+
+    // Anything that doesn't decode as a price feed is reported as an invalid feed
+    set_account_type(&mut test, &price_feed, AccountType::SubscriberProfile);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPriceFeed)));
+
+    set_account_type(&mut test, &price_feed, AccountType::PriceFeed);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+}
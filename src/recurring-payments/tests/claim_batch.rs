@@ -0,0 +1,115 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::SECONDS_PER_DAY,
+    error::RecurringPaymentsError,
+    processor::Processor,
+    state::{CycleInterval, SubscriberProfile, Subscription},
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+fn subscription(test: &ProgramTest, subscriber: &Subscriber) -> Subscription {
+    Subscription::unpack(&test.account(&subscriber.subscription).unwrap().data).unwrap()
+}
+
+fn subscriber_profile(test: &ProgramTest, subscriber: &Subscriber) -> SubscriberProfile {
+    let key = Processor::subscriber_profile_id(&test.program_id, &subscriber.owner).0;
+    SubscriberProfile::unpack(&test.account(&key).unwrap().data).unwrap()
+}
+
+#[test]
+fn a_failed_claim_skips_its_subscription_and_is_reported_in_the_return_data() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let first = test.subscribe(&plan, 1_000, 3).unwrap();
+    let claimed = test.subscribe(&plan, 1_000, 3).unwrap();
+    let last = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &claimed, &claimed.vault))
+        .unwrap();
+
+    let batch = [(&first, &first.vault), (&claimed, &claimed.vault), (&last, &last.vault)];
+    test.process(claim_batch(&program_id, &plan, &batch)).unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![1, 0, 1])));
+    assert_eq!(test.token_balance(&plan.payout), 300);
+    assert_eq!(test.token_balance(&first.vault), 200);
+    assert_eq!(test.token_balance(&claimed.vault), 200);
+    assert_eq!(test.token_balance(&last.vault), 200);
+
+    // Nothing is left to claim this cycle, so a second batch claims none
+    test.process(claim_batch(&program_id, &plan, &batch)).unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![0, 0, 0])));
+
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim_batch(&program_id, &plan, &batch)).unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![1, 1, 1])));
+    assert_eq!(test.token_balance(&plan.payout), 600);
+}
+
+#[test]
+fn a_claim_over_the_spending_cap_leaves_the_profile_and_subscription_untouched() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let capped = test.subscribe(&plan, 1_000, 3).unwrap();
+    let uncapped = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+    test.process(set_spending_cap(&program_id, &capped.owner, &plan.mint, 50))
+        .unwrap();
+
+    let batch = [(&capped, &capped.vault), (&uncapped, &uncapped.vault)];
+    test.process(claim_batch(&program_id, &plan, &batch)).unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![0, 1])));
+    assert_eq!(subscriber_profile(&test, &capped).spent_this_period, 0);
+    assert_eq!(subscription(&test, &capped).withdrawn_amount, 0);
+    assert_eq!(test.token_balance(&capped.vault), 300);
+    assert_eq!(test.token_balance(&plan.payout), 100);
+
+    // Alone the same claim fails the instruction
+    let result = test.process(claim(&program_id, &plan, &capped, &capped.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SpendingCapExceeded)));
+}
+
+#[test]
+fn a_delegate_without_allowance_is_skipped_instead_of_failing_the_batch() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let approved = test.subscribe(&plan, 1_000, 0).unwrap();
+    let revoked = test.subscribe(&plan, 1_000, 0).unwrap();
+    test.approve(&approved.token_account, &plan.authority, 1_000);
+    test.approve(&revoked.token_account, &plan.authority, 50);
+    let program_id = test.program_id;
+
+    let batch = [(&approved, &approved.token_account), (&revoked, &revoked.token_account)];
+    test.process(claim_batch(&program_id, &plan, &batch)).unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![1, 0])));
+    assert_eq!(test.token_balance(&revoked.token_account), 1_000);
+    assert_eq!(test.token_balance(&plan.payout), 100);
+
+    let result = test.process(claim(&program_id, &plan, &revoked, &revoked.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InsufficientSourceFunds)));
+}
+
+#[test]
+fn batches_need_at_least_one_whole_subscription() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    let result = test.process(claim_batch(&program_id, &plan, &[]));
+    assert_eq!(result, Err(ProgramError::NotEnoughAccountKeys));
+
+    let mut instruction = claim_batch(&program_id, &plan, &[(&subscriber, &subscriber.vault)]);
+    instruction.accounts.pop();
+    assert_eq!(test.process(instruction), Err(ProgramError::NotEnoughAccountKeys));
+
+    // Only the plan owner claims
+    let mut instruction = claim_batch(&program_id, &plan, &[(&subscriber, &subscriber.vault)]);
+    instruction.accounts[0].is_signer = false;
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+}
@@ -0,0 +1,56 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{CUSTOMER_REFERENCE_LEN, SECONDS_PER_DAY},
+    state::{CycleInterval, Subscription},
+};
+use solana_program::program_pack::Pack;
+
+fn subscription(test: &ProgramTest, subscriber: &Subscriber) -> Subscription {
+    Subscription::unpack(&test.account(&subscriber.subscription).unwrap().data).unwrap()
+}
+
+/// Zero pads a short string, the way a merchant would store their own customer ID
+fn reference(id: &str) -> [u8; CUSTOMER_REFERENCE_LEN] {
+    let mut reference = [0; CUSTOMER_REFERENCE_LEN];
+    reference[..id.len()].copy_from_slice(id.as_bytes());
+    reference
+}
+
+#[test]
+fn subscriptions_keep_the_customer_reference_across_claims() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test
+        .subscribe_with_reference(&plan, 1_000, 3, reference("cus_0042"))
+        .unwrap();
+    let program_id = test.program_id;
+    assert_eq!(
+        subscription(&test, &subscriber).customer_reference,
+        reference("cus_0042")
+    );
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    assert_eq!(
+        subscription(&test, &subscriber).customer_reference(),
+        Some(format!("{}{}", "6375735f30303432", "0".repeat(48)))
+    );
+}
+
+#[test]
+fn subscriptions_without_a_reference_read_as_none() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(subscription(&test, &subscriber).customer_reference(), None);
+}
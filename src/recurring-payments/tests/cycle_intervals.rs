@@ -0,0 +1,108 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{SECONDS_PER_DAY, SUBSCRIPTION_PLAN_SIZE},
+    error::RecurringPaymentsError,
+    state::CycleInterval,
+};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+#[test]
+fn calendar_month_cycles_renew_on_the_anchor_day() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::CalendarMonths(1), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+
+    // November has 30 days, the next cycle starts on December 14th at the anchor's time of day
+    let december = NOW + 30 * SECONDS_PER_DAY;
+    test.warp_to(december - 1);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+    test.warp_to(december);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+
+    // December has 31
+    let january = december + 31 * SECONDS_PER_DAY;
+    test.warp_to(january - 1);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+    test.warp_to(january);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 300);
+}
+
+#[test]
+fn intervals_must_be_positive_and_match_the_plan() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Weeks(1), 100);
+
+    assert_eq!(
+        create_plan_every(&mut test, &plan, CycleInterval::CalendarMonths(0)),
+        Err(error(RecurringPaymentsError::InvalidSubscriptionTimeframe))
+    );
+
+    // Seven days is a week, but subscribers have to agree to the interval exactly as the plan states it
+    let days = Plan {
+        timeframe: CycleInterval::Days(7),
+        ..plan.clone()
+    };
+    let result = test.subscribe(&days, 1_000, 1);
+    assert_eq!(
+        result.map(|_| ()),
+        Err(error(RecurringPaymentsError::InvalidSubscriptionTimeframe))
+    );
+    test.subscribe(&plan, 1_000, 1).unwrap();
+}
+
+fn create_plan_every(test: &mut ProgramTest, plan: &Plan, timeframe: CycleInterval) -> ProgramResult {
+    let program_id = test.program_id;
+    let account = test.create_program_account(SUBSCRIPTION_PLAN_SIZE);
+    let (authority, nonce) = authority_id(&program_id, &account);
+    test.process(create_subscription_plan(
+        &program_id,
+        &account,
+        &plan.owner,
+        &authority,
+        &plan.mint,
+        &plan.payout,
+        None,
+        nonce,
+        timeframe,
+        100,
+        PlanTerms::default(),
+    ))
+}
+
+#[test]
+fn plans_bill_between_an_hour_and_ten_years() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+
+    assert_eq!(
+        create_plan_every(&mut test, &plan, CycleInterval::Seconds(3_599)),
+        Err(error(RecurringPaymentsError::CycleTooShort))
+    );
+    create_plan_every(&mut test, &plan, CycleInterval::Seconds(3_600)).unwrap();
+
+    create_plan_every(&mut test, &plan, CycleInterval::Years(10)).unwrap();
+    create_plan_every(&mut test, &plan, CycleInterval::CalendarMonths(120)).unwrap();
+    assert_eq!(
+        create_plan_every(&mut test, &plan, CycleInterval::CalendarMonths(121)),
+        Err(error(RecurringPaymentsError::CycleTooLong))
+    );
+    assert_eq!(
+        create_plan_every(&mut test, &plan, CycleInterval::Days(3_661)),
+        Err(error(RecurringPaymentsError::CycleTooLong))
+    );
+}
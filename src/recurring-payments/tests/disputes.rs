@@ -0,0 +1,200 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::SECONDS_PER_DAY,
+    error::RecurringPaymentsError,
+    state::{CycleInterval, Subscription},
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const DAY: i64 = SECONDS_PER_DAY;
+
+fn subscription(test: &ProgramTest, subscriber: &Subscriber) -> Subscription {
+    Subscription::unpack(&test.account(&subscriber.subscription).unwrap().data).unwrap()
+}
+
+/// Creates a monthly plan holding claims for `dispute_window` seconds, and an escrow subscriber to it
+fn disputed_plan(test: &mut ProgramTest, mint: &Pubkey, dispute_window: i64) -> (Plan, Subscriber, Pubkey) {
+    let plan = test.create_plan_in(mint, CycleInterval::Days(30), 100);
+    let arbiter = test.create_wallet(LAMPORTS_PER_WALLET);
+    let plan = test.set_dispute_window(&plan, &arbiter, dispute_window).unwrap();
+    let subscriber = test.subscribe(&plan, 1_000, 4).unwrap();
+    (plan, subscriber, arbiter)
+}
+
+#[test]
+fn claims_are_held_in_escrow_until_the_dispute_window_passes() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let (plan, subscriber, _) = disputed_plan(&mut test, &mint, 7 * DAY);
+    let escrow = plan.escrow.unwrap();
+    let program_id = test.program_id;
+    assert_eq!(test.token_account(&escrow).owner, plan.authority);
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&escrow), 100);
+    assert_eq!(test.token_balance(&plan.payout), 0);
+
+    test.warp_to(NOW + 7 * DAY - 1);
+    let result = test.process(release_escrow(&program_id, &plan, &subscriber));
+    assert_eq!(result, Err(error(RecurringPaymentsError::DisputeWindowOpen)));
+
+    // Anyone can release once the window has passed
+    test.warp_to(NOW + 7 * DAY);
+    test.process(release_escrow(&program_id, &plan, &subscriber)).unwrap();
+    assert_eq!(test.token_balance(&escrow), 0);
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    let result = test.process(release_escrow(&program_id, &plan, &subscriber));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+
+    // Claims have to go through the plan's escrow account
+    test.warp_to(NOW + 30 * DAY);
+    let direct = Plan {
+        escrow: Some(plan.payout),
+        ..plan.clone()
+    };
+    let result = test.process(claim(&program_id, &direct, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidEscrowAccount)));
+}
+
+#[test]
+fn claims_release_earlier_escrow_when_cycles_are_shorter_than_the_window() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let (plan, subscriber, _) = disputed_plan(&mut test, &mint, 40 * DAY);
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + 30 * DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 0);
+
+    // The first claim matured on day 40 and goes out with the third, the second one stays held until day 70
+    test.warp_to(NOW + 60 * DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&plan.escrow.unwrap()), 200);
+    let state = subscription(&test, &subscriber);
+    assert_eq!(state.escrow_release, NOW + 70 * DAY);
+    assert_eq!(state.escrow_window_end(), NOW + 100 * DAY);
+
+    test.warp_to(NOW + 70 * DAY);
+    test.process(release_escrow(&program_id, &plan, &subscriber)).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    assert_eq!(subscription(&test, &subscriber).escrowed_amount, 100);
+}
+
+#[test]
+fn the_arbiter_splits_a_disputed_escrow() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let (plan, subscriber, arbiter) = disputed_plan(&mut test, &mint, 7 * DAY);
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+
+    let result = test.process(resolve_dispute(&program_id, &plan, &subscriber, &arbiter, 30));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NotDisputed)));
+    test.process(open_dispute(&program_id, &plan, &subscriber)).unwrap();
+
+    // A dispute freezes the escrow and the subscription past the window
+    test.warp_to(NOW + 30 * DAY);
+    let result = test.process(release_escrow(&program_id, &plan, &subscriber));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SubscriptionDisputed)));
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SubscriptionDisputed)));
+
+    let result = test.process(resolve_dispute(&program_id, &plan, &subscriber, &plan.owner, 30));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidArbiter)));
+    let result = test.process(resolve_dispute(&program_id, &plan, &subscriber, &arbiter, 101));
+    assert_eq!(result, Err(error(RecurringPaymentsError::RefundExceedsEscrow)));
+    test.process(resolve_dispute(&program_id, &plan, &subscriber, &arbiter, 30))
+        .unwrap();
+    assert_eq!(test.token_balance(&subscriber.token_account), 630);
+    assert_eq!(test.token_balance(&plan.payout), 70);
+    let state = subscription(&test, &subscriber);
+    assert_eq!(state.escrowed_amount, 0);
+    assert_eq!(state.refunded_amount, 30);
+    assert!(!state.is_disputed);
+
+    // Billing carries on, and disputes can only be opened while something is held
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + 37 * DAY);
+    let result = test.process(open_dispute(&program_id, &plan, &subscriber));
+    assert_eq!(result, Err(error(RecurringPaymentsError::DisputeWindowClosed)));
+}
+
+#[test]
+fn native_escrow_stays_in_the_subscription_account() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), 1_000_000);
+    let arbiter = test.create_wallet(LAMPORTS_PER_WALLET);
+    let plan = test.set_dispute_window(&plan, &arbiter, 7 * DAY).unwrap();
+    let subscriber = test.subscribe(&plan, LAMPORTS_PER_WALLET, 4).unwrap();
+    let program_id = test.program_id;
+    assert_eq!(plan.escrow, None);
+
+    let payout = test.lamports(&plan.payout);
+    let held = test.lamports(&subscriber.subscription);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+        .unwrap();
+    assert_eq!(test.lamports(&subscriber.subscription), held);
+    assert_eq!(subscription(&test, &subscriber).escrowed_amount, 1_000_000);
+
+    // Escrowed lamports are not part of the prepaid balance left to claim or withdraw
+    let result = test.process(withdraw_unused(
+        &program_id,
+        &plan,
+        &subscriber,
+        &subscriber.subscription,
+        &subscriber.owner,
+        3_000_001,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InsufficientVaultFunds)));
+
+    test.process(open_dispute(&program_id, &plan, &subscriber)).unwrap();
+    let wallet = test.lamports(&subscriber.owner);
+    test.process(resolve_dispute(&program_id, &plan, &subscriber, &arbiter, 1_000_000))
+        .unwrap();
+    assert_eq!(test.lamports(&subscriber.owner), wallet + 1_000_000);
+    assert_eq!(test.lamports(&plan.payout), payout);
+
+    test.warp_to(NOW + 30 * DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+        .unwrap();
+    test.warp_to(NOW + 37 * DAY);
+    test.process(release_escrow(&program_id, &plan, &subscriber)).unwrap();
+    assert_eq!(test.lamports(&plan.payout), payout + 1_000_000);
+}
+
+#[test]
+fn token_2022_escrow_pays_the_transfer_fee_again_on_release() {
+    let mut test = ProgramTest::new();
+    // 1% capped at 1000 token units
+    let mint = test.create_mint_2022(6, Some((100, 1_000)));
+    let plan = test.create_plan_in(&mint, CycleInterval::Days(30), 10_000);
+    let arbiter = test.create_wallet(LAMPORTS_PER_WALLET);
+    let plan = test.set_dispute_window(&plan, &arbiter, 7 * DAY).unwrap();
+    let subscriber = test.subscribe(&plan, 10_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000);
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.escrow.unwrap()), 9_900);
+    assert_eq!(subscription(&test, &subscriber).escrowed_amount, 9_900);
+
+    test.warp_to(NOW + 7 * DAY);
+    test.process(release_escrow(&program_id, &plan, &subscriber)).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 9_801);
+}
@@ -0,0 +1,229 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{error::RecurringPaymentsError, processor::Processor, state::CycleInterval};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+const DAY: i64 = 86_400;
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+#[test]
+fn escrow_subscription_deposits_into_its_vault_pda() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+
+    let vault = test.token_account(&subscriber.vault);
+    assert_eq!(vault.owner, plan.authority);
+    assert_eq!(vault.mint, plan.mint);
+    assert_eq!(vault.amount, 300);
+    assert_eq!(test.token_balance(&subscriber.token_account), 700);
+}
+
+#[test]
+fn claims_draw_from_the_vault_once_per_cycle() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&subscriber.vault), 200);
+
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+
+    test.warp_to(NOW + 30 * DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    assert_eq!(test.token_balance(&subscriber.token_account), 700);
+}
+
+#[test]
+fn escrow_claims_only_draw_from_the_vault() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSourceAccount)));
+}
+
+#[test]
+fn delegate_claims_pull_from_the_subscriber() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 1_000);
+    let program_id = test.program_id;
+
+    assert!(test.account(&subscriber.vault).is_none());
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&subscriber.token_account), 900);
+}
+
+#[test]
+fn subscriptions_draw_only_from_the_subscribers_account() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let owner = test.create_wallet(LAMPORTS_PER_WALLET);
+    let victim = Pubkey::new_unique();
+    let subscription = test.create_program_account(recurring_payments_service::constants::SUBSCRIPTION_SIZE);
+    let subscriber = Subscriber {
+        subscription,
+        owner,
+        token_account: test.create_token_account(&plan.mint, &victim, 1_000),
+        vault: Processor::subscription_vault_id(&test.program_id, &plan.plan, &subscription).0,
+    };
+    let program_id = test.program_id;
+
+    let result = test.process(create_subscription(&program_id, &plan, &subscriber, 0));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriber)));
+}
+
+#[test]
+fn anyone_can_top_up_the_vault() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 1).unwrap();
+    let sponsor = test.create_wallet(LAMPORTS_PER_WALLET);
+    let source = test.create_token_account(&plan.mint, &sponsor, 500);
+    let program_id = test.program_id;
+
+    test.process(top_up(
+        &program_id,
+        &plan,
+        &sponsor,
+        &subscriber.subscription,
+        &source,
+        &subscriber.vault,
+        250,
+    ))
+    .unwrap();
+    assert_eq!(test.token_balance(&subscriber.vault), 350);
+    assert_eq!(test.token_balance(&source), 250);
+}
+
+#[test]
+fn top_ups_only_reach_the_subscriptions_vault() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 1).unwrap();
+    let other = test.subscribe(&plan, 1_000, 1).unwrap();
+    let delegate = test.subscribe(&plan, 1_000, 0).unwrap();
+    let program_id = test.program_id;
+
+    let result = test.process(top_up(
+        &program_id,
+        &plan,
+        &subscriber.owner,
+        &subscriber.subscription,
+        &subscriber.token_account,
+        &other.vault,
+        100,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidVaultAccount)));
+
+    let result = test.process(top_up(
+        &program_id,
+        &plan,
+        &delegate.owner,
+        &delegate.subscription,
+        &delegate.token_account,
+        &delegate.vault,
+        100,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPaymentMode)));
+}
+
+#[test]
+fn withdraw_unused_keeps_the_unclaimed_part_of_the_cycle() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    let withdraw = |amount| {
+        withdraw_unused(
+            &program_id,
+            &plan,
+            &subscriber,
+            &subscriber.vault,
+            &subscriber.token_account,
+            amount,
+        )
+    };
+    assert_eq!(
+        test.process(withdraw(201)),
+        Err(error(RecurringPaymentsError::InsufficientVaultFunds))
+    );
+    test.process(withdraw(150)).unwrap();
+    assert_eq!(test.token_balance(&subscriber.vault), 150);
+    assert_eq!(test.token_balance(&subscriber.token_account), 850);
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.process(withdraw(50)).unwrap();
+    assert_eq!(test.token_balance(&subscriber.vault), 0);
+}
+
+#[test]
+fn only_the_subscriber_withdraws_unused_funds() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let other = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    let impostor = Subscriber {
+        owner: other.owner,
+        ..subscriber
+    };
+    let result = test.process(withdraw_unused(
+        &program_id,
+        &plan,
+        &impostor,
+        &impostor.vault,
+        &other.token_account,
+        100,
+    ));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = test.process(withdraw_unused(
+        &program_id,
+        &plan,
+        &other,
+        &impostor.vault,
+        &other.token_account,
+        100,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidVaultAccount)));
+}
+
+#[test]
+fn claims_check_the_mint_against_the_plan() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    let other_mint = Plan {
+        mint: test.create_mint(6),
+        ..plan.clone()
+    };
+    let result = test.process(claim(&program_id, &other_mint, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidMint)));
+
+    test.set_mint(plan.mint, spl_token::id(), 9, None);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidMintDecimals)));
+}
@@ -0,0 +1,132 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{SECONDS_PER_DAY, SUBSCRIPTION_SIZE},
+    error::RecurringPaymentsError,
+    state::CycleInterval,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const MONTH: i64 = 30 * SECONDS_PER_DAY;
+
+#[test]
+fn subscriptions_end_after_max_cycles_and_close_once_their_vault_is_empty() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        max_cycles: 2,
+        ..PlanTerms::default()
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, terms);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    let result = test.process(close_subscription(&program_id, &subscriber, Some(&subscriber.vault)));
+    assert_eq!(result, Err(error(RecurringPaymentsError::TermNotComplete)));
+
+    test.warp_to(NOW + MONTH);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + 2 * MONTH);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::TermCompleted)));
+    assert_eq!(test.token_balance(&plan.payout), 200);
+
+    let result = test.process(close_subscription(&program_id, &subscriber, Some(&subscriber.vault)));
+    assert_eq!(result, Err(error(RecurringPaymentsError::VaultNotEmpty)));
+
+    // Nothing is reserved for a cycle past the term, the whole deposit left can be withdrawn
+    test.process(withdraw_unused(
+        &program_id,
+        &plan,
+        &subscriber,
+        &subscriber.vault,
+        &subscriber.token_account,
+        100,
+    ))
+    .unwrap();
+    assert_eq!(test.token_balance(&subscriber.token_account), 800);
+
+    // Anyone can close it, the rent goes back to the subscriber
+    let rent = test.lamports(&subscriber.subscription);
+    let wallet = test.lamports(&subscriber.owner);
+    let stranger = Subscriber {
+        owner: Pubkey::new_unique(),
+        ..subscriber.clone()
+    };
+    let result = test.process(close_subscription(&program_id, &stranger, Some(&subscriber.vault)));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriber)));
+    test.process(close_subscription(&program_id, &subscriber, Some(&subscriber.vault)))
+        .unwrap();
+    assert!(test.account(&subscriber.subscription).is_none());
+    assert_eq!(test.lamports(&subscriber.owner), wallet + rent);
+}
+
+#[test]
+fn subscriptions_end_at_the_plan_end_timestamp() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        end_timestamp: NOW + MONTH + MONTH / 2,
+        ..PlanTerms::default()
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, terms);
+    let subscriber = test.subscribe(&plan, 1_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 1_000);
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    test.warp_to(NOW + MONTH);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+
+    test.warp_to(terms.end_timestamp);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::TermCompleted)));
+    assert_eq!(test.token_balance(&plan.payout), 200);
+
+    let result = test.subscribe(&plan, 1_000, 0);
+    assert_eq!(result.map(|_| ()), Err(error(RecurringPaymentsError::TermCompleted)));
+
+    test.process(close_subscription(&program_id, &subscriber, None))
+        .unwrap();
+    assert!(test.account(&subscriber.subscription).is_none());
+}
+
+#[test]
+fn native_subscriptions_close_back_to_the_subscriber_wallet() {
+    let mut test = ProgramTest::new();
+    let terms = PlanTerms {
+        max_cycles: 1,
+        ..PlanTerms::default()
+    };
+    let plan = test.create_plan_with_terms(&spl_token::native_mint::id(), CycleInterval::Days(30), 1_000_000, terms);
+    let subscriber = test.subscribe(&plan, LAMPORTS_PER_WALLET, 2).unwrap();
+    let program_id = test.program_id;
+
+    let payout = test.lamports(&plan.payout);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+        .unwrap();
+    assert_eq!(test.lamports(&plan.payout), payout + 1_000_000);
+
+    test.warp_to(NOW + MONTH);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription));
+    assert_eq!(result, Err(error(RecurringPaymentsError::TermCompleted)));
+
+    // The unused cycle is returned along with the rent
+    let subscription = test.lamports(&subscriber.subscription);
+    let wallet = test.lamports(&subscriber.owner);
+    assert_eq!(subscription, test.rent.minimum_balance(SUBSCRIPTION_SIZE) + 1_000_000);
+    test.process(close_subscription(&program_id, &subscriber, None))
+        .unwrap();
+    assert!(test.account(&subscriber.subscription).is_none());
+    assert_eq!(test.lamports(&subscriber.owner), wallet + subscription);
+}
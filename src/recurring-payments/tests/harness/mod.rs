@@ -0,0 +1,1268 @@
+//! Runs the program's instructions against accounts held in memory. Accounts are serialized the way the runtime
+//! passes them to a program, cross-program invocations of the system and token programs run in process, and every
+//! program's account changes are checked against the runtime's ownership and writability rules.
+
+#![allow(dead_code)]
+
+use recurring_payments_service::{
+    constants::{
+        CUSTOMER_REFERENCE_LEN, PLAN_ESCROW_SEED, SETTLEMENT_VAULT_SEED, SUBSCRIPTION_PLAN_SIZE, SUBSCRIPTION_SIZE,
+    },
+    instruction,
+    processor::Processor,
+    state::{AccountType, CycleInterval, PriceFeed},
+};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader,
+    clock::{Clock, UnixTimestamp},
+    entrypoint::{deserialize, ProgramResult, BPF_ALIGN_OF_U128, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program, sysvar,
+};
+use solana_sdk::{
+    account::{create_account_for_test, Account},
+    native_loader,
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeConfig},
+        BaseStateWithExtensions, BaseStateWithExtensionsMut, ExtensionType, StateWithExtensions,
+        StateWithExtensionsMut,
+    },
+    state::{Account as TokenAccount, AccountState as TokenAccountState, Mint},
+};
+use std::{cell::RefCell, collections::HashMap, mem::size_of, sync::Once};
+
+/// Start of the test clock, 2023-11-14T22:13:20Z
+pub const NOW: UnixTimestamp = 1_700_000_000;
+
+/// Accounts and sysvars the program runs against
+pub struct ProgramTest {
+    pub program_id: Pubkey,
+    pub clock: Clock,
+    pub rent: Rent,
+    accounts: HashMap<Pubkey, Account>,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+}
+
+impl ProgramTest {
+    pub fn new() -> Self {
+        let mut program_test = Self {
+            program_id: Pubkey::new_unique(),
+            clock: Clock {
+                unix_timestamp: NOW,
+                ..Clock::default()
+            },
+            rent: Rent::default(),
+            accounts: HashMap::new(),
+            return_data: None,
+        };
+        program_test.add_program(system_program::id(), native_loader::id());
+        program_test.add_program(spl_token::id(), bpf_loader::id());
+        program_test.add_program(spl_token_2022::id(), bpf_loader::id());
+        program_test.add_program(program_test.program_id, bpf_loader::id());
+        program_test.set_account(sysvar::rent::id(), create_account_for_test(&program_test.rent));
+        program_test.set_mint(
+            spl_token::native_mint::id(),
+            spl_token::id(),
+            spl_token::native_mint::DECIMALS,
+            None,
+        );
+        program_test.warp_to(NOW);
+        program_test
+    }
+
+    fn add_program(&mut self, program_id: Pubkey, loader: Pubkey) {
+        let program = Account {
+            lamports: self.rent.minimum_balance(0),
+            data: vec![],
+            owner: loader,
+            executable: true,
+            rent_epoch: 0,
+        };
+        self.set_account(program_id, program);
+    }
+
+    /// Moves the clock sysvar to `unix_timestamp`
+    pub fn warp_to(&mut self, unix_timestamp: UnixTimestamp) {
+        self.clock.unix_timestamp = unix_timestamp;
+        self.set_account(sysvar::clock::id(), create_account_for_test(&self.clock));
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, account: Account) {
+        self.accounts.insert(key, account);
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.account(key).map_or(0, |account| account.lamports)
+    }
+
+    /// Return data the last processed instruction left
+    pub fn return_data(&self) -> Option<&(Pubkey, Vec<u8>)> {
+        self.return_data.as_ref()
+    }
+
+    /// Creates a system account holding `lamports`
+    pub fn create_wallet(&mut self, lamports: u64) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.set_account(key, Account::new(lamports, 0, &system_program::id()));
+        key
+    }
+
+    /// Creates a rent exempt account of `len` zeroed bytes owned by the program, as a client would right before
+    /// the instruction that initializes it
+    pub fn create_program_account(&mut self, len: usize) -> Pubkey {
+        let key = Pubkey::new_unique();
+        let program_id = self.program_id;
+        self.set_account(key, Account::new(self.rent.minimum_balance(len), len, &program_id));
+        key
+    }
+
+    /// Creates an spl-token mint
+    pub fn create_mint(&mut self, decimals: u8) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.set_mint(key, spl_token::id(), decimals, None);
+        key
+    }
+
+    /// Creates a Token-2022 mint, withholding `transfer_fee_basis_points` of every transfer up to `maximum_fee` when
+    /// a fee is given
+    pub fn create_mint_2022(&mut self, decimals: u8, transfer_fee: Option<(u16, u64)>) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.set_mint(key, spl_token_2022::id(), decimals, transfer_fee);
+        key
+    }
+
+    /// Writes a mint account at `key`
+    pub fn set_mint(&mut self, key: Pubkey, token_program: Pubkey, decimals: u8, transfer_fee: Option<(u16, u64)>) {
+        let extension_types = if transfer_fee.is_some() {
+            vec![ExtensionType::TransferFeeConfig]
+        } else {
+            vec![]
+        };
+        let len = ExtensionType::try_calculate_account_len::<Mint>(&extension_types).unwrap();
+        let mut account = Account::new(self.rent.minimum_balance(len), len, &token_program);
+        let mut mint = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut account.data).unwrap();
+        if let Some((transfer_fee_basis_points, maximum_fee)) = transfer_fee {
+            let transfer_fee = TransferFee {
+                epoch: 0.into(),
+                maximum_fee: maximum_fee.into(),
+                transfer_fee_basis_points: transfer_fee_basis_points.into(),
+            };
+            let transfer_fee_config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+            transfer_fee_config.older_transfer_fee = transfer_fee;
+            transfer_fee_config.newer_transfer_fee = transfer_fee;
+        }
+        mint.base = Mint {
+            mint_authority: Some(Pubkey::new_unique()).into(),
+            supply: u64::MAX,
+            decimals,
+            is_initialized: true,
+            freeze_authority: None.into(),
+        };
+        mint.pack_base();
+        if !extension_types.is_empty() {
+            mint.init_account_type().unwrap();
+        }
+        self.set_account(key, account);
+    }
+
+    /// Writes a price feed account at `key` owned by `oracle`, pricing one whole token at `price * 10^exponent` USD
+    pub fn set_price_feed(
+        &mut self,
+        key: Pubkey,
+        oracle: &Pubkey,
+        price: i64,
+        exponent: i32,
+        publish_time: UnixTimestamp,
+    ) {
+        let mut account = Account::new(self.rent.minimum_balance(PriceFeed::LEN), PriceFeed::LEN, oracle);
+        let price_feed = PriceFeed {
+            account_type: AccountType::PriceFeed,
+            is_initialized: true,
+            price,
+            confidence: 0,
+            exponent,
+            publish_time,
+        };
+        PriceFeed::pack(price_feed, &mut account.data).unwrap();
+        self.set_account(key, account);
+    }
+
+    /// Creates a price feed owned by `oracle`, published now
+    pub fn create_price_feed(&mut self, oracle: &Pubkey, price: i64, exponent: i32) -> Pubkey {
+        let key = Pubkey::new_unique();
+        self.set_price_feed(key, oracle, price, exponent, self.clock.unix_timestamp);
+        key
+    }
+
+    /// Creates a token account of `mint` holding `amount`, with the extensions the mint requires
+    pub fn create_token_account(&mut self, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+        let key = Pubkey::new_unique();
+        let mint_account = self.account(mint).expect("mint exists").clone();
+        let extension_types = ExtensionType::get_required_init_account_extensions(
+            &StateWithExtensions::<Mint>::unpack(&mint_account.data)
+                .unwrap()
+                .get_extension_types()
+                .unwrap(),
+        );
+        let len = ExtensionType::try_calculate_account_len::<TokenAccount>(&extension_types).unwrap();
+        let mut account = Account::new(self.rent.minimum_balance(len), len, &mint_account.owner);
+        let mut token_account =
+            StateWithExtensionsMut::<TokenAccount>::unpack_uninitialized(&mut account.data).unwrap();
+        for extension_type in extension_types {
+            token_account.init_account_extension_from_type(extension_type).unwrap();
+        }
+        token_account.base = TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: TokenAccountState::Initialized,
+            ..TokenAccount::default()
+        };
+        token_account.pack_base();
+        if len > TokenAccount::LEN {
+            token_account.init_account_type().unwrap();
+        }
+        self.set_account(key, account);
+        key
+    }
+
+    pub fn token_account(&self, key: &Pubkey) -> TokenAccount {
+        StateWithExtensions::<TokenAccount>::unpack(&self.account(key).expect("token account exists").data)
+            .unwrap()
+            .base
+    }
+
+    pub fn token_balance(&self, key: &Pubkey) -> u64 {
+        self.token_account(key).amount
+    }
+
+    /// Approves `delegate` to move `amount` out of a token account, as the owner would with an `Approve`
+    pub fn approve(&mut self, key: &Pubkey, delegate: &Pubkey, amount: u64) {
+        let account = self.accounts.get_mut(key).unwrap();
+        let mut token_account = StateWithExtensionsMut::<TokenAccount>::unpack(&mut account.data).unwrap();
+        token_account.base.delegate = Some(*delegate).into();
+        token_account.base.delegated_amount = amount;
+        token_account.pack_base();
+    }
+
+    /// Processes one instruction of the program as a transaction of its own. Signers are the accounts the
+    /// instruction marks as signers. Nothing changes when it fails.
+    pub fn process(&mut self, instruction: Instruction) -> ProgramResult {
+        static INSTALL_STUBS: Once = Once::new();
+        INSTALL_STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(Runtime));
+        });
+        assert_eq!(
+            instruction.program_id, self.program_id,
+            "only the program itself is processed"
+        );
+
+        let (mut buffer, layout) = serialize(&instruction, &self.accounts);
+        let input = buffer.as_mut_ptr() as *mut u8;
+        let writable = layout.iter().map(|account| account.is_writable).collect();
+        let initial = unsafe { snapshot(input, &layout) };
+        let context = Context {
+            clock: self.clock.clone(),
+            rent: self.rent,
+            return_data: None,
+            input,
+            checkpoint: initial.clone(),
+            layout,
+            frames: vec![Frame {
+                program_id: self.program_id,
+                writable,
+            }],
+            cpi_error: None,
+        };
+        CONTEXT.with(|cell| *cell.borrow_mut() = Some(context));
+
+        let result = {
+            let (program_id, account_infos, instruction_data) = unsafe { deserialize(input) };
+            Processor::process(program_id, &account_infos, instruction_data)
+        };
+
+        let context = CONTEXT.with(|cell| cell.borrow_mut().take()).unwrap();
+        // A failed invocation fails the whole transaction, even when the program carries on
+        let result = result.and(context.cpi_error.map_or(Ok(()), Err));
+        if result.is_ok() {
+            let accounts = unsafe { snapshot(input, &context.layout) };
+            verify(&context.frames[0], &context.checkpoint, &accounts, &context.layout);
+            for ((account_layout, state), initial) in context.layout.iter().zip(accounts).zip(initial) {
+                if state == initial {
+                    continue;
+                }
+                assert!(
+                    state.lamports == 0 || state.lamports >= self.rent.minimum_balance(state.data.len()),
+                    "account {} is left below rent exemption",
+                    account_layout.key
+                );
+                if state.lamports == 0 {
+                    self.accounts.remove(&account_layout.key);
+                    continue;
+                }
+                let account = self.accounts.entry(account_layout.key).or_default();
+                account.lamports = state.lamports;
+                account.data = state.data;
+                account.owner = state.owner;
+            }
+            self.return_data = context.return_data;
+        }
+        drop(buffer);
+        result
+    }
+}
+
+impl Default for ProgramTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the authority PDA of a plan and its nonce
+pub fn authority_id(program_id: &Pubkey, subscription_plan: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&subscription_plan.to_bytes()[..32]], program_id)
+}
+
+/// Where the runtime put an account in the serialized input
+struct AccountLayout {
+    key: Pubkey,
+    is_writable: bool,
+    offset: usize,
+}
+
+impl AccountLayout {
+    fn owner(&self) -> usize {
+        self.offset + size_of::<Pubkey>()
+    }
+
+    fn lamports(&self) -> usize {
+        self.owner() + size_of::<Pubkey>()
+    }
+
+    fn data_len(&self) -> usize {
+        self.lamports() + size_of::<u64>()
+    }
+
+    fn data(&self) -> usize {
+        self.data_len() + size_of::<u64>()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct AccountSnapshot {
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+}
+
+/// A program running in the transaction and the accounts it was given write access to
+struct Frame {
+    program_id: Pubkey,
+    writable: Vec<bool>,
+}
+
+struct Context {
+    clock: Clock,
+    rent: Rent,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+    input: *mut u8,
+    layout: Vec<AccountLayout>,
+    frames: Vec<Frame>,
+    /// Account states when the running program last called or returned from another program
+    checkpoint: Vec<AccountSnapshot>,
+    cpi_error: Option<ProgramError>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+fn with_context<R>(f: impl FnOnce(&mut Context) -> R) -> R {
+    CONTEXT.with(|cell| f(cell.borrow_mut().as_mut().expect("an instruction is being processed")))
+}
+
+/// Serializes the accounts of an instruction in the aligned input format programs read with `deserialize`
+fn serialize(instruction: &Instruction, accounts: &HashMap<Pubkey, Account>) -> (Vec<u64>, Vec<AccountLayout>) {
+    let mut unique: Vec<AccountMeta> = vec![];
+    for meta in &instruction.accounts {
+        match unique.iter_mut().find(|unique| unique.pubkey == meta.pubkey) {
+            Some(unique) => {
+                unique.is_signer |= meta.is_signer;
+                unique.is_writable |= meta.is_writable;
+            },
+            None => unique.push(meta.clone()),
+        }
+    }
+
+    let mut input = vec![];
+    let mut layout = vec![];
+    input.extend_from_slice(&(instruction.accounts.len() as u64).to_le_bytes());
+    for meta in &instruction.accounts {
+        if let Some(index) = layout
+            .iter()
+            .position(|account: &AccountLayout| account.key == meta.pubkey)
+        {
+            input.push(index as u8);
+            input.extend_from_slice(&[0; 7]);
+            layout.push(AccountLayout {
+                key: meta.pubkey,
+                is_writable: false,
+                offset: usize::MAX,
+            });
+            continue;
+        }
+        let meta = unique.iter().find(|unique| unique.pubkey == meta.pubkey).unwrap();
+        let account = accounts.get(&meta.pubkey).cloned().unwrap_or_default();
+        input.push(NON_DUP_MARKER);
+        input.push(meta.is_signer as u8);
+        input.push(meta.is_writable as u8);
+        input.push(account.executable as u8);
+        input.extend_from_slice(&[0; 4]);
+        let offset = input.len();
+        input.extend_from_slice(meta.pubkey.as_ref());
+        input.extend_from_slice(account.owner.as_ref());
+        input.extend_from_slice(&account.lamports.to_le_bytes());
+        input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&account.data);
+        input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+        input.resize(
+            input.len() + (input.len() as *const u8).align_offset(BPF_ALIGN_OF_U128),
+            0,
+        );
+        input.extend_from_slice(&account.rent_epoch.to_le_bytes());
+        layout.push(AccountLayout {
+            key: meta.pubkey,
+            is_writable: meta.is_writable,
+            offset,
+        });
+    }
+    input.extend_from_slice(&(instruction.data.len() as u64).to_le_bytes());
+    input.extend_from_slice(&instruction.data);
+    input.extend_from_slice(instruction.program_id.as_ref());
+
+    // Duplicates only point at the first occurrence
+    layout.retain(|account| account.offset != usize::MAX);
+
+    let mut aligned = vec![0u64; input.len().div_ceil(8)];
+    unsafe { std::ptr::copy_nonoverlapping(input.as_ptr(), aligned.as_mut_ptr() as *mut u8, input.len()) };
+    (aligned, layout)
+}
+
+/// Reads the current state of every account from the serialized input
+unsafe fn snapshot(input: *const u8, layout: &[AccountLayout]) -> Vec<AccountSnapshot> {
+    layout
+        .iter()
+        .map(|account| {
+            let owner = *(input.add(account.owner()) as *const Pubkey);
+            let lamports = *(input.add(account.lamports()) as *const u64);
+            let data_len = *(input.add(account.data_len()) as *const u64) as usize;
+            let data = std::slice::from_raw_parts(input.add(account.data()), data_len).to_vec();
+            AccountSnapshot { owner, lamports, data }
+        })
+        .collect()
+}
+
+/// Checks what a program changed between two snapshots is allowed by the runtime. A violation is a bug in the
+/// program, not an error it returns, so it panics.
+fn verify(frame: &Frame, before: &[AccountSnapshot], after: &[AccountSnapshot], layout: &[AccountLayout]) {
+    let total = |states: &[AccountSnapshot]| states.iter().map(|state| state.lamports as u128).sum::<u128>();
+    assert_eq!(
+        total(before),
+        total(after),
+        "{} created or destroyed lamports",
+        frame.program_id
+    );
+
+    for (((before, after), account), writable) in before.iter().zip(after).zip(layout).zip(&frame.writable) {
+        if before == after {
+            continue;
+        }
+        assert!(
+            writable,
+            "{} modified read-only account {}",
+            frame.program_id, account.key
+        );
+        let is_owner = before.owner == frame.program_id;
+        assert!(
+            is_owner || before.owner == after.owner,
+            "{} changed the owner of account {} it does not own",
+            frame.program_id,
+            account.key
+        );
+        assert!(
+            is_owner || before.data == after.data,
+            "{} modified the data of account {} it does not own",
+            frame.program_id,
+            account.key
+        );
+        assert!(
+            is_owner || before.lamports <= after.lamports,
+            "{} debited account {} it does not own",
+            frame.program_id,
+            account.key
+        );
+    }
+}
+
+/// Syscalls of the running program, served from the thread's context
+struct Runtime;
+
+impl SyscallStubs for Runtime {
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = with_context(|context| context.clock.clone());
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let rent = with_context(|context| context.rent);
+        unsafe { *(var_addr as *mut Rent) = rent };
+        0
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        with_context(|context| context.return_data.clone())
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        with_context(|context| {
+            let program_id = context.frames.last().unwrap().program_id;
+            context.return_data = (!data.is_empty()).then(|| (program_id, data.to_vec()));
+        })
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = with_context(|context| {
+            let accounts = unsafe { snapshot(context.input, &context.layout) };
+            let frame = context.frames.last().unwrap();
+            verify(frame, &context.checkpoint, &accounts, &context.layout);
+            context.checkpoint = accounts;
+            frame.program_id
+        });
+
+        let signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(
+            account_infos
+                .iter()
+                .any(|account_info| *account_info.key == instruction.program_id),
+            "{} is invoked without its program account",
+            instruction.program_id
+        );
+        let mut callee_infos = vec![];
+        for meta in &instruction.accounts {
+            let account_info = account_infos
+                .iter()
+                .find(|account_info| *account_info.key == meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            if meta.is_signer && !account_info.is_signer && !signers.contains(&meta.pubkey) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            assert!(
+                !meta.is_writable || account_info.is_writable,
+                "{} passes read-only account {} as writable",
+                caller,
+                meta.pubkey
+            );
+            let mut callee_info = account_info.clone();
+            callee_info.is_signer = meta.is_signer;
+            callee_info.is_writable = meta.is_writable;
+            callee_infos.push(callee_info);
+        }
+
+        with_context(|context| {
+            let writable = context
+                .layout
+                .iter()
+                .map(|account| {
+                    instruction
+                        .accounts
+                        .iter()
+                        .any(|meta| meta.pubkey == account.key && meta.is_writable)
+                })
+                .collect();
+            context.frames.push(Frame {
+                program_id: instruction.program_id,
+                writable,
+            });
+        });
+
+        let result = if instruction.program_id == system_program::id() {
+            process_system_instruction(&callee_infos, &instruction.data)
+        } else if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(&instruction.program_id, &callee_infos, &instruction.data)
+        } else if instruction.program_id == spl_token_2022::id() {
+            spl_token_2022::processor::Processor::process(&instruction.program_id, &callee_infos, &instruction.data)
+        } else {
+            panic!("{} can't be invoked", instruction.program_id)
+        };
+
+        with_context(|context| {
+            let accounts = unsafe { snapshot(context.input, &context.layout) };
+            let frame = context.frames.pop().unwrap();
+            match &result {
+                Ok(()) => verify(&frame, &context.checkpoint, &accounts, &context.layout),
+                Err(error) => context.cpi_error = Some(error.clone()),
+            }
+            context.checkpoint = accounts;
+        });
+        result
+    }
+}
+
+/// The system program instructions programs invoke to move lamports and create accounts at their PDAs
+fn process_system_instruction(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    // SystemError::AccountAlreadyInUse
+    let account_already_in_use = ProgramError::Custom(0);
+    match limited_deserialize(data, 1_232).map_err(|_| ProgramError::InvalidInstructionData)? {
+        SystemInstruction::Transfer { lamports } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if !from.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if !from.data_is_empty() || *from.owner != system_program::id() {
+                return Err(ProgramError::InvalidArgument);
+            }
+            if from.lamports() < lamports {
+                return Err(ProgramError::InsufficientFunds);
+            }
+            **from.try_borrow_mut_lamports()? -= lamports;
+            **to.try_borrow_mut_lamports()? += lamports;
+            Ok(())
+        },
+        SystemInstruction::Allocate { space } => {
+            let account = &accounts[0];
+            if !account.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if !account.data_is_empty() || *account.owner != system_program::id() {
+                return Err(account_already_in_use);
+            }
+            account.realloc(space as usize, true)
+        },
+        SystemInstruction::Assign { owner } => {
+            let account = &accounts[0];
+            if *account.owner == owner {
+                return Ok(());
+            }
+            if !account.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            account.assign(&owner);
+            Ok(())
+        },
+        instruction => panic!("{:?} is not supported", instruction),
+    }
+}
+
+/// A plan created through the program together with the keys tests act with
+#[derive(Clone)]
+pub struct Plan {
+    pub plan: Pubkey,
+    pub owner: Pubkey,
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    /// The program owning the mint
+    pub mint_program: Pubkey,
+    pub payout: Pubkey,
+    pub timeframe: CycleInterval,
+    pub max_amount: u64,
+    /// The feed converting `max_amount` from USD cents, for USD priced mints
+    pub price_feed: Option<Pubkey>,
+    /// Charged on signup along with the upfront period, in the same unit as `max_amount`
+    pub setup_fee: u64,
+    pub terms: PlanTerms,
+    /// The mint's escrow account once the plan has a dispute window, the subscription account holds SOL escrow
+    pub escrow: Option<Pubkey>,
+    /// The mint's settlement vault once the plan has a settlement interval
+    pub settlement_vault: Option<Pubkey>,
+}
+
+impl Plan {
+    pub fn is_native(&self) -> bool {
+        self.mint == spl_token::native_mint::id()
+    }
+
+    /// The program moving the plan's funds, the system program for SOL plans
+    pub fn token_program(&self) -> Pubkey {
+        if self.is_native() {
+            system_program::id()
+        } else {
+            self.mint_program
+        }
+    }
+}
+
+/// Plan settings most tests leave at their defaults, an open ended plan
+#[derive(Clone, Copy, Default)]
+pub struct PlanTerms {
+    /// Setup fee of the plan's first mint
+    pub setup_fee: u64,
+    pub max_cycles: u64,
+    pub end_timestamp: UnixTimestamp,
+    pub charge_upfront: bool,
+    pub billing_anchor_day: u8,
+}
+
+/// A subscription created through the program
+#[derive(Clone)]
+pub struct Subscriber {
+    pub subscription: Pubkey,
+    pub owner: Pubkey,
+    pub token_account: Pubkey,
+    pub vault: Pubkey,
+}
+
+impl ProgramTest {
+    /// Creates a plan paid in a fresh spl-token mint
+    pub fn create_plan(&mut self, timeframe: CycleInterval, max_amount: u64) -> Plan {
+        let mint = self.create_mint(6);
+        self.create_plan_in(&mint, timeframe, max_amount)
+    }
+
+    /// Creates a plan paid in `mint`
+    pub fn create_plan_in(&mut self, mint: &Pubkey, timeframe: CycleInterval, max_amount: u64) -> Plan {
+        self.create_plan_with_terms(mint, timeframe, max_amount, PlanTerms::default())
+    }
+
+    /// Creates a plan paid in `mint`, out to a fresh wallet for the native mint
+    pub fn create_plan_with_terms(
+        &mut self,
+        mint: &Pubkey,
+        timeframe: CycleInterval,
+        max_amount: u64,
+        terms: PlanTerms,
+    ) -> Plan {
+        let mint = *mint;
+        let owner = self.create_wallet(LAMPORTS_PER_WALLET);
+        let payout = if mint == spl_token::native_mint::id() {
+            self.create_wallet(LAMPORTS_PER_WALLET)
+        } else {
+            self.create_token_account(&mint, &owner, 0)
+        };
+        self.register_plan(owner, mint, payout, timeframe, max_amount, None, terms)
+    }
+
+    /// Creates a plan paid in a fresh spl-token mint and priced in USD cents through `price_feed`
+    pub fn create_usd_plan(
+        &mut self,
+        timeframe: CycleInterval,
+        cents: u64,
+        price_feed: &Pubkey,
+        terms: PlanTerms,
+    ) -> Plan {
+        let mint = self.create_mint(6);
+        let owner = self.create_wallet(LAMPORTS_PER_WALLET);
+        let payout = self.create_token_account(&mint, &owner, 0);
+        self.register_plan(owner, mint, payout, timeframe, cents, Some(*price_feed), terms)
+    }
+
+    /// Creates a plan paid in SOL out to a fresh wallet
+    pub fn create_native_plan(&mut self, timeframe: CycleInterval, max_amount: u64) -> Plan {
+        self.create_plan_with_terms(
+            &spl_token::native_mint::id(),
+            timeframe,
+            max_amount,
+            PlanTerms::default(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn register_plan(
+        &mut self,
+        owner: Pubkey,
+        mint: Pubkey,
+        payout: Pubkey,
+        timeframe: CycleInterval,
+        max_amount: u64,
+        price_feed: Option<Pubkey>,
+        terms: PlanTerms,
+    ) -> Plan {
+        let plan = self.create_program_account(SUBSCRIPTION_PLAN_SIZE);
+        let (authority, nonce) = authority_id(&self.program_id, &plan);
+        self.process(create_subscription_plan(
+            &self.program_id,
+            &plan,
+            &owner,
+            &authority,
+            &mint,
+            &payout,
+            price_feed.as_ref(),
+            nonce,
+            timeframe,
+            max_amount,
+            terms,
+        ))
+        .unwrap();
+        Plan {
+            plan,
+            owner,
+            authority,
+            mint,
+            mint_program: self.account(&mint).unwrap().owner,
+            payout,
+            timeframe,
+            max_amount,
+            price_feed,
+            setup_fee: terms.setup_fee,
+            terms,
+            escrow: None,
+            settlement_vault: None,
+        }
+    }
+
+    /// Gives a plan a dispute window and an arbiter, and returns the plan as claims see it from then on
+    pub fn set_dispute_window(
+        &mut self,
+        plan: &Plan,
+        arbiter: &Pubkey,
+        dispute_window: i64,
+    ) -> Result<Plan, ProgramError> {
+        self.process(set_dispute_settings(&self.program_id, plan, arbiter, dispute_window))?;
+        let escrow = if dispute_window > 0 && !plan.is_native() {
+            Some(Processor::plan_vault_id(&self.program_id, PLAN_ESCROW_SEED, &plan.plan, &plan.mint).0)
+        } else {
+            None
+        };
+        Ok(Plan { escrow, ..plan.clone() })
+    }
+
+    /// Gives a plan a settlement interval, and returns the plan as claims see it from then on
+    pub fn set_settlement_interval(&mut self, plan: &Plan, settlement_interval: i64) -> Result<Plan, ProgramError> {
+        self.process(set_settlement_schedule(&self.program_id, plan, settlement_interval))?;
+        let settlement_vault = if settlement_interval > 0 && !plan.is_native() {
+            Some(Processor::plan_vault_id(&self.program_id, SETTLEMENT_VAULT_SEED, &plan.plan, &plan.mint).0)
+        } else {
+            None
+        };
+        Ok(Plan {
+            settlement_vault,
+            ..plan.clone()
+        })
+    }
+
+    /// Adds a fresh spl-token mint to a plan and returns the plan as subscribers paying in that mint see it
+    pub fn accept_mint(&mut self, plan: &Plan, max_amount: u64) -> Result<Plan, ProgramError> {
+        self.accept_mint_priced(plan, max_amount, None)
+    }
+
+    /// Adds a fresh spl-token mint to a plan, priced in USD cents when a price feed is given
+    pub fn accept_mint_priced(
+        &mut self,
+        plan: &Plan,
+        max_amount: u64,
+        price_feed: Option<&Pubkey>,
+    ) -> Result<Plan, ProgramError> {
+        let mint = self.create_mint(6);
+        let payout = self.create_token_account(&mint, &plan.owner, 0);
+        let accepted = Plan {
+            mint,
+            mint_program: spl_token::id(),
+            payout,
+            max_amount,
+            price_feed: price_feed.copied(),
+            setup_fee: 0,
+            ..plan.clone()
+        };
+        self.process(add_accepted_mint(&self.program_id, &accepted, &plan.owner))?;
+        Ok(accepted)
+    }
+
+    /// Subscribes a new wallet holding `balance` tokens, or lamports for SOL plans, to a plan, prepaying
+    /// `prepaid_cycles` cycles
+    pub fn subscribe(&mut self, plan: &Plan, balance: u64, prepaid_cycles: u64) -> Result<Subscriber, ProgramError> {
+        self.subscribe_with_reference(plan, balance, prepaid_cycles, [0; CUSTOMER_REFERENCE_LEN])
+    }
+
+    /// Subscribes like `subscribe`, recording the merchant's reference to the customer
+    pub fn subscribe_with_reference(
+        &mut self,
+        plan: &Plan,
+        balance: u64,
+        prepaid_cycles: u64,
+        customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
+    ) -> Result<Subscriber, ProgramError> {
+        let subscription = self.create_program_account(SUBSCRIPTION_SIZE);
+        let (owner, token_account, vault) = if plan.is_native() {
+            let owner = self.create_wallet(balance);
+            (owner, owner, subscription)
+        } else {
+            let owner = self.create_wallet(LAMPORTS_PER_WALLET);
+            let token_account = self.create_token_account(&plan.mint, &owner, balance);
+            let vault = Processor::subscription_vault_id(&self.program_id, &plan.plan, &subscription).0;
+            (owner, token_account, vault)
+        };
+        let subscriber = Subscriber {
+            subscription,
+            owner,
+            token_account,
+            vault,
+        };
+        self.process(create_subscription_with_reference(
+            &self.program_id,
+            plan,
+            &subscriber,
+            prepaid_cycles,
+            customer_reference,
+        ))?;
+        Ok(subscriber)
+    }
+}
+
+/// Lamports new wallets start with
+pub const LAMPORTS_PER_WALLET: u64 = 10_000_000_000;
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_subscription_plan(
+    program_id: &Pubkey,
+    plan: &Pubkey,
+    owner: &Pubkey,
+    authority: &Pubkey,
+    mint: &Pubkey,
+    payout: &Pubkey,
+    price_feed: Option<&Pubkey>,
+    nonce: u8,
+    timeframe: CycleInterval,
+    max_amount: u64,
+    terms: PlanTerms,
+) -> Instruction {
+    instruction::create_subscription_plan(
+        program_id,
+        plan,
+        owner,
+        authority,
+        mint,
+        payout,
+        price_feed,
+        nonce,
+        timeframe,
+        max_amount,
+        terms.setup_fee,
+        terms.max_cycles,
+        terms.end_timestamp,
+        terms.charge_upfront,
+        terms.billing_anchor_day,
+    )
+}
+
+pub fn create_subscription(
+    program_id: &Pubkey,
+    plan: &Plan,
+    subscriber: &Subscriber,
+    prepaid_cycles: u64,
+) -> Instruction {
+    create_subscription_with_reference(
+        program_id,
+        plan,
+        subscriber,
+        prepaid_cycles,
+        [0; CUSTOMER_REFERENCE_LEN],
+    )
+}
+
+pub fn create_subscription_with_reference(
+    program_id: &Pubkey,
+    plan: &Plan,
+    subscriber: &Subscriber,
+    prepaid_cycles: u64,
+    customer_reference: [u8; CUSTOMER_REFERENCE_LEN],
+) -> Instruction {
+    let vault = if prepaid_cycles > 0 && !plan.is_native() {
+        Some(&subscriber.vault)
+    } else {
+        None
+    };
+    let (payout, price_feed) = if plan.terms.charge_upfront || plan.setup_fee > 0 {
+        (Some(&plan.payout), plan.price_feed.as_ref())
+    } else {
+        (None, None)
+    };
+    instruction::create_subscription(
+        program_id,
+        &subscriber.subscription,
+        &plan.plan,
+        &subscriber.owner,
+        &subscriber.token_account,
+        &plan.mint,
+        &plan.token_program(),
+        vault,
+        payout,
+        price_feed,
+        plan.timeframe,
+        plan.max_amount,
+        prepaid_cycles,
+        customer_reference,
+    )
+}
+
+/// The account a claim reads after the plan's escrow or settlement vault, the rent sysvar for SOL plans
+fn rent_or_price_feed(plan: &Plan) -> Option<Pubkey> {
+    if plan.is_native() {
+        Some(sysvar::rent::id())
+    } else {
+        plan.price_feed
+    }
+}
+
+pub fn claim(program_id: &Pubkey, plan: &Plan, subscriber: &Subscriber, source: &Pubkey) -> Instruction {
+    instruction::claim(
+        program_id,
+        &plan.owner,
+        &subscriber.subscription,
+        &plan.plan,
+        &plan.authority,
+        source,
+        &plan.payout,
+        &plan.mint,
+        &plan.token_program(),
+        &Processor::subscriber_profile_id(program_id, &subscriber.owner).0,
+        plan.escrow.or(plan.settlement_vault).as_ref(),
+        rent_or_price_feed(plan).as_ref(),
+    )
+}
+
+/// Claims every subscriber of a plan in one instruction, each paying out of its own `source`
+pub fn claim_batch(program_id: &Pubkey, plan: &Plan, subscribers: &[(&Subscriber, &Pubkey)]) -> Instruction {
+    let claims: Vec<_> = subscribers
+        .iter()
+        .map(|(subscriber, source)| {
+            let subscriber_profile = Processor::subscriber_profile_id(program_id, &subscriber.owner).0;
+            (subscriber.subscription, **source, subscriber_profile)
+        })
+        .collect();
+    instruction::claim_batch(
+        program_id,
+        &plan.owner,
+        &plan.plan,
+        &plan.authority,
+        &plan.payout,
+        &plan.mint,
+        &plan.token_program(),
+        plan.escrow.or(plan.settlement_vault).as_ref(),
+        rent_or_price_feed(plan).as_ref(),
+        &claims,
+    )
+}
+
+pub fn top_up(
+    program_id: &Pubkey,
+    plan: &Plan,
+    source_owner: &Pubkey,
+    subscription: &Pubkey,
+    source: &Pubkey,
+    vault: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    instruction::top_up(
+        program_id,
+        source_owner,
+        subscription,
+        source,
+        vault,
+        &plan.mint,
+        &plan.token_program(),
+        amount,
+    )
+}
+
+pub fn withdraw_unused(
+    program_id: &Pubkey,
+    plan: &Plan,
+    subscriber: &Subscriber,
+    vault: &Pubkey,
+    destination: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    instruction::withdraw_unused(
+        program_id,
+        &subscriber.owner,
+        &subscriber.subscription,
+        &plan.plan,
+        &plan.authority,
+        vault,
+        destination,
+        &plan.mint,
+        &plan.token_program(),
+        plan.is_native(),
+        amount,
+    )
+}
+
+/// Adds `plan.mint` to the plan at `plan.max_amount`, paid out to `plan.payout` and priced through `plan.price_feed`
+pub fn add_accepted_mint(program_id: &Pubkey, plan: &Plan, owner: &Pubkey) -> Instruction {
+    instruction::add_accepted_mint(
+        program_id,
+        &plan.plan,
+        owner,
+        &plan.mint,
+        &plan.payout,
+        plan.price_feed.as_ref(),
+        plan.max_amount,
+        plan.setup_fee,
+    )
+}
+
+pub fn close_subscription(program_id: &Pubkey, subscriber: &Subscriber, vault: Option<&Pubkey>) -> Instruction {
+    instruction::close_subscription(program_id, &subscriber.subscription, &subscriber.owner, vault)
+}
+
+pub fn set_spending_cap(program_id: &Pubkey, subscriber: &Pubkey, mint: &Pubkey, monthly_cap: u64) -> Instruction {
+    let subscriber_profile = Processor::subscriber_profile_id(program_id, subscriber).0;
+    instruction::set_spending_cap(program_id, &subscriber_profile, subscriber, mint, monthly_cap)
+}
+
+pub fn refund(
+    program_id: &Pubkey,
+    plan: &Plan,
+    subscriber: &Subscriber,
+    refund_authority: &Pubkey,
+    amount: u64,
+    restore_allowance: bool,
+) -> Instruction {
+    instruction::refund(
+        program_id,
+        refund_authority,
+        &subscriber.subscription,
+        &plan.plan,
+        &plan.payout,
+        &subscriber.token_account,
+        &plan.mint,
+        &plan.token_program(),
+        amount,
+        restore_allowance,
+    )
+}
+
+/// The vault PDA, mint and token program each of the plan's mints is given when a plan starts holding claims, none
+/// for SOL
+fn plan_vaults(program_id: &Pubkey, plan: &Plan, seed: &[u8]) -> Vec<(Pubkey, Pubkey, Pubkey)> {
+    if plan.is_native() {
+        return Vec::new();
+    }
+    let vault = Processor::plan_vault_id(program_id, seed, &plan.plan, &plan.mint).0;
+    vec![(vault, plan.mint, plan.mint_program)]
+}
+
+/// Creates a `SetDisputeSettings` instruction for a plan with a single mint
+pub fn set_dispute_settings(program_id: &Pubkey, plan: &Plan, arbiter: &Pubkey, dispute_window: i64) -> Instruction {
+    instruction::set_dispute_settings(
+        program_id,
+        &plan.plan,
+        &plan.owner,
+        arbiter,
+        &plan_vaults(program_id, plan, PLAN_ESCROW_SEED),
+        dispute_window,
+    )
+}
+
+pub fn open_dispute(program_id: &Pubkey, plan: &Plan, subscriber: &Subscriber) -> Instruction {
+    instruction::open_dispute(program_id, &subscriber.owner, &subscriber.subscription, &plan.plan)
+}
+
+/// The account a subscription's escrow is held in, the subscription account itself for SOL
+fn escrow_account(plan: &Plan, subscriber: &Subscriber) -> Pubkey {
+    plan.escrow.unwrap_or(subscriber.subscription)
+}
+
+pub fn resolve_dispute(
+    program_id: &Pubkey,
+    plan: &Plan,
+    subscriber: &Subscriber,
+    arbiter: &Pubkey,
+    refund_amount: u64,
+) -> Instruction {
+    instruction::resolve_dispute(
+        program_id,
+        arbiter,
+        &subscriber.subscription,
+        &plan.plan,
+        &plan.authority,
+        &escrow_account(plan, subscriber),
+        &plan.payout,
+        &subscriber.token_account,
+        &plan.mint,
+        &plan.token_program(),
+        plan.is_native(),
+        refund_amount,
+    )
+}
+
+pub fn release_escrow(program_id: &Pubkey, plan: &Plan, subscriber: &Subscriber) -> Instruction {
+    instruction::release_escrow(
+        program_id,
+        &subscriber.subscription,
+        &plan.plan,
+        &plan.authority,
+        &escrow_account(plan, subscriber),
+        &plan.payout,
+        &plan.mint,
+        &plan.token_program(),
+        plan.is_native(),
+    )
+}
+
+/// Creates a `SetSettlementSchedule` instruction for a plan with a single mint
+pub fn set_settlement_schedule(program_id: &Pubkey, plan: &Plan, settlement_interval: i64) -> Instruction {
+    instruction::set_settlement_schedule(
+        program_id,
+        &plan.plan,
+        &plan.owner,
+        &plan_vaults(program_id, plan, SETTLEMENT_VAULT_SEED),
+        settlement_interval,
+    )
+}
+
+pub fn settle(program_id: &Pubkey, plan: &Plan, settlement_vault: &Pubkey) -> Instruction {
+    instruction::settle(
+        program_id,
+        &plan.plan,
+        &plan.authority,
+        settlement_vault,
+        &plan.payout,
+        &plan.mint,
+        &plan.mint_program,
+    )
+}
+
+pub fn set_keeper_tip(program_id: &Pubkey, plan: &Plan, keeper_tip: u64) -> Instruction {
+    instruction::set_keeper_tip(program_id, &plan.plan, &plan.owner, keeper_tip)
+}
+
+pub fn migrate_subscription_plan(program_id: &Pubkey, payer: &Pubkey, plan: &Plan) -> Instruction {
+    instruction::migrate_subscription_plan(program_id, payer, &plan.plan, &plan.payout, &plan.mint)
+}
+
+pub fn migrate_subscription(program_id: &Pubkey, payer: &Pubkey, plan: &Plan, subscriber: &Subscriber) -> Instruction {
+    instruction::migrate_subscription(
+        program_id,
+        payer,
+        &subscriber.subscription,
+        &plan.plan,
+        &subscriber.token_account,
+    )
+}
+
+pub fn update_plan_metadata(program_id: &Pubkey, plan: &Plan, name: &str, uri: &str, external_id: &str) -> Instruction {
+    instruction::update_plan_metadata(program_id, &plan.plan, &plan.owner, name, uri, external_id)
+}
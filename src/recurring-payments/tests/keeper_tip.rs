@@ -0,0 +1,120 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::SECONDS_PER_DAY, error::RecurringPaymentsError, processor::Processor, state::CycleInterval,
+};
+use solana_program::{instruction::Instruction, program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const TIP: u64 = 5_000;
+
+/// Sends lamports to the plan account for it to pay tips with
+fn fund(test: &mut ProgramTest, plan: &Plan, lamports: u64) {
+    let mut account = test.account(&plan.plan).unwrap().clone();
+    account.lamports += lamports;
+    test.set_account(plan.plan, account);
+}
+
+/// Signs a claim as `keeper` instead of the plan owner
+fn claimed_by(mut instruction: Instruction, keeper: &Pubkey) -> Instruction {
+    instruction.accounts[0].pubkey = *keeper;
+    instruction
+}
+
+#[test]
+fn keepers_claim_for_the_tip_once_the_plan_pays_one() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let keeper = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+
+    let result = test.process(claimed_by(
+        claim(&program_id, &plan, &subscriber, &subscriber.vault),
+        &keeper,
+    ));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    test.process(set_keeper_tip(&program_id, &plan, TIP)).unwrap();
+    let result = test.process(claimed_by(
+        claim(&program_id, &plan, &subscriber, &subscriber.vault),
+        &keeper,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InsufficientKeeperTipFunds)));
+
+    fund(&mut test, &plan, TIP);
+    let plan_lamports = test.lamports(&plan.plan);
+    test.process(claimed_by(
+        claim(&program_id, &plan, &subscriber, &subscriber.vault),
+        &keeper,
+    ))
+    .unwrap();
+    assert_eq!(test.lamports(&keeper), LAMPORTS_PER_WALLET + TIP);
+    assert_eq!(test.lamports(&plan.plan), plan_lamports - TIP);
+    // Claimed funds still go to the plan's payout account
+    assert_eq!(test.token_balance(&plan.payout), 100);
+
+    // The owner claims without a tip
+    let owner_lamports = test.lamports(&plan.owner);
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.lamports(&plan.owner), owner_lamports);
+    assert_eq!(test.lamports(&plan.plan), plan_lamports - TIP);
+}
+
+#[test]
+fn batches_pay_a_tip_per_claimed_subscription_while_the_plan_can_fund_it() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscribers: Vec<_> = (0..3).map(|_| test.subscribe(&plan, 1_000, 3).unwrap()).collect();
+    let keeper = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+    test.process(set_keeper_tip(&program_id, &plan, TIP)).unwrap();
+    fund(&mut test, &plan, 2 * TIP);
+    test.process(claim(&program_id, &plan, &subscribers[0], &subscribers[0].vault))
+        .unwrap();
+
+    // The first subscription was claimed already and earns no tip, the plan runs out of tips on the third
+    let batch: Vec<_> = subscribers
+        .iter()
+        .map(|subscriber| (subscriber, &subscriber.vault))
+        .collect();
+    test.process(claimed_by(claim_batch(&program_id, &plan, &batch), &keeper))
+        .unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![0, 1, 1])));
+    assert_eq!(test.lamports(&keeper), LAMPORTS_PER_WALLET + 2 * TIP);
+    assert_eq!(test.token_balance(&plan.payout), 300);
+
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claimed_by(claim_batch(&program_id, &plan, &batch), &keeper))
+        .unwrap();
+    assert_eq!(test.return_data(), Some(&(program_id, vec![0, 0, 0])));
+    assert_eq!(test.token_balance(&plan.payout), 300);
+}
+
+#[test]
+fn only_the_plan_owner_sets_the_tip() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+
+    let mut instruction = set_keeper_tip(&program_id, &plan, TIP);
+    instruction.accounts[1].pubkey = Pubkey::new_unique();
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+
+    test.process(set_keeper_tip(&program_id, &plan, TIP)).unwrap();
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let keeper = test.create_wallet(LAMPORTS_PER_WALLET);
+    fund(&mut test, &plan, TIP);
+
+    // A keeper can't point the profile at another wallet's to dodge its cap
+    let mut instruction = claimed_by(claim(&program_id, &plan, &subscriber, &subscriber.vault), &keeper);
+    instruction.accounts[9].pubkey = Processor::subscriber_profile_id(&program_id, &keeper).0;
+    let result = test.process(instruction);
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriberProfile)));
+}
@@ -0,0 +1,354 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{
+        LEGACY_SUBSCRIPTION_PLAN_SIZE, LEGACY_SUBSCRIPTION_SIZE, SECONDS_PER_DAY, SUBSCRIPTION_PLAN_SIZE,
+        SUBSCRIPTION_PLAN_V1_SIZE, SUBSCRIPTION_PLAN_V2_SIZE, SUBSCRIPTION_SIZE, SUBSCRIPTION_V1_SIZE,
+    },
+    error::RecurringPaymentsError,
+    instruction,
+    state::{
+        AccountType, CycleInterval, LegacySubscription, LegacySubscriptionPlan, PaymentMode, PlanMetadata,
+        Subscription, SubscriptionPlan, SubscriptionPlanV1, SubscriptionPlanV2, SubscriptionV1,
+    },
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+fn subscription(test: &ProgramTest, subscriber: &Subscriber) -> Subscription {
+    Subscription::unpack(&test.account(&subscriber.subscription).unwrap().data).unwrap()
+}
+
+/// Writes a plan in the version 0 layout, billing `max_amount` every `days` days
+fn create_legacy_plan(test: &mut ProgramTest, days: u64, max_amount: u64) -> Plan {
+    let plan = test.create_program_account(LEGACY_SUBSCRIPTION_PLAN_SIZE);
+    let (authority, nonce) = authority_id(&test.program_id, &plan);
+    let owner = test.create_wallet(LAMPORTS_PER_WALLET);
+    let mint = test.create_mint(6);
+    let payout = test.create_token_account(&mint, &owner, 0);
+    let legacy = LegacySubscriptionPlan {
+        is_initialized: true,
+        nonce,
+        owner,
+        authority,
+        token: payout,
+        subscription_timeframe: days,
+        max_amount,
+    };
+    let mut account = test.account(&plan).unwrap().clone();
+    LegacySubscriptionPlan::pack(legacy, &mut account.data).unwrap();
+    test.set_account(plan, account);
+    Plan {
+        plan,
+        owner,
+        authority,
+        mint,
+        mint_program: spl_token::id(),
+        payout,
+        timeframe: CycleInterval::Days(days),
+        max_amount,
+        price_feed: None,
+        setup_fee: 0,
+        terms: PlanTerms::default(),
+        escrow: None,
+        settlement_vault: None,
+    }
+}
+
+/// Writes a version 0 subscription to a legacy plan, whose cycle started at `cycle_start`. Like every legacy
+/// subscription it records the plan owner, the subscriber only owns the approved token account.
+fn create_legacy_subscription(
+    test: &mut ProgramTest,
+    plan: &Plan,
+    cycle_start: i64,
+    withdrawn_amount: u64,
+) -> Subscriber {
+    let subscription = test.create_program_account(LEGACY_SUBSCRIPTION_SIZE);
+    let owner = test.create_wallet(LAMPORTS_PER_WALLET);
+    let token_account = test.create_token_account(&plan.mint, &owner, 1_000);
+    test.approve(&token_account, &plan.authority, 1_000);
+    let legacy = LegacySubscription {
+        is_initialized: true,
+        is_approved: true,
+        subscription_plan_account: plan.plan,
+        token_account,
+        owner: plan.owner,
+        cycle_start,
+        subscription_timeframe: 30,
+        max_amount: plan.max_amount,
+        withdrawn_amount,
+    };
+    let mut account = test.account(&subscription).unwrap().clone();
+    LegacySubscription::pack(legacy, &mut account.data).unwrap();
+    test.set_account(subscription, account);
+    Subscriber {
+        subscription,
+        owner,
+        token_account,
+        vault: token_account,
+    }
+}
+
+#[test]
+fn migrated_accounts_keep_billing_where_the_legacy_ones_left_off() {
+    let mut test = ProgramTest::new();
+    let plan = create_legacy_plan(&mut test, 30, 100);
+    let subscriber = create_legacy_subscription(&mut test, &plan, NOW - 10 * SECONDS_PER_DAY, 100);
+    let payer = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(ProgramError::InvalidAccountData));
+
+    // Subscriptions take their terms from the plan, so it goes first
+    let result = test.process(migrate_subscription(&program_id, &payer, &plan, &subscriber));
+    assert_eq!(result, Err(ProgramError::InvalidAccountData));
+    test.process(migrate_subscription_plan(&program_id, &payer, &plan))
+        .unwrap();
+    test.process(migrate_subscription(&program_id, &payer, &plan, &subscriber))
+        .unwrap();
+
+    let account = test.account(&subscriber.subscription).unwrap();
+    assert_eq!(account.data.len(), SUBSCRIPTION_SIZE);
+    assert_eq!(account.lamports, test.rent.minimum_balance(SUBSCRIPTION_SIZE));
+    let migrated = subscription(&test, &subscriber);
+    assert_eq!(migrated.owner, subscriber.owner);
+    assert_eq!(migrated.mint, plan.mint);
+    assert_eq!(migrated.subscription_timeframe, CycleInterval::Days(30));
+    assert_eq!(migrated.max_amount, 100);
+    assert_eq!(migrated.withdrawn_amount, 100);
+    assert_eq!(migrated.payment_mode, PaymentMode::Delegate);
+
+    // This cycle was paid before the migration, the next one is claimed as usual
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+    test.warp_to(NOW + 20 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+
+    // The subscriber, not the merchant the legacy account recorded, controls the subscription
+    let result = test.process(close_subscription(
+        &program_id,
+        &Subscriber {
+            owner: plan.owner,
+            ..subscriber.clone()
+        },
+        None,
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriber)));
+}
+
+#[test]
+fn migration_checks_the_accounts_the_legacy_layout_records() {
+    let mut test = ProgramTest::new();
+    let plan = create_legacy_plan(&mut test, 30, 100);
+    let subscriber = create_legacy_subscription(&mut test, &plan, NOW, 0);
+    let payer = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+
+    let mut instruction = migrate_subscription_plan(&program_id, &payer, &plan);
+    instruction.accounts[0].is_signer = false;
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+
+    let other_payout = test.create_token_account(&plan.mint, &plan.owner, 0);
+    let result = test.process(migrate_subscription_plan(
+        &program_id,
+        &payer,
+        &Plan {
+            payout: other_payout,
+            ..plan.clone()
+        },
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPayoutAccount)));
+    test.process(migrate_subscription_plan(&program_id, &payer, &plan))
+        .unwrap();
+
+    let other_token_account = test.create_token_account(&plan.mint, &payer, 0);
+    let result = test.process(migrate_subscription(
+        &program_id,
+        &payer,
+        &plan,
+        &Subscriber {
+            token_account: other_token_account,
+            ..subscriber.clone()
+        },
+    ));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSourceAccount)));
+    test.process(migrate_subscription(&program_id, &payer, &plan, &subscriber))
+        .unwrap();
+
+    // Accounts already in the current layout are left alone
+    let result = test.process(migrate_subscription_plan(&program_id, &payer, &plan));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToMigrate)));
+    let result = test.process(migrate_subscription(&program_id, &payer, &plan, &subscriber));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToMigrate)));
+    assert_eq!(subscription(&test, &subscriber).owner, subscriber.owner);
+}
+
+/// Rewrites a plan created through the program in the version 1 layout, from before plans had reserved bytes
+fn downgrade_plan(test: &mut ProgramTest, plan: &Plan) {
+    let mut account = test.account(&plan.plan).unwrap().clone();
+    let subscription_plan = SubscriptionPlan::unpack(&account.data).unwrap();
+    let v1 = SubscriptionPlanV1 {
+        account_type: AccountType::SubscriptionPlan,
+        version: 1,
+        is_initialized: true,
+        nonce: subscription_plan.nonce,
+        owner: subscription_plan.owner,
+        authority: subscription_plan.authority,
+        subscription_timeframe: subscription_plan.subscription_timeframe,
+        max_cycles: subscription_plan.max_cycles,
+        end_timestamp: subscription_plan.end_timestamp,
+        charge_upfront: subscription_plan.charge_upfront,
+        billing_anchor_day: subscription_plan.billing_anchor_day,
+        dispute_window: subscription_plan.dispute_window,
+        arbiter: subscription_plan.arbiter,
+        settlement_interval: subscription_plan.settlement_interval,
+        keeper_tip: subscription_plan.keeper_tip,
+        price_feed_owner: subscription_plan.price_feed_owner,
+        accepted_mints: subscription_plan.accepted_mints,
+    };
+    account.data = vec![0; SUBSCRIPTION_PLAN_V1_SIZE];
+    account.lamports = test.rent.minimum_balance(SUBSCRIPTION_PLAN_V1_SIZE);
+    SubscriptionPlanV1::pack(v1, &mut account.data).unwrap();
+    test.set_account(plan.plan, account);
+}
+
+/// Rewrites a plan created through the program in the version 2 layout, which has no metadata
+fn downgrade_plan_to_v2(test: &mut ProgramTest, plan: &Plan) {
+    let mut account = test.account(&plan.plan).unwrap().clone();
+    let subscription_plan = SubscriptionPlan::unpack(&account.data).unwrap();
+    let v2 = SubscriptionPlanV2 {
+        account_type: AccountType::SubscriptionPlan,
+        version: 2,
+        is_initialized: true,
+        nonce: subscription_plan.nonce,
+        owner: subscription_plan.owner,
+        authority: subscription_plan.authority,
+        subscription_timeframe: subscription_plan.subscription_timeframe,
+        max_cycles: subscription_plan.max_cycles,
+        end_timestamp: subscription_plan.end_timestamp,
+        charge_upfront: subscription_plan.charge_upfront,
+        billing_anchor_day: subscription_plan.billing_anchor_day,
+        dispute_window: subscription_plan.dispute_window,
+        arbiter: subscription_plan.arbiter,
+        settlement_interval: subscription_plan.settlement_interval,
+        keeper_tip: subscription_plan.keeper_tip,
+        price_feed_owner: subscription_plan.price_feed_owner,
+        reserved: subscription_plan.reserved,
+        accepted_mints: subscription_plan.accepted_mints,
+    };
+    account.data = vec![0; SUBSCRIPTION_PLAN_V2_SIZE];
+    account.lamports = test.rent.minimum_balance(SUBSCRIPTION_PLAN_V2_SIZE);
+    SubscriptionPlanV2::pack(v2, &mut account.data).unwrap();
+    test.set_account(plan.plan, account);
+}
+
+/// Rewrites a subscription created through the program in the version 1 layout
+fn downgrade_subscription(test: &mut ProgramTest, subscriber: &Subscriber) {
+    let mut account = test.account(&subscriber.subscription).unwrap().clone();
+    let subscription = Subscription::unpack(&account.data).unwrap();
+    let v1 = SubscriptionV1 {
+        account_type: AccountType::Subscription,
+        version: 1,
+        is_initialized: true,
+        is_approved: subscription.is_approved,
+        subscription_plan_account: subscription.subscription_plan_account,
+        token_account: subscription.token_account,
+        mint: subscription.mint,
+        owner: subscription.owner,
+        cycle_anchor: subscription.cycle_anchor,
+        cycle_start: subscription.cycle_start,
+        subscription_timeframe: subscription.subscription_timeframe,
+        max_cycles: subscription.max_cycles,
+        end_timestamp: subscription.end_timestamp,
+        max_amount: subscription.max_amount,
+        withdrawn_amount: subscription.withdrawn_amount,
+        refunded_amount: subscription.refunded_amount,
+        payment_mode: subscription.payment_mode,
+        vault_account: subscription.vault_account,
+        escrowed_amount: subscription.escrowed_amount,
+        escrow_release: subscription.escrow_release,
+        is_disputed: subscription.is_disputed,
+        recent_escrowed_amount: subscription.recent_escrowed_amount,
+        recent_escrow_release: subscription.recent_escrow_release,
+    };
+    account.data = vec![0; SUBSCRIPTION_V1_SIZE];
+    account.lamports = test.rent.minimum_balance(SUBSCRIPTION_V1_SIZE);
+    SubscriptionV1::pack(v1, &mut account.data).unwrap();
+    test.set_account(subscriber.subscription, account);
+}
+
+#[test]
+fn version_1_accounts_migrate_without_extra_accounts() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let payer = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    downgrade_plan(&mut test, &plan);
+    downgrade_subscription(&mut test, &subscriber);
+
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(ProgramError::InvalidAccountData));
+
+    test.process(instruction::migrate_account(&program_id, &payer, &plan.plan))
+        .unwrap();
+    test.process(instruction::migrate_account(
+        &program_id,
+        &payer,
+        &subscriber.subscription,
+    ))
+    .unwrap();
+    for (key, len) in [
+        (plan.plan, SUBSCRIPTION_PLAN_SIZE),
+        (subscriber.subscription, SUBSCRIPTION_SIZE),
+    ] {
+        let account = test.account(&key).unwrap();
+        assert_eq!(account.data.len(), len);
+        assert_eq!(account.lamports, test.rent.minimum_balance(len));
+    }
+    assert_eq!(subscription(&test, &subscriber).withdrawn_amount, 100);
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    assert_eq!(test.token_balance(&subscriber.vault), 100);
+}
+
+#[test]
+fn version_2_plans_migrate_with_empty_metadata() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    let payer = test.create_wallet(LAMPORTS_PER_WALLET);
+    let program_id = test.program_id;
+    test.process(set_keeper_tip(&program_id, &plan, 5_000)).unwrap();
+    downgrade_plan_to_v2(&mut test, &plan);
+
+    let result = test.process(update_plan_metadata(&program_id, &plan, "Pro", "", ""));
+    assert_eq!(result, Err(ProgramError::InvalidAccountData));
+
+    test.process(instruction::migrate_account(&program_id, &payer, &plan.plan))
+        .unwrap();
+    let account = test.account(&plan.plan).unwrap();
+    assert_eq!(account.data.len(), SUBSCRIPTION_PLAN_SIZE);
+    assert_eq!(account.lamports, test.rent.minimum_balance(SUBSCRIPTION_PLAN_SIZE));
+    let subscription_plan = SubscriptionPlan::unpack(&account.data).unwrap();
+    assert_eq!(subscription_plan.metadata, PlanMetadata::default());
+    assert_eq!(subscription_plan.keeper_tip, 5_000);
+
+    test.process(update_plan_metadata(&program_id, &plan, "Pro", "", ""))
+        .unwrap();
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+}
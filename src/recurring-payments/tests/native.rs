@@ -0,0 +1,128 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{constants::SUBSCRIPTION_SIZE, error::RecurringPaymentsError, state::CycleInterval};
+use solana_program::{native_token::LAMPORTS_PER_SOL, program_error::ProgramError};
+
+const DAY: i64 = 86_400;
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+#[test]
+fn native_subscriptions_prepay_into_the_subscription_account() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+    let subscriber = test.subscribe(&plan, 5 * LAMPORTS_PER_SOL, 2).unwrap();
+
+    let rent = test.rent.minimum_balance(SUBSCRIPTION_SIZE);
+    assert_eq!(test.lamports(&subscriber.subscription), rent + 2 * LAMPORTS_PER_SOL);
+    assert_eq!(test.lamports(&subscriber.owner), 3 * LAMPORTS_PER_SOL);
+}
+
+#[test]
+fn native_claims_pay_the_payout_wallet_and_keep_rent() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+    let subscriber = test.subscribe(&plan, 5 * LAMPORTS_PER_SOL, 2).unwrap();
+    let program_id = test.program_id;
+    let payout = test.lamports(&plan.payout);
+
+    for cycle in 0..2 {
+        test.warp_to(NOW + cycle * 30 * DAY);
+        test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+            .unwrap();
+    }
+    assert_eq!(test.lamports(&plan.payout), payout + 2 * LAMPORTS_PER_SOL);
+    assert_eq!(
+        test.lamports(&subscriber.subscription),
+        test.rent.minimum_balance(SUBSCRIPTION_SIZE)
+    );
+
+    test.warp_to(NOW + 60 * DAY);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InsufficientVaultFunds)));
+}
+
+#[test]
+fn native_top_ups_transfer_lamports_into_the_subscription() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+    let subscriber = test.subscribe(&plan, 5 * LAMPORTS_PER_SOL, 1).unwrap();
+    let program_id = test.program_id;
+    let before = test.lamports(&subscriber.subscription);
+
+    test.process(top_up(
+        &program_id,
+        &plan,
+        &subscriber.owner,
+        &subscriber.subscription,
+        &subscriber.owner,
+        &subscriber.subscription,
+        LAMPORTS_PER_SOL,
+    ))
+    .unwrap();
+    assert_eq!(test.lamports(&subscriber.subscription), before + LAMPORTS_PER_SOL);
+    assert_eq!(test.lamports(&subscriber.owner), 3 * LAMPORTS_PER_SOL);
+}
+
+#[test]
+fn native_withdraw_unused_keeps_the_current_cycle() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+    let subscriber = test.subscribe(&plan, 5 * LAMPORTS_PER_SOL, 3).unwrap();
+    let program_id = test.program_id;
+
+    let withdraw = |amount| {
+        withdraw_unused(
+            &program_id,
+            &plan,
+            &subscriber,
+            &subscriber.subscription,
+            &subscriber.owner,
+            amount,
+        )
+    };
+    let result = test.process(withdraw(2 * LAMPORTS_PER_SOL + 1));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InsufficientVaultFunds)));
+
+    test.process(withdraw(2 * LAMPORTS_PER_SOL)).unwrap();
+    assert_eq!(test.lamports(&subscriber.owner), 4 * LAMPORTS_PER_SOL);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+        .unwrap();
+    assert_eq!(
+        test.lamports(&subscriber.subscription),
+        test.rent.minimum_balance(SUBSCRIPTION_SIZE)
+    );
+}
+
+#[test]
+fn native_plans_only_take_escrow_subscriptions() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+
+    let result = test.subscribe(&plan, 5 * LAMPORTS_PER_SOL, 0);
+    assert_eq!(
+        result.err(),
+        Some(error(RecurringPaymentsError::NativePlanRequiresEscrow))
+    );
+}
+
+#[test]
+fn native_subscriptions_record_the_subscribers_own_wallet() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), LAMPORTS_PER_SOL);
+    let owner = test.create_wallet(5 * LAMPORTS_PER_SOL);
+    let subscription = test.create_program_account(SUBSCRIPTION_SIZE);
+    let subscriber = Subscriber {
+        subscription,
+        owner,
+        token_account: test.create_wallet(LAMPORTS_PER_WALLET),
+        vault: subscription,
+    };
+    let program_id = test.program_id;
+
+    let result = test.process(create_subscription(&program_id, &plan, &subscriber, 1));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriber)));
+}
@@ -0,0 +1,92 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::MAX_PLAN_URI_LEN,
+    error::RecurringPaymentsError,
+    state::{CycleInterval, PlanMetadata, SubscriptionPlan},
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+fn metadata(test: &ProgramTest, plan: &Plan) -> PlanMetadata {
+    SubscriptionPlan::unpack(&test.account(&plan.plan).unwrap().data)
+        .unwrap()
+        .metadata
+}
+
+#[test]
+fn the_owner_sets_and_clears_the_metadata() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+    assert_eq!(metadata(&test, &plan), PlanMetadata::default());
+
+    test.process(update_plan_metadata(
+        &program_id,
+        &plan,
+        "Pro",
+        "https://example.com/pro.json",
+        "price_123",
+    ))
+    .unwrap();
+    let set = metadata(&test, &plan);
+    assert_eq!(set.name(), "Pro");
+    assert_eq!(set.uri(), "https://example.com/pro.json");
+    assert_eq!(set.external_id(), "price_123");
+
+    // A shorter value leaves nothing of the longer one behind
+    test.process(update_plan_metadata(&program_id, &plan, "Pr", "", "price_123"))
+        .unwrap();
+    assert_eq!(metadata(&test, &plan).name(), "Pr");
+    assert_eq!(metadata(&test, &plan).uri(), "");
+
+    test.process(update_plan_metadata(&program_id, &plan, "", "", ""))
+        .unwrap();
+    assert_eq!(metadata(&test, &plan), PlanMetadata::default());
+
+    // Metadata is only shown to subscribers, billing carries on as before
+    let subscriber = test.subscribe(&plan, 1_000, 3).unwrap();
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+}
+
+#[test]
+fn only_the_plan_owner_updates_the_metadata() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+
+    let mut instruction = update_plan_metadata(&program_id, &plan, "Pro", "", "");
+    instruction.accounts[1].pubkey = Pubkey::new_unique();
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+
+    let mut instruction = update_plan_metadata(&program_id, &plan, "Pro", "", "");
+    instruction.accounts[1].is_signer = false;
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+
+    assert_eq!(metadata(&test, &plan), PlanMetadata::default());
+}
+
+#[test]
+fn metadata_that_does_not_fit_is_rejected() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+    test.process(update_plan_metadata(&program_id, &plan, "Pro", "", ""))
+        .unwrap();
+
+    let uri = "u".repeat(MAX_PLAN_URI_LEN + 1);
+    let result = test.process(update_plan_metadata(&program_id, &plan, "Pro", &uri, ""));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPlanMetadata)));
+
+    let result = test.process(update_plan_metadata(&program_id, &plan, "Pro\0", "", ""));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPlanMetadata)));
+
+    // A rejected update leaves the metadata as it was
+    assert_eq!(metadata(&test, &plan).name(), "Pro");
+}
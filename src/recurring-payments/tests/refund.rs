@@ -0,0 +1,118 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    error::RecurringPaymentsError,
+    state::{CycleInterval, Subscription},
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+fn subscription(test: &ProgramTest, subscriber: &Subscriber) -> Subscription {
+    Subscription::unpack(&test.account(&subscriber.subscription).unwrap().data).unwrap()
+}
+
+#[test]
+fn refunds_restoring_the_allowance_can_be_claimed_again() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+
+    test.process(refund(&program_id, &plan, &subscriber, &plan.owner, 40, true))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 60);
+    assert_eq!(test.token_balance(&subscriber.token_account), 840);
+    let state = subscription(&test, &subscriber);
+    assert_eq!(state.refunded_amount, 40);
+    assert_eq!(state.withdrawn_amount, 60);
+
+    // Only the refunded part of this cycle can be claimed again
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+
+    // A plain refund leaves the allowance alone and adds up
+    test.process(refund(&program_id, &plan, &subscriber, &plan.owner, 25, false))
+        .unwrap();
+    let state = subscription(&test, &subscriber);
+    assert_eq!(state.refunded_amount, 65);
+    assert_eq!(state.withdrawn_amount, 100);
+}
+
+#[test]
+fn refunds_are_signed_by_the_payout_owner_or_its_delegate() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+
+    let stranger = test.create_wallet(LAMPORTS_PER_WALLET);
+    let result = test.process(refund(&program_id, &plan, &subscriber, &stranger, 10, false));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let delegate = test.create_wallet(LAMPORTS_PER_WALLET);
+    test.approve(&plan.payout, &delegate, 10);
+    test.process(refund(&program_id, &plan, &subscriber, &delegate, 10, false))
+        .unwrap();
+    assert_eq!(test.token_balance(&subscriber.token_account), 810);
+
+    // The refund always goes to the subscription's own token account
+    let other_account = test.create_token_account(&plan.mint, &stranger, 0);
+    let elsewhere = Subscriber {
+        token_account: other_account,
+        ..subscriber.clone()
+    };
+    let result = test.process(refund(&program_id, &plan, &elsewhere, &plan.owner, 10, false));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidDestinationAccount)));
+    assert_eq!(test.token_balance(&plan.payout), 90);
+}
+
+#[test]
+fn native_refunds_are_paid_by_the_payout_wallet() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_native_plan(CycleInterval::Days(30), 1_000_000);
+    let subscriber = test.subscribe(&plan, LAMPORTS_PER_WALLET, 2).unwrap();
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.subscription))
+        .unwrap();
+
+    let result = test.process(refund(&program_id, &plan, &subscriber, &plan.owner, 400_000, false));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let payout = test.lamports(&plan.payout);
+    let wallet = test.lamports(&subscriber.owner);
+    test.process(refund(&program_id, &plan, &subscriber, &plan.payout, 400_000, false))
+        .unwrap();
+    assert_eq!(test.lamports(&plan.payout), payout - 400_000);
+    assert_eq!(test.lamports(&subscriber.owner), wallet + 400_000);
+    assert_eq!(subscription(&test, &subscriber).refunded_amount, 400_000);
+}
+
+#[test]
+fn usd_priced_refunds_cannot_restore_the_allowance() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let price_feed = test.create_price_feed(&oracle, 200, -2);
+    let plan = test.create_usd_plan(CycleInterval::Days(30), 500, &price_feed, PlanTerms::default());
+    let subscriber = test.subscribe(&plan, 10_000_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000_000);
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+
+    let result = test.process(refund(&program_id, &plan, &subscriber, &plan.owner, 1_000_000, true));
+    assert_eq!(result, Err(error(RecurringPaymentsError::CannotRestoreUsdAllowance)));
+    test.process(refund(&program_id, &plan, &subscriber, &plan.owner, 1_000_000, false))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 1_500_000);
+}
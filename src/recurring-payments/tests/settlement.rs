@@ -0,0 +1,115 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{PLAN_ESCROW_SEED, SECONDS_PER_DAY},
+    error::RecurringPaymentsError,
+    processor::Processor,
+    state::CycleInterval,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const DAY: i64 = SECONDS_PER_DAY;
+
+#[test]
+fn claims_collect_in_the_settlement_vault_until_settle() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let plan = test.set_settlement_interval(&plan, 7 * DAY).unwrap();
+    let settlement_vault = plan.settlement_vault.unwrap();
+    let first = test.subscribe(&plan, 1_000, 2).unwrap();
+    let second = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+    assert_eq!(test.token_account(&settlement_vault).owner, plan.authority);
+
+    test.process(claim(&program_id, &plan, &first, &first.vault)).unwrap();
+    test.process(claim(&program_id, &plan, &second, &second.vault)).unwrap();
+    assert_eq!(test.token_balance(&settlement_vault), 200);
+    assert_eq!(test.token_balance(&plan.payout), 0);
+
+    // The first settlement is due right away, the next one an interval later
+    test.process(settle(&program_id, &plan, &settlement_vault)).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    let result = test.process(settle(&program_id, &plan, &settlement_vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SettlementNotDue)));
+
+    test.warp_to(NOW + 30 * DAY);
+    test.process(claim(&program_id, &plan, &first, &first.vault)).unwrap();
+    test.process(settle(&program_id, &plan, &settlement_vault)).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 300);
+    test.warp_to(NOW + 37 * DAY);
+    let result = test.process(settle(&program_id, &plan, &settlement_vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+}
+
+#[test]
+fn settlement_vaults_are_distinct_pdas_of_the_plan() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let arbiter = test.create_wallet(LAMPORTS_PER_WALLET);
+    let plan = test.set_dispute_window(&plan, &arbiter, 7 * DAY).unwrap();
+    let program_id = test.program_id;
+
+    // The escrow account cannot double as the settlement vault
+    let mut instruction = set_settlement_schedule(&program_id, &plan, 7 * DAY);
+    instruction.accounts[3].pubkey = plan.escrow.unwrap();
+    let result = test.process(instruction);
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSettlementVault)));
+
+    let mut instruction = set_settlement_schedule(&program_id, &plan, 7 * DAY);
+    instruction.accounts[3].pubkey = Pubkey::new_unique();
+    let result = test.process(instruction);
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSettlementVault)));
+
+    let plan = test.set_settlement_interval(&plan, 7 * DAY).unwrap();
+    assert_ne!(plan.settlement_vault, plan.escrow);
+    assert_eq!(
+        plan.escrow,
+        Some(Processor::plan_vault_id(&program_id, PLAN_ESCROW_SEED, &plan.plan, &plan.mint).0)
+    );
+
+    // Running it again keeps the vault, and only the owner can
+    let owner = test.lamports(&plan.owner);
+    test.set_settlement_interval(&plan, DAY).unwrap();
+    assert_eq!(test.lamports(&plan.owner), owner);
+    let stranger = Plan {
+        owner: Pubkey::new_unique(),
+        ..plan.clone()
+    };
+    let result = test.process(set_settlement_schedule(&program_id, &stranger, DAY));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // Only the plan's own vault can be settled
+    let result = test.process(settle(&program_id, &plan, &plan.escrow.unwrap()));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSettlementVault)));
+}
+
+#[test]
+fn turning_settlement_off_pays_claims_out_directly_and_frees_the_vault() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let plan = test.set_settlement_interval(&plan, 7 * DAY).unwrap();
+    let settlement_vault = plan.settlement_vault.unwrap();
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.process(settle(&program_id, &plan, &settlement_vault)).unwrap();
+
+    test.warp_to(NOW + 30 * DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    let plan = test.set_settlement_interval(&plan, 0).unwrap();
+
+    // Whatever is left in the vault can be swept before the interval is up
+    test.process(settle(&program_id, &plan, &settlement_vault)).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    let subscriber = test.subscribe(&plan, 1_000, 1).unwrap();
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 300);
+}
@@ -0,0 +1,124 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{SECONDS_PER_DAY, SUBSCRIBER_PROFILE_SIZE},
+    error::RecurringPaymentsError,
+    processor::Processor,
+    state::{CycleInterval, SubscriberProfile},
+};
+use solana_program::{program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, system_program};
+use solana_sdk::account::Account;
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const WEEK: i64 = 7 * SECONDS_PER_DAY;
+
+fn subscriber_profile(test: &ProgramTest, subscriber: &Subscriber) -> SubscriberProfile {
+    let key = Processor::subscriber_profile_id(&test.program_id, &subscriber.owner).0;
+    SubscriberProfile::unpack(&test.account(&key).unwrap().data).unwrap()
+}
+
+#[test]
+fn claims_over_the_monthly_cap_fail_until_the_next_month() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(7), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 6).unwrap();
+    let program_id = test.program_id;
+    test.process(set_spending_cap(&program_id, &subscriber.owner, &plan.mint, 250))
+        .unwrap();
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + WEEK);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    test.warp_to(NOW + 2 * WEEK);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SpendingCapExceeded)));
+    assert_eq!(test.token_balance(&plan.payout), 200);
+    assert_eq!(subscriber_profile(&test, &subscriber).spent_this_period, 200);
+
+    // The cycle left unclaimed can be claimed once a new calendar month begins, Nov 14th to Dec 14th
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 300);
+    let profile = subscriber_profile(&test, &subscriber);
+    assert_eq!(profile.period_start, NOW + 30 * SECONDS_PER_DAY);
+    assert_eq!(profile.spent_this_period, 100);
+}
+
+#[test]
+fn claims_in_another_mint_than_the_cap_are_rejected() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let other_mint = test.create_mint(6);
+    let program_id = test.program_id;
+    test.process(set_spending_cap(&program_id, &subscriber.owner, &other_mint, 1_000))
+        .unwrap();
+
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::SpendingCapMintMismatch)));
+    assert_eq!(test.token_balance(&plan.payout), 0);
+
+    // Moving the cap to the plan's mint lets the claim through
+    test.process(set_spending_cap(&program_id, &subscriber.owner, &plan.mint, 1_000))
+        .unwrap();
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(subscriber_profile(&test, &subscriber).spent_this_period, 100);
+}
+
+#[test]
+fn profiles_are_created_even_if_someone_funded_the_address_first() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+    let (profile, bump) = Processor::subscriber_profile_id(&program_id, &subscriber.owner);
+    test.set_account(profile, Account::new(1, 0, &system_program::id()));
+
+    let wallet = test.lamports(&subscriber.owner);
+    test.process(set_spending_cap(&program_id, &subscriber.owner, &plan.mint, 500))
+        .unwrap();
+    let rent = test.rent.minimum_balance(SUBSCRIBER_PROFILE_SIZE);
+    assert_eq!(test.account(&profile).unwrap().owner, program_id);
+    assert_eq!(test.lamports(&profile), rent);
+    assert_eq!(test.lamports(&subscriber.owner), wallet - (rent - 1));
+
+    let subscriber_profile = subscriber_profile(&test, &subscriber);
+    assert_eq!(subscriber_profile.bump, bump);
+    assert_eq!(subscriber_profile.owner, subscriber.owner);
+    assert_eq!(subscriber_profile.mint, plan.mint);
+    assert_eq!(subscriber_profile.monthly_cap, 500);
+    assert_eq!(subscriber_profile.period_start, NOW);
+}
+
+#[test]
+fn claims_need_the_subscriber_profile_address() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    let program_id = test.program_id;
+
+    // Skipping the profile would skip the cap
+    let mut instruction = claim(&program_id, &plan, &subscriber, &subscriber.vault);
+    instruction.accounts[9].pubkey = Pubkey::new_unique();
+    let result = test.process(instruction);
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidSubscriberProfile)));
+
+    // Only the wallet itself can set its cap
+    let mut instruction = set_spending_cap(&program_id, &subscriber.owner, &plan.mint, 0);
+    instruction.accounts[1].is_signer = false;
+    assert_eq!(test.process(instruction), Err(ProgramError::MissingRequiredSignature));
+
+    // Without a profile claims are not capped
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+}
@@ -0,0 +1,69 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::state::CycleInterval;
+
+/// 1% capped at 1000 token units
+const TRANSFER_FEE: Option<(u16, u64)> = Some((100, 1_000));
+
+#[test]
+fn token_2022_plans_transfer_through_the_mints_program() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint_2022(6, None);
+    let plan = test.create_plan_in(&mint, CycleInterval::Days(30), 100);
+    let subscriber = test.subscribe(&plan, 1_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 1_000);
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.account(&plan.payout).unwrap().owner, spl_token_2022::id());
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&subscriber.token_account), 900);
+}
+
+#[test]
+fn deposits_are_grossed_up_by_the_transfer_fee() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint_2022(6, TRANSFER_FEE);
+    let plan = test.create_plan_in(&mint, CycleInterval::Days(30), 10_000);
+    let subscriber = test.subscribe(&plan, 1_000_000, 3).unwrap();
+    let program_id = test.program_id;
+
+    assert_eq!(test.token_balance(&subscriber.vault), 30_000);
+    assert_eq!(test.token_balance(&subscriber.token_account), 1_000_000 - 30_304);
+
+    test.process(top_up(
+        &program_id,
+        &plan,
+        &subscriber.owner,
+        &subscriber.subscription,
+        &subscriber.token_account,
+        &subscriber.vault,
+        10_000,
+    ))
+    .unwrap();
+    assert_eq!(test.token_balance(&subscriber.vault), 40_000);
+    assert_eq!(
+        test.token_balance(&subscriber.token_account),
+        1_000_000 - 30_304 - 10_102
+    );
+}
+
+#[test]
+fn every_prepaid_cycle_can_be_claimed_despite_the_transfer_fee() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint_2022(6, TRANSFER_FEE);
+    let plan = test.create_plan_in(&mint, CycleInterval::Days(30), 10_000);
+    let subscriber = test.subscribe(&plan, 1_000_000, 2).unwrap();
+    let program_id = test.program_id;
+
+    for cycle in 0..2 {
+        test.warp_to(NOW + cycle * 30 * 86_400);
+        test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+            .unwrap();
+    }
+    assert_eq!(test.token_balance(&subscriber.vault), 0);
+    // The merchant bears the fee on claims
+    assert_eq!(test.token_balance(&plan.payout), 2 * 9_900);
+}
@@ -0,0 +1,230 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{
+    constants::{SECONDS_PER_DAY, SUBSCRIPTION_PLAN_SIZE, SUBSCRIPTION_SIZE},
+    error::RecurringPaymentsError,
+    state::CycleInterval,
+};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+const UPFRONT: PlanTerms = PlanTerms {
+    setup_fee: 0,
+    max_cycles: 0,
+    end_timestamp: 0,
+    charge_upfront: true,
+    billing_anchor_day: 0,
+};
+
+/// 2023-12-01T00:00:00Z, the first anchor on the 1st after `NOW`
+const DECEMBER_1ST: i64 = 1_701_388_800;
+
+#[test]
+fn upfront_plans_charge_the_first_cycle_on_signup() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, UPFRONT);
+    let subscriber = test.subscribe(&plan, 1_000, 0).unwrap();
+    let program_id = test.program_id;
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&subscriber.token_account), 900);
+
+    // The first cycle is paid for already
+    test.approve(&subscriber.token_account, &plan.authority, 900);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 200);
+}
+
+#[test]
+fn upfront_charges_come_on_top_of_the_escrow_deposit() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, UPFRONT);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 100);
+    assert_eq!(test.token_balance(&subscriber.vault), 200);
+    assert_eq!(test.token_balance(&subscriber.token_account), 700);
+
+    // The payout account has to be the plan's
+    let wrong_payout = Plan {
+        payout: test.create_token_account(&mint, &plan.owner, 0),
+        ..plan.clone()
+    };
+    let result = test.subscribe(&wrong_payout, 1_000, 2);
+    assert_eq!(
+        result.map(|_| ()),
+        Err(error(RecurringPaymentsError::InvalidPayoutAccount))
+    );
+}
+
+#[test]
+fn native_upfront_charges_go_straight_to_the_payout_wallet() {
+    let mut test = ProgramTest::new();
+    let native_mint = spl_token::native_mint::id();
+    let plan = test.create_plan_with_terms(&native_mint, CycleInterval::Days(30), 1_000_000, UPFRONT);
+    let payout = test.lamports(&plan.payout);
+    let subscriber = test.subscribe(&plan, LAMPORTS_PER_WALLET, 1).unwrap();
+
+    assert_eq!(test.lamports(&plan.payout), payout + 1_000_000);
+    assert_eq!(test.lamports(&subscriber.owner), LAMPORTS_PER_WALLET - 2_000_000);
+    assert_eq!(
+        test.lamports(&subscriber.subscription),
+        test.rent.minimum_balance(SUBSCRIPTION_SIZE) + 1_000_000
+    );
+}
+
+#[test]
+fn usd_priced_upfront_charges_convert_at_signup() {
+    let mut test = ProgramTest::new();
+    let price_feed = test.create_price_feed(&Pubkey::new_unique(), 200, -2);
+    let plan = test.create_usd_plan(CycleInterval::Days(30), 500, &price_feed, UPFRONT);
+    test.subscribe(&plan, 10_000_000, 0).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 2_500_000);
+}
+
+#[test]
+fn anchored_plans_prorate_the_first_period() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        billing_anchor_day: 1,
+        ..PlanTerms::default()
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::CalendarMonths(1), 3_000, terms);
+    let subscriber = test.subscribe(&plan, 10_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000);
+    let program_id = test.program_id;
+
+    // Signing up on November 14th at 22:13:20 leaves 16 days and 6_400 seconds of November's 30 days
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 1_607);
+
+    test.warp_to(DECEMBER_1ST - 1);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+    test.warp_to(DECEMBER_1ST);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 4_607);
+}
+
+#[test]
+fn anchored_upfront_plans_charge_the_prorated_period_on_signup() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        billing_anchor_day: 1,
+        ..UPFRONT
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::CalendarMonths(1), 3_000, terms);
+    let subscriber = test.subscribe(&plan, 10_000, 2).unwrap();
+    let program_id = test.program_id;
+    assert_eq!(test.token_balance(&plan.payout), 1_607);
+
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault));
+    assert_eq!(result, Err(error(RecurringPaymentsError::NothingToClaim)));
+    test.warp_to(DECEMBER_1ST);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.vault))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 4_607);
+}
+
+#[test]
+fn billing_anchors_need_a_calendar_month_plan_and_a_day_every_month_has() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let program_id = test.program_id;
+
+    for (timeframe, billing_anchor_day) in [(CycleInterval::Days(30), 1), (CycleInterval::CalendarMonths(1), 29)] {
+        let account = test.create_program_account(SUBSCRIPTION_PLAN_SIZE);
+        let (authority, nonce) = authority_id(&program_id, &account);
+        let terms = PlanTerms {
+            billing_anchor_day,
+            ..PlanTerms::default()
+        };
+        let result = test.process(create_subscription_plan(
+            &program_id,
+            &account,
+            &plan.owner,
+            &authority,
+            &plan.mint,
+            &plan.payout,
+            None,
+            nonce,
+            timeframe,
+            100,
+            terms,
+        ));
+        assert_eq!(result, Err(error(RecurringPaymentsError::InvalidBillingAnchor)));
+    }
+}
+
+#[test]
+fn setup_fees_are_charged_once_on_signup() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        setup_fee: 50,
+        ..PlanTerms::default()
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, terms);
+    let subscriber = test.subscribe(&plan, 1_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 950);
+    let program_id = test.program_id;
+    assert_eq!(test.token_balance(&plan.payout), 50);
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    test.warp_to(NOW + 30 * SECONDS_PER_DAY);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 250);
+    assert_eq!(test.token_balance(&subscriber.token_account), 750);
+}
+
+#[test]
+fn setup_fees_and_upfront_periods_go_out_together() {
+    let mut test = ProgramTest::new();
+    let mint = test.create_mint(6);
+    let terms = PlanTerms {
+        setup_fee: 50,
+        ..UPFRONT
+    };
+    let plan = test.create_plan_with_terms(&mint, CycleInterval::Days(30), 100, terms);
+    let subscriber = test.subscribe(&plan, 1_000, 2).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 150);
+    assert_eq!(test.token_balance(&subscriber.vault), 200);
+    assert_eq!(test.token_balance(&subscriber.token_account), 650);
+}
+
+#[test]
+fn each_accepted_mint_has_its_own_setup_fee() {
+    let mut test = ProgramTest::new();
+    let plan = test.create_plan(CycleInterval::Days(30), 100);
+    let mint = test.create_mint(6);
+    let usdt = Plan {
+        mint,
+        payout: test.create_token_account(&mint, &plan.owner, 0),
+        max_amount: 250,
+        setup_fee: 25,
+        ..plan.clone()
+    };
+    let program_id = test.program_id;
+    test.process(add_accepted_mint(&program_id, &usdt, &plan.owner))
+        .unwrap();
+
+    test.subscribe(&usdt, 1_000, 0).unwrap();
+    assert_eq!(test.token_balance(&usdt.payout), 25);
+    test.subscribe(&plan, 1_000, 0).unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 0);
+}
@@ -0,0 +1,117 @@
+mod harness;
+
+use harness::*;
+use recurring_payments_service::{constants::MAX_PRICE_FEED_AGE, error::RecurringPaymentsError, state::CycleInterval};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+fn error(error: RecurringPaymentsError) -> ProgramError {
+    error.into()
+}
+
+/// $5.00 a cycle
+const PRICE_CENTS: u64 = 500;
+
+/// Creates a plan charging `PRICE_CENTS` through a feed pricing its token at $2, and a delegate subscriber
+fn usd_plan(test: &mut ProgramTest, oracle: &Pubkey) -> (Plan, Subscriber) {
+    let price_feed = test.create_price_feed(oracle, 200, -2);
+    let plan = test.create_usd_plan(CycleInterval::Days(30), PRICE_CENTS, &price_feed, PlanTerms::default());
+    let subscriber = test.subscribe(&plan, 10_000_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000_000);
+    (plan, subscriber)
+}
+
+#[test]
+fn claims_convert_usd_cents_at_the_current_price() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let (plan, subscriber) = usd_plan(&mut test, &oracle);
+    let program_id = test.program_id;
+
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 2_500_000);
+
+    // The token doubles in price, the next cycle costs half as many tokens
+    test.warp_to(NOW + 30 * 86_400);
+    test.set_price_feed(plan.price_feed.unwrap(), &oracle, 400, -2, NOW + 30 * 86_400);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 3_750_000);
+}
+
+#[test]
+fn stale_and_future_feeds_are_rejected() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let (plan, subscriber) = usd_plan(&mut test, &oracle);
+    let price_feed = plan.price_feed.unwrap();
+    let program_id = test.program_id;
+
+    test.warp_to(NOW + MAX_PRICE_FEED_AGE + 1);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::StalePriceFeed)));
+
+    test.set_price_feed(price_feed, &oracle, 200, -2, NOW + MAX_PRICE_FEED_AGE + 2);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::PriceFeedFromFuture)));
+
+    test.set_price_feed(price_feed, &oracle, 200, -2, NOW + MAX_PRICE_FEED_AGE + 1);
+    test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&plan.payout), 2_500_000);
+}
+
+#[test]
+fn claims_only_read_the_plan_feed_from_its_oracle() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let (plan, subscriber) = usd_plan(&mut test, &oracle);
+    let program_id = test.program_id;
+
+    // Another feed, even one written by the same oracle
+    let other_feed = Plan {
+        price_feed: Some(test.create_price_feed(&oracle, 1, -2)),
+        ..plan.clone()
+    };
+    let result = test.process(claim(&program_id, &other_feed, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPriceFeed)));
+
+    // The plan's feed closed and recreated by some other program
+    test.set_price_feed(plan.price_feed.unwrap(), &Pubkey::new_unique(), 1, -2, NOW);
+    let result = test.process(claim(&program_id, &plan, &subscriber, &subscriber.token_account));
+    assert_eq!(result, Err(error(RecurringPaymentsError::InvalidPriceFeed)));
+    assert_eq!(test.token_balance(&plan.payout), 0);
+}
+
+#[test]
+fn usd_priced_mints_share_the_first_feed_oracle() {
+    let mut test = ProgramTest::new();
+    let oracle = Pubkey::new_unique();
+    let (plan, _) = usd_plan(&mut test, &oracle);
+
+    let foreign_feed = test.create_price_feed(&Pubkey::new_unique(), 100, -2);
+    let result = test.accept_mint_priced(&plan, PRICE_CENTS, Some(&foreign_feed));
+    assert_eq!(result.map(|_| ()), Err(error(RecurringPaymentsError::InvalidPriceFeed)));
+
+    let price_feed = test.create_price_feed(&oracle, 100, -2);
+    let usdt = test.accept_mint_priced(&plan, PRICE_CENTS, Some(&price_feed)).unwrap();
+    let subscriber = test.subscribe(&usdt, 10_000_000, 0).unwrap();
+    test.approve(&subscriber.token_account, &plan.authority, 10_000_000);
+    let program_id = test.program_id;
+    test.process(claim(&program_id, &usdt, &subscriber, &subscriber.token_account))
+        .unwrap();
+    assert_eq!(test.token_balance(&usdt.payout), 5_000_000);
+}
+
+#[test]
+fn usd_priced_mints_only_take_delegate_subscriptions() {
+    let mut test = ProgramTest::new();
+    let price_feed = test.create_price_feed(&Pubkey::new_unique(), 200, -2);
+    let plan = test.create_usd_plan(CycleInterval::Days(30), PRICE_CENTS, &price_feed, PlanTerms::default());
+
+    let result = test.subscribe(&plan, 10_000_000, 1);
+    assert_eq!(
+        result.map(|_| ()),
+        Err(error(RecurringPaymentsError::OraclePricingRequiresDelegate))
+    );
+}